@@ -0,0 +1,99 @@
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use telegram_bot::types::{chat::User, UserId};
+
+use crate::{money::Money, order::Order};
+
+/// An order which has ended, kept so that it can be looked up later
+/// Orders are kept separately until everyone who owes money for them has paid
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArchivedOrder {
+    /// Identifies this order among the conversation's archived orders, increasing with each order archived
+    pub id: u64,
+    pub order: Order,
+    /// when the order was ended
+    pub ended_at: DateTime<Utc>,
+    /// users who have paid the creater of the order what they owe
+    #[serde(default)]
+    pub paid: Vec<UserId>,
+    /// who ended the order, who may reopen it shortly afterwards
+    #[serde(default)]
+    pub ended_by: Option<UserId>,
+}
+
+impl ArchivedOrder {
+    /// Returns what each participant owed the creater of the order when it ended, whether or not they have paid, sorted by name
+    pub fn debts(&self) -> Vec<(&User, Money)> {
+        if !self.order.has_amounts() {
+            return vec![];
+        }
+        self.order
+            .amounts_owed()
+            .into_iter()
+            .filter(|(user, amount)| user.id != self.order.owner.id && !amount.is_zero())
+            .collect()
+    }
+
+    /// Returns the participants who still owe the creater of the order money, and how much, sorted by name
+    pub fn unpaid(&self) -> Vec<(&User, Money)> {
+        self.debts()
+            .into_iter()
+            .filter(|(user, _)| !self.paid.contains(&user.id))
+            .collect()
+    }
+
+    /// Describes who has yet to pay for this order
+    pub fn payment_status(&self) -> String {
+        let unpaid = self.unpaid();
+        if unpaid.is_empty() {
+            return format!("Everyone has paid {}.", self.order.owner.first_name);
+        }
+        let lines: Vec<String> = unpaid
+            .iter()
+            .map(|(user, amount)| format!("{}: {}", user.first_name, amount))
+            .collect();
+        format!(
+            "Still to pay {}:\n{}",
+            self.order.owner.first_name,
+            lines.join("\n")
+        )
+    }
+
+    /// Returns a one line description of the order, for listing several archived orders
+    pub fn summary_line(&self) -> String {
+        let participants = self.order.participants().len();
+        format!(
+            "{} by {}, ended {} ({} {})",
+            self.order.name,
+            self.order.owner.first_name,
+            format_time(&self.ended_at),
+            participants,
+            if participants == 1 {
+                "person"
+            } else {
+                "people"
+            }
+        )
+    }
+}
+
+impl fmt::Display for ArchivedOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Started by {} on {}, ended {}.\n{}",
+            self.order.owner.first_name,
+            format_time(&self.order.started_at),
+            format_time(&self.ended_at),
+            self.order
+        )
+    }
+}
+
+/// Formats a time in the bot's local timezone, e.g "Fri 18 Oct 12:30"
+pub fn format_time(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .format("%a %d %b %H:%M")
+        .to_string()
+}
@@ -1,10 +1,24 @@
-use std::{collections::HashMap, default::Default, string::String};
-use telegram_bot::types::{
-    chat::{MessageChat, User},
-    InlineKeyboardMarkup,
+use chrono::{DateTime, NaiveTime, Utc};
+use std::string::String;
+use telegram_bot::{
+    types::{chat::User, ChatId, InlineKeyboardMarkup, UserId},
+    InlineKeyboardButton,
 };
 
-use crate::conversation_orders::ConversationOrders;
+use crate::{
+    archive::{format_time, ArchivedOrder},
+    callback::{self, CallbackAction},
+    charges::Charge,
+    command::{ItemRequest, Recipient, StartOptions},
+    conversation_orders::ConversationOrders,
+    deadline,
+    menu::{self, MenuItem, OptionGroup},
+    money::Money,
+    order::{self, Order, OrderState},
+    schedule::Recurrence,
+    storage::{MemoryStorage, Storage},
+    undo::{Inverse, UndoEntry},
+};
 
 /// The result of executing a bot command
 pub struct CommandResult {
@@ -35,71 +49,281 @@ impl CommandResult {
     }
 }
 
-#[derive(Default)]
 /// Food Ordering Bot implementation logic
-pub struct Bot {
-    active_orders: HashMap<MessageChat, ConversationOrders>,
+/// Active orders are kept in `S`, which decides whether they outlive the bot
+pub struct Bot<S: Storage = MemoryStorage> {
+    storage: S,
 }
 
-impl Bot {
-    pub fn new() -> Self {
-        Self {
-            ..Default::default()
+impl<S: Storage> Bot<S> {
+    /// Creates a bot, resuming any orders already in the given storage
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Persists a conversation's orders after they have changed
+    /// Failures are logged rather than returned, since the in-memory orders remain usable
+    fn save(&mut self, chat: ChatId) {
+        if let Err(e) = self.storage.save(chat) {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Records how to revert a change the user just made, so that they can undo it with /undo
+    fn record_undo(&mut self, chat: ChatId, user: &User, description: String, inverse: Inverse) {
+        if let Some(conversation_orders) = self.storage.get_mut(chat) {
+            let order_started_at = inverse
+                .order_name()
+                .and_then(|order_name| conversation_orders.orders.get(order_name))
+                .map(|order| order.started_at);
+            conversation_orders.push_undo(UndoEntry {
+                user: user.id,
+                description,
+                inverse,
+                order_started_at,
+            });
+        }
+    }
+
+    /// Reverts the last change the user made, such as ordering, cancelling, or starting or ending an order
+    pub fn undo(&mut self, chat: ChatId, user: &User) -> CommandResult {
+        let undone = match self.storage.get_mut(chat) {
+            Some(conversation_orders) => conversation_orders.undo(user, Utc::now()),
+            None => return CommandResult::failure("You have nothing to undo.".into()),
+        };
+        // the change is forgotten even if it couldn't be undone, so that earlier changes can be undone next
+        self.save(chat);
+        match undone {
+            Ok((description, Some(order))) => CommandResult {
+                success: true,
+                response: format!("Undid {}.\n\n{}", description, order),
+                reply_markup: Some(order.generate_reply_markup()),
+            },
+            Ok((description, None)) => CommandResult::success(format!("Undid {}.", description)),
+            Err(msg) => CommandResult::failure(msg),
         }
     }
 
+    /// Returns the orders of a conversation, creating them if the conversation has none yet
+    fn conversation_mut(&mut self, chat: ChatId) -> &mut ConversationOrders {
+        if self.storage.get(chat).is_none() {
+            self.storage.insert(chat, ConversationOrders::default());
+        }
+        self.storage
+            .get_mut(chat)
+            .expect("conversation orders were just inserted")
+    }
+
     /// Starts an order
     pub fn start_order(
         &mut self,
-        chat: MessageChat,
+        chat: ChatId,
         creater: User,
         order_name: String,
+        options: StartOptions,
     ) -> CommandResult {
-        if order_name.len() > 30 {
-            return CommandResult::failure(
-                "Order names must not exceed 30 characters.".to_string(),
-            );
-        }
-        match self.active_orders.get_mut(&chat) {
-            // there are already orders for this conversation
-            Some(conversation_orders) => {
-                if conversation_orders.add_order(creater, order_name.clone()) {
-                    CommandResult::success(format!("Order started for {}.\nUse /order {} <item> to order, /view to show active orders and /end {} when done.", order_name, order_name, order_name))
-                } else {
-                    CommandResult::failure(format!(
-                        "There is already an order for {} in progress.",
-                        order_name
+        self.start_order_with_menu(
+            chat,
+            creater,
+            order_name,
+            options,
+            "The menu is below, tap on an item to order it.",
+        )
+    }
+
+    /// Starts an order offering the same items as the last ended order with this name
+    pub fn start_order_again(
+        &mut self,
+        chat: ChatId,
+        creater: User,
+        order_name: String,
+        options: StartOptions,
+    ) -> CommandResult {
+        let mut previous_menu: Vec<MenuItem> =
+            match self.storage.get(chat).and_then(|conversation_orders| {
+                conversation_orders.find_last_archived_order(&order_name)
+            }) {
+                Some(archived) => archived.order.menu(),
+                None => {
+                    return CommandResult::failure(format!(
+                        "No previous order for {} was found. Use /start {} to start a new order.",
+                        order_name, order_name
                     ))
                 }
+            };
+        // items given when starting the order again are offered too
+        previous_menu.extend(options.menu);
+        let options = StartOptions {
+            menu: previous_menu,
+            ..options
+        };
+        let menu_description = format!(
+            "Items from the last order for {} are below, tap on one to order it.",
+            order_name
+        );
+        self.start_order_with_menu(chat, creater, order_name, options, &menu_description)
+    }
+
+    /// Starts an order, describing its menu with `menu_description` if it has one
+    fn start_order_with_menu(
+        &mut self,
+        chat: ChatId,
+        creater: User,
+        order_name: String,
+        options: StartOptions,
+        menu_description: &str,
+    ) -> CommandResult {
+        if let Err(res) = check_order_name(&order_name) {
+            return res;
+        }
+        let mut order = Order::new(order_name.clone(), creater);
+        order.allow_multiple_items = options.allow_multiple_items;
+        let saved_menu = self.storage.get(chat).and_then(|conversation_orders| {
+            // a saved menu with the same name as the order is offered unless another menu is given
+            let menu_name = match &options.saved_menu {
+                Some(menu_name) => menu_name,
+                None if options.menu.is_empty() => &order_name,
+                None => return None,
+            };
+            conversation_orders.menus.get(menu_name)
+        });
+        match (saved_menu, &options.saved_menu) {
+            (Some(saved_menu), _) => order.offer_menu(saved_menu),
+            (None, Some(menu_name)) => {
+                return CommandResult::failure(format!(
+                    "There is no saved menu named {}. Use /menu list to see saved menus.",
+                    menu_name
+                ))
             }
-            None => {
-                let mut conversation_orders = ConversationOrders {
-                    orders: HashMap::new(),
-                };
-                conversation_orders.add_order(creater, order_name.clone());
-                self.active_orders.insert(chat, conversation_orders);
-                CommandResult::success(format!("Order started for {}.\nUse /order <item> to order, /view to show active orders, /end when done, or start another order.", order_name))
+            (None, None) => (),
+        }
+        order.offer_menu(&options.menu);
+        if options.menu_only && order.attached_menu.is_empty() {
+            return CommandResult::failure(
+                "Only orders offering a menu can be limited to it. List the menu's items on the following lines, or use --menu <menu name>.".into(),
+            );
+        }
+        order.menu_only = options.menu_only;
+        order.deadline = options
+            .deadline
+            .map(|time| deadline::next_occurrence(time, Utc::now()));
+        for item in order.items.keys() {
+            if let Err(msg) = check_item_name_length(&order_name, item) {
+                return CommandResult::failure(msg);
             }
         }
+        let reply_markup = if order.items.is_empty() {
+            None
+        } else {
+            Some(order.generate_reply_markup())
+        };
+        let order_deadline = order.deadline;
+        let creater = order.owner.clone();
+        let conversation_orders = self.conversation_mut(chat);
+        if !conversation_orders.insert_order(order) {
+            return CommandResult::failure(format!(
+                "There is already an order for {} in progress.",
+                order_name
+            ));
+        }
+        let is_only_order = conversation_orders.orders.len() == 1;
+        self.record_undo(
+            chat,
+            &creater,
+            format!("starting {}", order_name),
+            Inverse::RemoveOrder {
+                order_name: order_name.clone(),
+            },
+        );
+        self.save(chat);
+        let mut response = if is_only_order {
+            format!("Order started for {}.\nUse /order <item> to order, /view to show active orders, /end when done, or start another order.", order_name)
+        } else {
+            format!("Order started for {}.\nUse /order {} <item> to order, /view to show active orders and /end {} when done.", order_name, order_name, order_name)
+        };
+        if options.allow_multiple_items {
+            response.push_str("\nEveryone may order several different items.");
+        }
+        if options.menu_only {
+            response.push_str("\nOnly items on the menu may be ordered.");
+        }
+        if let Some(deadline) = order_deadline {
+            response.push_str(&format!(
+                "\nThe order ends automatically at {}.",
+                format_time(&deadline)
+            ));
+        }
+        if reply_markup.is_some() {
+            response.push('\n');
+            response.push_str(menu_description);
+        }
+        CommandResult {
+            success: true,
+            response,
+            reply_markup,
+        }
+    }
+
+    /// Terminates an order, if any, keeping it in the conversation's history
+    /// Only the creater of the order may end it, unless `user` is an administrator of the conversation
+    pub fn end_order(&mut self, chat: ChatId, user: &User, order_name: &str) -> CommandResult {
+        self.end_order_as(chat, user, false, order_name)
+    }
+
+    /// Terminates an order on behalf of an administrator of the conversation, who may end anyone's order
+    pub fn end_order_as_admin(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+    ) -> CommandResult {
+        self.end_order_as(chat, user, true, order_name)
     }
 
-    /// Terminates an order, if any
-    pub fn end_order(
+    /// Returns whether ending an order requires `user` to be an administrator, since they didn't start it
+    pub fn needs_admin_to_end(&self, chat: ChatId, user: &User, order_name: &str) -> bool {
+        self.storage
+            .get(chat)
+            .and_then(|conversation_orders| conversation_orders.orders.get(order_name))
+            .is_some_and(|order| !order.is_managed_by(user))
+    }
+
+    fn end_order_as(
         &mut self,
-        chat: &MessageChat,
+        chat: ChatId,
         user: &User,
+        is_admin: bool,
         order_name: &str,
     ) -> CommandResult {
-        match self.active_orders.get_mut(chat) {
-            Some(conversation_orders) => match conversation_orders.remove_order(user, order_name) {
-                Ok(completed_order) => {
-                    if self.active_orders[chat].orders.is_empty() {
-                        self.active_orders.remove(chat);
+        match self.storage.get_mut(chat) {
+            Some(conversation_orders) => {
+                match conversation_orders.remove_order(user, is_admin, order_name) {
+                    Ok(completed_order) => {
+                        let ended_order = conversation_orders.end_order(completed_order, user);
+                        self.record_undo(
+                            chat,
+                            user,
+                            format!("ending {}", order_name),
+                            Inverse::ReopenOrder { id: ended_order.id },
+                        );
+                        self.save(chat);
+                        let mut res = if ended_order.unpaid().is_empty() {
+                            CommandResult::success(format!("{}", ended_order.order))
+                        } else {
+                            payment_result(&ended_order)
+                        };
+                        // in case the wrong order was ended
+                        res.reply_markup
+                            .get_or_insert_with(InlineKeyboardMarkup::new)
+                            .add_row(vec![InlineKeyboardButton::callback(
+                                "Undo",
+                                CallbackAction::ReopenOrder(ended_order.id).to_data(),
+                            )]);
+                        res
                     }
-                    CommandResult::success(format!("{}", completed_order))
+                    Err(msg) => CommandResult::failure(msg),
                 }
-                Err(msg) => CommandResult::failure(msg),
-            },
+            }
             None => CommandResult::failure(
                 "There are no orders in progress. To start an order, use /start <order name>."
                     .into(),
@@ -107,142 +331,2926 @@ impl Bot {
         }
     }
 
+    /// Reopens the most recently ended order, or the most recently ended one with the given name
+    pub fn reopen_order(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: Option<&str>,
+    ) -> CommandResult {
+        let id = self
+            .storage
+            .get(chat)
+            .and_then(|conversation_orders| conversation_orders.last_ended_order_id(order_name));
+        match (id, order_name) {
+            (Some(id), _) => self.reopen_ended_order(chat, user, id),
+            (None, Some(order_name)) => {
+                CommandResult::failure(format!("No ended order for {} was found.", order_name))
+            }
+            (None, None) => CommandResult::failure("No orders have ended yet.".into()),
+        }
+    }
+
+    /// Reopens an ended order with all its items, if it ended recently
+    pub fn reopen_ended_order(&mut self, chat: ChatId, user: &User, id: u64) -> CommandResult {
+        let reopened = match self.storage.get_mut(chat) {
+            Some(conversation_orders) => conversation_orders.reopen_order(user, id, Utc::now()),
+            None => return CommandResult::failure("No orders have ended yet.".into()),
+        };
+        match reopened {
+            Ok(order) => {
+                self.save(chat);
+                CommandResult {
+                    success: true,
+                    response: format!(
+                        "Reopened {}.\n\n{}\nUse /order <item> to order and /end {} when done.",
+                        order.name, order, order.name
+                    ),
+                    reply_markup: Some(order.generate_reply_markup()),
+                }
+            }
+            Err(msg) => CommandResult::failure(msg),
+        }
+    }
+
+    /// Does whatever is due at `now`, such as posting reminders and ending orders whose deadline has passed
+    /// Returns the conversations to post the results to
+    pub fn run_timers(&mut self, now: DateTime<Utc>) -> Vec<(ChatId, CommandResult)> {
+        let mut results = self.start_scheduled_orders(now);
+        results.extend(self.post_reminders(now));
+        results.extend(self.end_overdue_orders(now));
+        results
+    }
+
+    /// Starts the scheduled orders which are due at `now`, returning the conversations to announce them in
+    pub fn start_scheduled_orders(&mut self, now: DateTime<Utc>) -> Vec<(ChatId, CommandResult)> {
+        let mut results = vec![];
+        for chat in self.storage.chats() {
+            let due_schedules = match self.storage.get_mut(chat) {
+                Some(conversation_orders) => conversation_orders.take_due_schedules(now),
+                None => continue,
+            };
+            if due_schedules.is_empty() {
+                continue;
+            }
+            self.save(chat);
+            for schedule in due_schedules {
+                let res =
+                    self.start_order(chat, schedule.owner, schedule.order_name, schedule.options);
+                results.push((chat, res));
+            }
+        }
+        results
+    }
+
+    /// Reminds conversations of orders whose deadline is approaching, inviting those who took part in recent orders but have yet to order
+    pub fn post_reminders(&mut self, now: DateTime<Utc>) -> Vec<(ChatId, CommandResult)> {
+        let mut results = vec![];
+        for chat in self.storage.chats() {
+            let conversation_orders = match self.storage.get_mut(chat) {
+                Some(conversation_orders) => conversation_orders,
+                None => continue,
+            };
+            let due_reminders = conversation_orders.take_due_reminders(now);
+            if due_reminders.is_empty() {
+                continue;
+            }
+            for (order, minutes_left) in due_reminders {
+                let ordered: Vec<UserId> =
+                    order.participants().iter().map(|user| user.id).collect();
+                let yet_to_order: Vec<&str> = conversation_orders
+                    .recent_participants()
+                    .into_iter()
+                    .filter(|user| !ordered.contains(&user.id))
+                    .map(|user| user.first_name.as_str())
+                    .collect();
+                let invitation = if yet_to_order.is_empty() {
+                    "Tap on an item or use /order <item> to order.".to_string()
+                } else {
+                    format!(
+                        "{}, you haven't ordered yet. Tap on an item or use /order <item> to order.",
+                        yet_to_order.join(", ")
+                    )
+                };
+                results.push((
+                    chat,
+                    CommandResult {
+                        success: true,
+                        response: format!(
+                            "{} ends in {} minutes, at {}.\n\n{}\n\n{}",
+                            order.name,
+                            minutes_left,
+                            format_time(
+                                &order
+                                    .deadline
+                                    .expect("reminders are only due for orders with a deadline")
+                            ),
+                            order,
+                            invitation
+                        ),
+                        reply_markup: Some(order.generate_reply_markup()),
+                    },
+                ));
+            }
+            self.save(chat);
+        }
+        results
+    }
+
+    /// Ends every order whose deadline is at or before `now`, returning the conversations to post their summaries to
+    pub fn end_overdue_orders(&mut self, now: DateTime<Utc>) -> Vec<(ChatId, CommandResult)> {
+        let mut results = vec![];
+        for chat in self.storage.chats() {
+            let overdue_orders = match self.storage.get(chat) {
+                Some(conversation_orders) => conversation_orders.overdue_orders(now),
+                None => continue,
+            };
+            for order_name in overdue_orders {
+                let owner = self.storage.get(chat).unwrap().orders[&order_name]
+                    .owner
+                    .clone();
+                let res = self.end_order(chat, &owner, &order_name);
+                results.push((
+                    chat,
+                    CommandResult {
+                        response: format!(
+                            "The deadline for {} has passed, so it has ended.\n\n{}",
+                            order_name, res.response
+                        ),
+                        ..res
+                    },
+                ));
+            }
+        }
+        results
+    }
+
     /// Adds an item to a running order
     pub fn add_item(
         &mut self,
-        chat: &MessageChat,
+        chat: ChatId,
         user: User,
         order_name: &str,
-        item: String,
+        request: ItemRequest,
     ) -> CommandResult {
-        // ensure that the item name isn't too long so that callback queries are <= 64 bytes, per Telegram limits
-        // callback queries are in the form "<order_name> <item>", so their lengths must not exceed 63
-        if order_name.len() + item.len() > 63 {
-            return CommandResult::failure("The sum of the lengths of order and item names must not exceed 63 characters, per Telegram limits.".to_string());
+        if let Err(msg) = check_item_name_length(order_name, &request.item) {
+            return CommandResult::failure(msg);
         }
-        match self.active_orders.get_mut(chat) {
-            Some(conversation_orders) => match conversation_orders.add_item(order_name, user, item)
-            {
-                Some(updated_order) => CommandResult {
-                    success: true,
-                    reply_markup: Some(updated_order.generate_reply_markup()),
-                    response: format!(
-                        "{}\nUse /order <item> to update your order and /end when done.\nYou can also tap on an existing item to update or cancel your order.",
-                        updated_order
+        match self.storage.get_mut(chat) {
+            Some(conversation_orders) => {
+                // items ordered for someone else belong to them, with the sender shown as having added them
+                let recipient = match &request.recipient {
+                    Some(Recipient::Mention(name)) => match conversation_orders.find_user(name) {
+                        Some(recipient) => Some(recipient.clone()),
+                        None => return CommandResult::failure(format!(
+                            "{} hasn't ordered anything in this chat yet, so they can't be found. Use their username or first name, or put a guest's name in quotes. For example, /order for \"Bob (guest)\" waffle",
+                            name
+                        )),
+                    },
+                    Some(Recipient::Guest(name)) => {
+                        // guests who have already ordered keep the name they were first given
+                        let guest = order::guest(name);
+                        Some(
+                            conversation_orders
+                                .find_user(name)
+                                .filter(|existing| existing.id == guest.id)
+                                .cloned()
+                                .unwrap_or(guest),
+                        )
+                    }
+                    None => None,
+                }
+                .filter(|recipient| recipient.id != user.id);
+                let orderer = recipient.as_ref().unwrap_or(&user);
+                let previous_items = match conversation_orders.orders.get(order_name) {
+                    Some(order) => {
+                        if let Err(msg) = order
+                            .check_open()
+                            .and_then(|_| order.check_on_menu(&request.item))
+                            .and_then(|_| {
+                                order.check_options(orderer, &request.item, &request.options)
+                            })
+                        {
+                            return CommandResult::failure(msg);
+                        }
+                        Some(Inverse::restore_items(order, orderer, recipient.clone()))
+                    }
+                    None => None,
+                };
+                let description = match &recipient {
+                    Some(recipient) => format!(
+                        "ordering {} for {} in {}",
+                        request.item, recipient.first_name, order_name
                     ),
-                },
-                None => CommandResult::failure(format!("Order {} not found.", order_name)),
-            },
+                    None => format!("ordering {} for {}", request.item, order_name),
+                };
+                let result = match &recipient {
+                    Some(recipient) => conversation_orders.add_item(
+                        order_name,
+                        recipient.clone(),
+                        request,
+                        Some(user.clone()),
+                    ),
+                    None => conversation_orders.add_item(order_name, user.clone(), request, None),
+                };
+                match result {
+                    Some(updated_order) => {
+                        if let Some(inverse) = previous_items {
+                            self.record_undo(chat, &user, description, inverse);
+                        }
+                        self.save(chat);
+                        CommandResult {
+                        success: true,
+                        reply_markup: Some(updated_order.generate_reply_markup()),
+                        response: format!(
+                            "{}\nUse /order <item> to update your order and /end when done.\nYou can also tap on an existing item to update or cancel your order.",
+                            updated_order
+                        ),
+                    }
+                    }
+                    None => CommandResult::failure(format!("Order {} not found.", order_name)),
+                }
+            }
             None => CommandResult::failure(
-                "There are no orders in progress. To start an order, use /start <order name>.".into(),
+                "There are no orders in progress. To start an order, use /start <order name>."
+                    .into(),
             ),
         }
     }
 
-    /// Cancels the currently selected item for an order
-    pub fn remove_item(
+    /// Sets the price of one of an item in a running order
+    pub fn set_price(
         &mut self,
-        chat: &MessageChat,
+        chat: ChatId,
         user: &User,
         order_name: &str,
+        item: &str,
+        price: Money,
     ) -> CommandResult {
-        match self.active_orders.get_mut(chat) {
-            Some(conversation_orders) => match conversation_orders.remove_item(order_name, user) {
-                Some(updated_order) => CommandResult {
-                    success: true,
-                    response: format!(
-                        "{}\nUse /order <item> to order, and /end when done.\nYou can also tap on an existing item to update or cancel your order.",
-                        updated_order
-                    ),
-                    reply_markup: Some(updated_order.generate_reply_markup()),
-                },
-                None => CommandResult::failure(format!(
-                    "You have either not placed any orders for {}, or order {} does not exist.",
-                    order_name, order_name
-                )),
-            },
+        if let Err(msg) = check_item_name_length(order_name, item) {
+            return CommandResult::failure(msg);
+        }
+        match self.storage.get_mut(chat) {
+            Some(conversation_orders) => {
+                let previous_price = match conversation_orders.orders.get(order_name) {
+                    Some(order) => {
+                        if let Err(msg) = order.check_on_menu(item) {
+                            return CommandResult::failure(msg);
+                        }
+                        order.prices.get(item).cloned()
+                    }
+                    None => None,
+                };
+                match conversation_orders.set_price(order_name, item, price) {
+                    Some(updated_order) => {
+                        self.record_undo(
+                            chat,
+                            user,
+                            format!("setting the price of {} for {}", item, order_name),
+                            Inverse::RestorePrice {
+                                order_name: order_name.to_string(),
+                                item: item.to_string(),
+                                price: previous_price,
+                            },
+                        );
+                        self.save(chat);
+                        CommandResult {
+                            success: true,
+                            response: format!(
+                                "The price of {} is now {}.\n\n{}",
+                                item, price, updated_order
+                            ),
+                            reply_markup: Some(updated_order.generate_reply_markup()),
+                        }
+                    }
+                    None => CommandResult::failure(format!("Order {} not found.", order_name)),
+                }
+            }
             None => CommandResult::failure(
-                "There are no orders in progress. To start an order, use /start <order name>.".into(),
+                "There are no orders in progress. To start an order, use /start <order name>."
+                    .into(),
             ),
         }
     }
 
-    /// Views all active orders for the chat
-    pub fn view_orders(&mut self, chat: &MessageChat) -> CommandResult {
-        match self.active_orders.get(chat) {
-            Some(conversation_orders) => CommandResult {
-                success: true,
-                response: format!("{}\n\nUse /order <item> to order, /cancel to cancel your order and /end when done.\nYou can also tap on an existing item to update or cancel your order.", conversation_orders),
-                reply_markup: Some(conversation_orders.generate_reply_markup()),
-            },
-            None => CommandResult::failure("There are no orders in progress. To start an order, use /start <order name>.".into())
+    /// Changes the quantity of an item the user ordered by `delta`
+    pub fn adjust_quantity(
+        &mut self,
+        chat: ChatId,
+        user: User,
+        order_name: &str,
+        item: &str,
+        delta: i32,
+    ) -> CommandResult {
+        match self.storage.get_mut(chat) {
+            Some(conversation_orders) => {
+                let previous_items = match conversation_orders.orders.get(order_name) {
+                    Some(order) => {
+                        if let Err(msg) = order.check_open() {
+                            return CommandResult::failure(msg);
+                        }
+                        Some(Inverse::restore_items(order, &user, None))
+                    }
+                    None => None,
+                };
+                match conversation_orders.adjust_quantity(order_name, user.clone(), item, delta) {
+                    Some((updated_order, _quantity)) => {
+                        if let Some(inverse) = previous_items {
+                            self.record_undo(
+                                chat,
+                                &user,
+                                format!(
+                                    "changing how many {} you ordered for {}",
+                                    item, order_name
+                                ),
+                                inverse,
+                            );
+                        }
+                        self.save(chat);
+                        CommandResult {
+                            success: true,
+                            response: format!(
+                                "{}\nUse /order <item> to update your order and /end when done.\nYou can also tap on an existing item to update or cancel your order.",
+                                updated_order
+                            ),
+                            reply_markup: Some(updated_order.generate_reply_markup()),
+                        }
+                    }
+                    None => CommandResult::failure(format!(
+                        "You have either not ordered {} for {}, or order {} does not exist.",
+                        item, order_name, order_name
+                    )),
+                }
+            }
+            None => CommandResult::failure(
+                "There are no orders in progress. To start an order, use /start <order name>."
+                    .into(),
+            ),
         }
     }
 
-    pub fn handle_callback_query(
+    /// Cancels an item the user ordered, or their only item if none is specified
+    pub fn remove_item(
         &mut self,
-        chat: &MessageChat,
-        user: User,
-        data: &str,
-        is_message_output_of_view: bool,
-    ) -> (CommandResult, String) {
-        let normalized_query = data.to_lowercase().trim().replace("@food_ordering_bot", "");
-        if let Some(sep) = normalized_query.find(' ') {
-            let order_name = &normalized_query[..sep];
-            let item = &normalized_query[sep + 1..];
-            // If only if let chains were properly implemented so this ugly 3-level nesting isn't needed :(
-            // if the user clicked on a button that corresponds to their current order, we should cancel it
-            // otherwise, the user wants to change their order
-            let mut should_cancel_existing_order = false;
-            if let Some(conversation_orders) = self.active_orders.get(chat) {
-                if let Some(order) = conversation_orders.orders.get(order_name) {
-                    if let Some(item_user_ordered) = order.find_user_item(&user) {
-                        should_cancel_existing_order = item_user_ordered == item
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        item: Option<&str>,
+    ) -> CommandResult {
+        match self.storage.get_mut(chat) {
+            Some(conversation_orders) => {
+                let previous_items = match conversation_orders.orders.get(order_name) {
+                    Some(order) => {
+                        if let Err(msg) = order.check_open() {
+                            return CommandResult::failure(msg);
+                        }
+                        Some(Inverse::restore_items(order, user, None))
+                    }
+                    None => None,
+                };
+                if item.is_none() {
+                    if let Some(order) = conversation_orders.orders.get(order_name) {
+                        if order.find_user_items(user).len() > 1 {
+                            return CommandResult::failure(format!(
+                                "You have ordered several items for {}. Specify the one to cancel, for example /cancel {} chocolate, or tap on it.",
+                                order_name, order_name
+                            ));
+                        }
                     }
                 }
+                match conversation_orders.remove_item(order_name, user, item) {
+                    Some(updated_order) => {
+                        let description = match item {
+                            Some(item) => format!("cancelling {} for {}", item, order_name),
+                            None => format!("cancelling your order for {}", order_name),
+                        };
+                        if let Some(inverse) = previous_items {
+                            self.record_undo(chat, user, description, inverse);
+                        }
+                        self.save(chat);
+                        CommandResult {
+                            success: true,
+                            response: format!(
+                                "{}\nUse /order <item> to order, and /end when done.\nYou can also tap on an existing item to update or cancel your order.",
+                                updated_order
+                            ),
+                            reply_markup: Some(updated_order.generate_reply_markup()),
+                        }
+                    }
+                    None => match item {
+                        Some(item) => CommandResult::failure(format!(
+                            "You have either not ordered {} for {}, or order {} does not exist.",
+                            item, order_name, order_name
+                        )),
+                        None => CommandResult::failure(format!(
+                            "You have either not placed any orders for {}, or order {} does not exist.",
+                            order_name, order_name
+                        )),
+                    },
+                }
             }
+            None => CommandResult::failure(
+                "There are no orders in progress. To start an order, use /start <order name>."
+                    .into(),
+            ),
+        }
+    }
 
-            let res = if should_cancel_existing_order {
-                self.remove_item(chat, &user, order_name)
-            } else {
-                // order this item, overriding any previous orders if needed
-                self.add_item(chat, user, order_name, item.to_string())
+    /// Returns the conversation's orders if the user manages the given order
+    /// Only the owner and co-owners of an order may change its settings
+    fn orders_owned_by(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+    ) -> Result<&mut ConversationOrders, CommandResult> {
+        let conversation_orders =
+            match self.storage.get_mut(chat) {
+                Some(conversation_orders) => conversation_orders,
+                None => return Err(CommandResult::failure(
+                    "There are no orders in progress. To start an order, use /start <order name>."
+                        .into(),
+                )),
             };
+        match conversation_orders.orders.get(order_name) {
+            Some(order) if !order.is_managed_by(user) => Err(CommandResult::failure(format!(
+                "Only {} may change this for {}.",
+                order.managers(),
+                order_name
+            ))),
+            Some(_) => Ok(conversation_orders),
+            None => Err(CommandResult::failure(format!(
+                "Order {} not found.",
+                order_name
+            ))),
+        }
+    }
 
-            if res.success {
-                let answer = if should_cancel_existing_order {
-                    format!("Cancelled order of {} for {}.", item, order_name)
-                } else {
-                    format!("Updated order for {} to {}.", order_name, item)
-                };
-                if is_message_output_of_view {
-                    // the response in res only contains info about the current order being edited
-                    // since the message associated with the callback query contains all orders,
-                    // we need to retrieve info about all orders to correctly edit it
-                    (self.view_orders(chat), answer)
-                } else {
-                    (res, answer)
-                }
-            } else {
-                let answer = res.response.clone();
-                (res, answer)
-            }
+    /// Shows who manages an order
+    pub fn list_owners(&self, chat: ChatId, order_name: &str) -> CommandResult {
+        let order = match self
+            .storage
+            .get(chat)
+            .and_then(|conversation_orders| conversation_orders.orders.get(order_name))
+        {
+            Some(order) => order,
+            None => return CommandResult::failure(format!("Order {} not found.", order_name)),
+        };
+        let co_owners: Vec<&str> = order
+            .co_owners
+            .iter()
+            .map(|user| user.first_name.as_str())
+            .collect();
+        let managers = if co_owners.is_empty() {
+            format!("{} is managed by {}.", order_name, order.owner.first_name)
         } else {
-            (
-                CommandResult::failure("Unrecognized callback query".into()),
-                "Invalid order or item name".to_string(),
+            format!(
+                "{} is managed by {}, with {} as co-owners.",
+                order_name,
+                order.owner.first_name,
+                co_owners.join(", ")
             )
+        };
+        CommandResult::success(format!(
+            "{}\nUse /owners add <name> or /owners remove <name> to change who manages it, or /handover <name> to hand it over.",
+            managers
+        ))
+    }
+
+    /// Lets another user manage an order, found by their username or first name
+    /// Only those who manage the order may add co-owners
+    pub fn add_owner(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        name: &str,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let co_owner = match conversation_orders.find_user(name) {
+            Some(co_owner) if order::is_guest(co_owner) => return guest_cannot_manage(co_owner),
+            Some(co_owner) => co_owner.clone(),
+            None => return unknown_user(name),
+        };
+        let order = conversation_orders.orders.get_mut(order_name).unwrap();
+        if !order.add_co_owner(co_owner.clone()) {
+            return CommandResult::failure(format!(
+                "{} already manages {}.",
+                co_owner.first_name, order_name
+            ));
         }
+        let managers = order.managers();
+        self.save(chat);
+        CommandResult::success(format!(
+            "{} may now manage {}. It is managed by {}.",
+            co_owner.first_name, order_name, managers
+        ))
     }
 
-    pub fn get_active_order_names(&self, chat: &MessageChat) -> Vec<&str> {
-        match self.active_orders.get(chat) {
-            Some(active_orders) => active_orders.orders.keys().map(|k| k.as_ref()).collect(),
-            None => vec![],
+    /// Stops a co-owner from managing an order, found by their username or first name
+    /// Only those who manage the order may remove co-owners
+    pub fn remove_owner(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        name: &str,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let co_owner = match conversation_orders.find_user(name) {
+            Some(co_owner) => co_owner.clone(),
+            None => return unknown_user(name),
+        };
+        let order = conversation_orders.orders.get_mut(order_name).unwrap();
+        if order.owner.id == co_owner.id {
+            return CommandResult::failure(format!(
+                "{} owns {}, so they can't be removed. Use /handover <name> to hand it over instead.",
+                co_owner.first_name, order_name
+            ));
+        }
+        if !order.remove_co_owner(&co_owner) {
+            return CommandResult::failure(format!(
+                "{} doesn't manage {}.",
+                co_owner.first_name, order_name
+            ));
         }
+        self.save(chat);
+        CommandResult::success(format!(
+            "{} no longer manages {}.",
+            co_owner.first_name, order_name
+        ))
     }
 
-    pub fn has_active_orders(&self) -> bool {
-        !self.active_orders.is_empty()
+    /// Hands over the user's management of an order to someone else, found by their username or first name
+    /// If the user owns the order, the new owner is paid for it instead
+    pub fn hand_over(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        name: &str,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let new_manager = match conversation_orders.find_user(name) {
+            Some(new_manager) if order::is_guest(new_manager) => {
+                return guest_cannot_manage(new_manager)
+            }
+            Some(new_manager) => new_manager.clone(),
+            None => return unknown_user(name),
+        };
+        if new_manager.id == user.id {
+            return CommandResult::failure(format!("You already manage {}.", order_name));
+        }
+        let order = conversation_orders.orders.get_mut(order_name).unwrap();
+        let was_owner = order.owner.id == user.id;
+        // owners may hand over to a co-owner, who then owns the order alone
+        if !was_owner && order.is_managed_by(&new_manager) {
+            return CommandResult::failure(format!(
+                "{} already manages {}.",
+                new_manager.first_name, order_name
+            ));
+        }
+        order.hand_over(user, new_manager.clone());
+        self.save(chat);
+        if was_owner {
+            CommandResult::success(format!(
+                "{} now owns {}, and will be paid for it.",
+                new_manager.first_name, order_name
+            ))
+        } else {
+            CommandResult::success(format!(
+                "{} now manages {} instead of {}.",
+                new_manager.first_name, order_name, user.first_name
+            ))
+        }
+    }
+
+    /// Locks or unlocks an order, or records that it was placed or has arrived
+    /// Only those who manage the order may change its state
+    pub fn change_state(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        state: OrderState,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let order = conversation_orders.orders.get_mut(order_name).unwrap();
+        if let Err(msg) = order.set_state(state) {
+            return CommandResult::failure(msg);
+        }
+        let order = order.clone();
+        self.save(chat);
+        CommandResult::success(match state {
+            OrderState::Locked => format!(
+                "{} is now locked, so orders can't be changed. Use /unlock {} to allow changes again, or /placed {} once it has been placed.",
+                order_name, order_name, order_name
+            ),
+            OrderState::Open => format!(
+                "{} is open again, so orders can be changed.",
+                order_name
+            ),
+            OrderState::Placed => format!(
+                "{} has been placed. Use /arrived {} once it arrives.",
+                order_name, order_name
+            ),
+            OrderState::Delivered => format!(
+                "{} has arrived!\n\n{}\n\nUse /end {} to end it.",
+                order_name, order, order_name
+            ),
+            OrderState::Closed => unreachable!("orders are closed by ending them"),
+        })
+    }
+
+    /// Allows or disallows ordering several different items in an order
+    /// Only the creater of the order may change this
+    pub fn toggle_multiple_items(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let allow_multiple_items = conversation_orders.toggle_multiple_items(order_name);
+        self.save(chat);
+        if allow_multiple_items == Some(true) {
+            CommandResult::success(format!(
+                "Everyone may now order several different items for {}.",
+                order_name
+            ))
+        } else {
+            CommandResult::success(format!(
+                "Ordering an item for {} now replaces your previous item.",
+                order_name
+            ))
+        }
+    }
+
+    /// Limits ordering to the items on an order's menu, or allows any item to be ordered again
+    /// Only the creater of the order may change this
+    pub fn toggle_menu_only(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let can_limit_to_menu = conversation_orders
+            .orders
+            .get(order_name)
+            .is_some_and(|order| order.menu_only || !order.attached_menu.is_empty());
+        if !can_limit_to_menu {
+            return CommandResult::failure(format!(
+                "{} was not started with a menu, so it can't be limited to one.",
+                order_name
+            ));
+        }
+        let menu_only = conversation_orders.toggle_menu_only(order_name);
+        self.save(chat);
+        if menu_only == Some(true) {
+            CommandResult::success(format!(
+                "Only items on the menu may now be ordered for {}.",
+                order_name
+            ))
+        } else {
+            CommandResult::success(format!("Any item may now be ordered for {}.", order_name))
+        }
+    }
+
+    /// Sets the time at which an order is automatically ended, or removes it
+    /// Only the creater of the order may change this
+    pub fn set_deadline(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        time: Option<NaiveTime>,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let deadline = time.map(|time| deadline::next_occurrence(time, Utc::now()));
+        match conversation_orders.set_deadline(order_name, deadline) {
+            Some(_) => {
+                self.save(chat);
+                match deadline {
+                    Some(deadline) => CommandResult::success(format!(
+                        "{} now ends automatically at {}.",
+                        order_name,
+                        format_time(&deadline)
+                    )),
+                    None => CommandResult::success(format!(
+                        "{} no longer ends automatically. Use /end {} when done.",
+                        order_name, order_name
+                    )),
+                }
+            }
+            None => CommandResult::failure(format!("Order {} not found.", order_name)),
+        }
+    }
+
+    /// Sets how many minutes before an order's deadline reminders are posted, with no reminders stopping them
+    /// Only the creater of the order may change this
+    pub fn set_reminders(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        reminders: Vec<u32>,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        match conversation_orders.set_reminders(order_name, reminders) {
+            Some(order) => {
+                self.save(chat);
+                if order.reminders.is_empty() {
+                    return CommandResult::success(format!(
+                        "Reminders will no longer be posted for {}.",
+                        order_name
+                    ));
+                }
+                let minutes: Vec<String> = order.reminders.iter().map(u32::to_string).collect();
+                let response = format!(
+                    "Reminders for {} will be posted {} minutes before it ends.",
+                    order_name,
+                    minutes.join(" and ")
+                );
+                if order.deadline.is_some() {
+                    CommandResult::success(response)
+                } else {
+                    CommandResult::success(format!(
+                        "{} Set when it ends with /deadline {} <time>.",
+                        response, order_name
+                    ))
+                }
+            }
+            None => CommandResult::failure(format!("Order {} not found.", order_name)),
+        }
+    }
+
+    /// Changes the delivery fee, service charge or tax, tip or how they are split for an order
+    /// Only the creater of the order may change these
+    pub fn set_charge(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: &str,
+        charge: Charge,
+    ) -> CommandResult {
+        let conversation_orders = match self.orders_owned_by(chat, user, order_name) {
+            Ok(conversation_orders) => conversation_orders,
+            Err(res) => return res,
+        };
+        let response = charge.to_string();
+        let previous_charges = conversation_orders.orders[order_name].charges.clone();
+        match conversation_orders.set_charge(order_name, charge) {
+            Some(updated_order) => {
+                self.record_undo(
+                    chat,
+                    user,
+                    format!("changing the charges for {}", order_name),
+                    Inverse::RestoreCharges {
+                        order_name: order_name.to_string(),
+                        charges: previous_charges,
+                    },
+                );
+                self.save(chat);
+                CommandResult::success(format!("{}\n\n{}", response, updated_order))
+            }
+            None => CommandResult::failure(format!("Order {} not found.", order_name)),
+        }
+    }
+
+    /// Records that the user paid for an ended order
+    /// The order may be omitted if the user only owes money for one order
+    pub fn mark_paid(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        order_name: Option<&str>,
+    ) -> CommandResult {
+        let unpaid_orders: Vec<(u64, String)> = match self.storage.get(chat) {
+            Some(conversation_orders) => conversation_orders
+                .find_unpaid_orders(user)
+                .into_iter()
+                .filter(|ended_order| order_name.is_none_or(|name| ended_order.order.name == name))
+                .map(|ended_order| (ended_order.id, ended_order.order.name.clone()))
+                .collect(),
+            None => vec![],
+        };
+        match unpaid_orders.as_slice() {
+            [] => match order_name {
+                Some(order_name) => {
+                    CommandResult::failure(format!("You don't owe anything for {}.", order_name))
+                }
+                None => CommandResult::failure("You don't owe anything.".into()),
+            },
+            [(id, _)] => self.pay(chat, user, *id),
+            _ => {
+                let names: Vec<&str> = unpaid_orders
+                    .iter()
+                    .map(|(_, name)| name.as_ref())
+                    .collect();
+                CommandResult::failure(format!(
+                    "You owe money for several orders: {}. Specify the one you paid for. For example, /paid {}",
+                    names.join(", "),
+                    names[0]
+                ))
+            }
+        }
+    }
+
+    /// Records that the user paid for the ended order with the given id
+    fn pay(&mut self, chat: ChatId, user: &User, id: u64) -> CommandResult {
+        let result = match self.storage.get_mut(chat) {
+            Some(conversation_orders) => conversation_orders.mark_paid(id, user),
+            None => Err("Everyone has already paid for this order.".into()),
+        };
+        match result {
+            Ok(ended_order) => {
+                self.save(chat);
+                payment_result(&ended_order)
+            }
+            Err(msg) => CommandResult::failure(msg),
+        }
+    }
+
+    /// Shows who has yet to pay for each ended order
+    pub fn view_unpaid(&self, chat: ChatId) -> CommandResult {
+        let awaiting_payment = match self.storage.get(chat) {
+            Some(conversation_orders) => &conversation_orders.awaiting_payment[..],
+            None => &[],
+        };
+        if awaiting_payment.is_empty() {
+            return CommandResult::failure("Everyone has paid for their orders.".into());
+        }
+        let orders: Vec<String> = awaiting_payment
+            .iter()
+            .map(|ended_order| {
+                format!(
+                    "{}\n{}",
+                    ended_order.summary_line(),
+                    ended_order.payment_status()
+                )
+            })
+            .collect();
+        CommandResult::success(orders.join("\n\n"))
+    }
+
+    /// Shows how much everyone owes or is owed across ended orders
+    pub fn view_balances(&self, chat: ChatId) -> CommandResult {
+        let balances = match self.storage.get(chat) {
+            Some(conversation_orders) => conversation_orders.ledger.outstanding_balances(),
+            None => vec![],
+        };
+        if balances.is_empty() {
+            return CommandResult::failure("Nobody owes anything.".into());
+        }
+        let lines: Vec<String> = balances
+            .iter()
+            .map(|balance| {
+                if balance.amount < Money::default() {
+                    format!("{} owes {}", balance.user.first_name, -balance.amount)
+                } else {
+                    format!("{} is owed {}", balance.user.first_name, balance.amount)
+                }
+            })
+            .collect();
+        CommandResult::success(format!(
+            "Balances:\n{}\n\nUse /settle to see who should pay whom.",
+            lines.join("\n")
+        ))
+    }
+
+    /// Suggests payments which settle everyone's balances, with buttons to record them
+    pub fn settle_up(&self, chat: ChatId) -> CommandResult {
+        let transfers = match self.storage.get(chat) {
+            Some(conversation_orders) => conversation_orders.ledger.settle_up(),
+            None => vec![],
+        };
+        if transfers.is_empty() {
+            return CommandResult::failure("Nobody owes anything.".into());
+        }
+        let lines: Vec<String> = transfers
+            .iter()
+            .map(|transfer| transfer.to_string())
+            .collect();
+        let mut reply_markup = InlineKeyboardMarkup::new();
+        for transfer in &transfers {
+            reply_markup.add_row(vec![InlineKeyboardButton::callback(
+                format!(
+                    "{} paid {} {}",
+                    transfer.from.first_name, transfer.to.first_name, transfer.amount
+                ),
+                CallbackAction::RecordTransfer(transfer.from.id, transfer.to.id, transfer.amount)
+                    .to_data(),
+            )]);
+        }
+        CommandResult {
+            success: true,
+            response: format!(
+                "To settle up:\n{}\n\nTap on a payment once it has been made.",
+                lines.join("\n")
+            ),
+            reply_markup: Some(reply_markup),
+        }
+    }
+
+    /// Records a payment suggested by /settle
+    /// Only the users paying or being paid may record it
+    fn record_transfer(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        from: UserId,
+        to: UserId,
+        amount: Money,
+    ) -> CommandResult {
+        if user.id != from && user.id != to {
+            return CommandResult::failure(
+                "Only the people paying or being paid may record this payment.".into(),
+            );
+        }
+        let result = match self.storage.get_mut(chat) {
+            Some(conversation_orders) => conversation_orders.record_transfer(from, to, amount),
+            None => Err("This payment has already been settled.".into()),
+        };
+        match result {
+            Ok(transfer) => {
+                self.save(chat);
+                let recorded = format!(
+                    "Recorded that {} paid {} {}.",
+                    transfer.from.first_name, transfer.to.first_name, transfer.amount
+                );
+                let res = self.settle_up(chat);
+                if res.success {
+                    CommandResult {
+                        response: format!("{}\n\n{}", recorded, res.response),
+                        ..res
+                    }
+                } else {
+                    CommandResult::success(format!("{}\nEveryone is settled up.", recorded))
+                }
+            }
+            Err(msg) => CommandResult::failure(msg),
+        }
+    }
+
+    /// Saves a menu for the conversation, so that orders can offer it from the start
+    pub fn save_menu(&mut self, chat: ChatId, name: String, items: Vec<MenuItem>) -> CommandResult {
+        if name.len() > 30 {
+            return CommandResult::failure("Menu names must not exceed 30 characters.".to_string());
+        }
+        let response = format!(
+            "Saved menu {}:\n{}\n\nUse /start {} or /start <order name> --menu {} to offer it.",
+            name,
+            menu::format_menu(&items),
+            name,
+            name
+        );
+        self.conversation_mut(chat).menus.insert(name, items);
+        self.save(chat);
+        CommandResult::success(response)
+    }
+
+    /// Saves a menu from the contents of an uploaded CSV or JSON file, reporting any invalid rows
+    pub fn import_menu(
+        &mut self,
+        chat: ChatId,
+        name: String,
+        file_name: &str,
+        contents: &[u8],
+    ) -> CommandResult {
+        match menu::import_menu(file_name, contents) {
+            Ok(items) => self.save_menu(chat, name, items),
+            Err(errors) => CommandResult::failure(format!(
+                "Menu {} was not saved, since {} has errors:\n{}",
+                name,
+                file_name,
+                errors.join("\n")
+            )),
+        }
+    }
+
+    /// Lists the menus saved for the conversation
+    pub fn list_menus(&self, chat: ChatId) -> CommandResult {
+        let names: Vec<&str> = match self.storage.get(chat) {
+            Some(conversation_orders) => conversation_orders
+                .menus
+                .keys()
+                .map(|name| name.as_ref())
+                .collect(),
+            None => vec![],
+        };
+        if names.is_empty() {
+            return CommandResult::failure(
+                "No menus have been saved. Save one with /menu save <name> followed by its items."
+                    .into(),
+            );
+        }
+        CommandResult::success(format!(
+            "Saved menus:\n{}\n\nUse /menu show <name> to see a menu's items.",
+            names.join("\n")
+        ))
+    }
+
+    /// Shows the items of a saved menu
+    pub fn show_menu(&self, chat: ChatId, name: &str) -> CommandResult {
+        match self
+            .storage
+            .get(chat)
+            .and_then(|conversation_orders| conversation_orders.menus.get(name))
+        {
+            Some(items) => {
+                CommandResult::success(format!("Menu {}:\n{}", name, menu::format_menu(items)))
+            }
+            None => CommandResult::failure(format!(
+                "There is no saved menu named {}. Use /menu list to see saved menus.",
+                name
+            )),
+        }
+    }
+
+    /// Deletes a saved menu
+    pub fn delete_menu(&mut self, chat: ChatId, name: &str) -> CommandResult {
+        let deleted = self
+            .storage
+            .get_mut(chat)
+            .and_then(|conversation_orders| conversation_orders.menus.remove(name))
+            .is_some();
+        if deleted {
+            self.save(chat);
+            CommandResult::success(format!("Deleted menu {}.", name))
+        } else {
+            CommandResult::failure(format!(
+                "There is no saved menu named {}. Use /menu list to see saved menus.",
+                name
+            ))
+        }
+    }
+
+    /// Schedules an order to be started automatically, such as every Friday at 10:00
+    pub fn schedule_order(
+        &mut self,
+        chat: ChatId,
+        owner: User,
+        order_name: String,
+        recurrence: Recurrence,
+        options: StartOptions,
+    ) -> CommandResult {
+        if let Err(res) = check_order_name(&order_name) {
+            return res;
+        }
+        if let Some(menu_name) = &options.saved_menu {
+            let has_menu = self.storage.get(chat).is_some_and(|conversation_orders| {
+                conversation_orders.menus.contains_key(menu_name)
+            });
+            if !has_menu {
+                return CommandResult::failure(format!(
+                    "There is no saved menu named {}. Use /menu list to see saved menus.",
+                    menu_name
+                ));
+            }
+        }
+        let schedule = self.conversation_mut(chat).add_schedule(
+            owner,
+            order_name,
+            recurrence,
+            options,
+            Utc::now(),
+        );
+        let response = format!(
+            "Scheduled {}.\nUse /schedules to see or delete scheduled orders.",
+            schedule
+        );
+        self.save(chat);
+        CommandResult::success(response)
+    }
+
+    /// Lists the orders scheduled for the chat, with buttons to delete them
+    pub fn list_schedules(&self, chat: ChatId) -> CommandResult {
+        let schedules = match self.storage.get(chat) {
+            Some(conversation_orders) if !conversation_orders.schedules.is_empty() => {
+                &conversation_orders.schedules
+            }
+            _ => {
+                return CommandResult::failure(
+                    "No orders have been scheduled. Schedule one with /schedule <order name> <days> <time>, such as /schedule waffles fri 10:00."
+                        .into(),
+                )
+            }
+        };
+        let mut response = "Scheduled orders:\n".to_string();
+        let mut reply_markup = InlineKeyboardMarkup::new();
+        for schedule in schedules {
+            response.push_str(&format!("\n{}. {}.", schedule.id, schedule));
+            reply_markup.add_row(vec![InlineKeyboardButton::callback(
+                format!("Delete {}. {}", schedule.id, schedule.order_name),
+                CallbackAction::DeleteSchedule(schedule.id).to_data(),
+            )]);
+        }
+        response.push_str(
+            "\n\nTap on a button or use /schedules delete <number> to delete a scheduled order.",
+        );
+        CommandResult {
+            success: true,
+            response,
+            reply_markup: Some(reply_markup),
+        }
+    }
+
+    /// Stops starting a scheduled order, listing those which remain
+    /// Only whoever scheduled the order may delete it
+    pub fn delete_schedule(&mut self, chat: ChatId, user: &User, id: u64) -> CommandResult {
+        let owner = self
+            .storage
+            .get(chat)
+            .and_then(|conversation_orders| {
+                conversation_orders.schedules.iter().find(|s| s.id == id)
+            })
+            .map(|schedule| (schedule.owner.clone(), schedule.order_name.clone()));
+        if let Some((owner, order_name)) = owner {
+            if owner.id != user.id {
+                return CommandResult::failure(format!(
+                    "Only {}, who scheduled {}, may delete it.",
+                    owner.first_name, order_name
+                ));
+            }
+        }
+        let deleted = self
+            .storage
+            .get_mut(chat)
+            .and_then(|conversation_orders| conversation_orders.remove_schedule(id));
+        match deleted {
+            Some(schedule) => {
+                self.save(chat);
+                let response = format!("Deleted scheduled order {}. {}.", id, schedule.order_name);
+                let remaining = self.list_schedules(chat);
+                if remaining.success {
+                    CommandResult {
+                        response: format!("{}\n\n{}", response, remaining.response),
+                        ..remaining
+                    }
+                } else {
+                    CommandResult::success(response)
+                }
+            }
+            None => CommandResult::failure(format!(
+                "There is no scheduled order numbered {}. Use /schedules to list them.",
+                id
+            )),
+        }
+    }
+
+    /// Views all active orders for the chat
+    pub fn view_orders(&self, chat: ChatId) -> CommandResult {
+        match self.storage.get(chat) {
+            Some(conversation_orders) if !conversation_orders.orders.is_empty() => CommandResult {
+                success: true,
+                response: format!("{}\n\nUse /order <item> to order, /cancel to cancel your order and /end when done.\nYou can also tap on an existing item to update or cancel your order.", conversation_orders),
+                reply_markup: Some(conversation_orders.generate_reply_markup()),
+            },
+            _ => CommandResult::failure("There are no orders in progress. To start an order, use /start <order name>.".into())
+        }
+    }
+
+    /// Lists up to `count` of the most recently ended orders, with buttons to show their summaries
+    pub fn view_history(&self, chat: ChatId, count: usize) -> CommandResult {
+        let recent_orders = match self.storage.get(chat) {
+            Some(conversation_orders) => conversation_orders.recent_history(count),
+            None => vec![],
+        };
+        if recent_orders.is_empty() {
+            return CommandResult::failure("No orders have ended yet.".into());
+        }
+        let lines: Vec<String> = recent_orders
+            .iter()
+            .enumerate()
+            .map(|(i, archived)| format!("{}. {}", i + 1, archived.summary_line()))
+            .collect();
+        let buttons: Vec<InlineKeyboardButton> = recent_orders
+            .iter()
+            .enumerate()
+            .map(|(i, archived)| {
+                InlineKeyboardButton::callback(
+                    format!("{}. {}", i + 1, archived.order.name),
+                    CallbackAction::ShowArchivedOrder(archived.id).to_data(),
+                )
+            })
+            .collect();
+        let mut reply_markup = InlineKeyboardMarkup::new();
+        for row in buttons.chunks(2) {
+            reply_markup.add_row(row.to_vec());
+        }
+        CommandResult {
+            success: true,
+            response: format!(
+                "Recently ended orders:\n\n{}\n\nTap on an order to show its summary.",
+                lines.join("\n")
+            ),
+            reply_markup: Some(reply_markup),
+        }
+    }
+
+    pub fn handle_callback_query(
+        &mut self,
+        chat: ChatId,
+        user: User,
+        data: &str,
+        is_message_output_of_view: bool,
+    ) -> (CommandResult, String) {
+        match callback::parse_callback_data(data) {
+            Some(CallbackAction::ToggleItem(order_name, item)) => {
+                self.toggle_item(chat, user, &order_name, &item, is_message_output_of_view)
+            }
+            Some(CallbackAction::IncrementItem(order_name, item)) => {
+                // items with options are only ordered once their options have been chosen,
+                // after which adding one more keeps the options already chosen
+                let order = self
+                    .storage
+                    .get(chat)
+                    .and_then(|conversation_orders| conversation_orders.orders.get(&order_name));
+                let has_ordered_item = order
+                    .is_some_and(|order| order.find_user_items(&user).iter().any(|i| i == &item));
+                let option_groups =
+                    order.map_or(vec![], |order| order.option_groups(&item).to_vec());
+                if !has_ordered_item && !option_groups.is_empty() {
+                    return self.start_option_wizard(chat, user, &order_name, &item, option_groups);
+                }
+                let res = self.adjust_quantity(chat, user, &order_name, &item, 1);
+                let answer = format!("Added one {} to your order for {}.", item, order_name);
+                self.callback_result(chat, res, answer, is_message_output_of_view)
+            }
+            Some(CallbackAction::DecrementItem(order_name, item)) => {
+                let res = self.adjust_quantity(chat, user, &order_name, &item, -1);
+                let answer = format!("Removed one {} from your order for {}.", item, order_name);
+                self.callback_result(chat, res, answer, is_message_output_of_view)
+            }
+            Some(CallbackAction::MarkPaid(id)) => {
+                let res = self.pay(chat, &user, id);
+                let answer = "Thanks for paying!".to_string();
+                self.callback_result(chat, res, answer, false)
+            }
+            Some(CallbackAction::RecordTransfer(from, to, amount)) => {
+                let res = self.record_transfer(chat, &user, from, to, amount);
+                let answer = "Payment recorded.".to_string();
+                self.callback_result(chat, res, answer, false)
+            }
+            Some(CallbackAction::ReopenOrder(id)) => {
+                let res = self.reopen_ended_order(chat, &user, id);
+                let answer = "Order reopened.".to_string();
+                self.callback_result(chat, res, answer, false)
+            }
+            Some(CallbackAction::DeleteSchedule(id)) => {
+                let res = self.delete_schedule(chat, &user, id);
+                let answer = "Scheduled order deleted.".to_string();
+                self.callback_result(chat, res, answer, false)
+            }
+            Some(CallbackAction::ChooseOption(id, index)) => {
+                self.choose_option(chat, user, id, Some(index), is_message_output_of_view)
+            }
+            Some(CallbackAction::FinishOptionGroup(id)) => {
+                self.choose_option(chat, user, id, None, is_message_output_of_view)
+            }
+            Some(CallbackAction::CancelOptions(id)) => {
+                self.cancel_options(chat, &user, id, is_message_output_of_view)
+            }
+            Some(CallbackAction::ShowArchivedOrder(id)) => {
+                match self
+                    .storage
+                    .get(chat)
+                    .and_then(|conversation_orders| conversation_orders.find_archived_order(id))
+                {
+                    Some(archived) => (
+                        CommandResult::success(format!("{}", archived)),
+                        format!("Showing order for {}.", archived.order.name),
+                    ),
+                    None => (
+                        CommandResult::failure("Archived order not found".into()),
+                        "This order is no longer kept.".to_string(),
+                    ),
+                }
+            }
+            None => (
+                CommandResult::failure("Unrecognized callback query".into()),
+                "Invalid order or item name".to_string(),
+            ),
+        }
+    }
+
+    /// Orders the tapped item, or cancels it if the user had already ordered it
+    fn toggle_item(
+        &mut self,
+        chat: ChatId,
+        user: User,
+        order_name: &str,
+        item: &str,
+        is_message_output_of_view: bool,
+    ) -> (CommandResult, String) {
+        // if the user clicked on a button that corresponds to an item they ordered, we should cancel it
+        // otherwise, the user wants to order it
+        let order = self
+            .storage
+            .get(chat)
+            .and_then(|conversation_orders| conversation_orders.orders.get(order_name));
+        let should_cancel_existing_order =
+            order.is_some_and(|order| order.find_user_items(&user).iter().any(|i| i == item));
+        // items with options are only ordered once their options have been chosen
+        let option_groups = order.map_or(vec![], |order| order.option_groups(item).to_vec());
+        if !should_cancel_existing_order && !option_groups.is_empty() {
+            return self.start_option_wizard(chat, user, order_name, item, option_groups);
+        }
+
+        let res = if should_cancel_existing_order {
+            self.remove_item(chat, &user, order_name, Some(item))
+        } else {
+            // order this item, overriding any previous orders if needed
+            self.add_item(chat, user, order_name, ItemRequest::new(item))
+        };
+        let answer = if should_cancel_existing_order {
+            format!("Cancelled order of {} for {}.", item, order_name)
+        } else {
+            format!("Updated order for {} to {}.", order_name, item)
+        };
+        self.callback_result(chat, res, answer, is_message_output_of_view)
+    }
+
+    /// Replaces the tapped message with the choices for the first of an item's option groups
+    fn start_option_wizard(
+        &mut self,
+        chat: ChatId,
+        user: User,
+        order_name: &str,
+        item: &str,
+        option_groups: Vec<OptionGroup>,
+    ) -> (CommandResult, String) {
+        let conversation_orders = self
+            .storage
+            .get_mut(chat)
+            .expect("items are only tapped on in conversations with orders");
+        if let Some(Err(msg)) = conversation_orders
+            .orders
+            .get(order_name)
+            .map(Order::check_open)
+        {
+            return (CommandResult::failure(msg.clone()), msg);
+        }
+        let wizard = conversation_orders.start_option_wizard(user, order_name, item, option_groups);
+        let res = CommandResult {
+            success: true,
+            response: wizard.prompt(),
+            reply_markup: Some(wizard.generate_reply_markup()),
+        };
+        let answer = format!("Choose the options of your {}.", item);
+        self.save(chat);
+        (res, answer)
+    }
+
+    /// Makes a choice in an option wizard, or finishes the current group if `choice` is None
+    /// The item is ordered once every group has been chosen, and the tapped message shows the order again
+    fn choose_option(
+        &mut self,
+        chat: ChatId,
+        user: User,
+        id: u64,
+        choice: Option<usize>,
+        is_message_output_of_view: bool,
+    ) -> (CommandResult, String) {
+        let wizard = match self.storage.get_mut(chat).and_then(|conversation_orders| {
+            conversation_orders
+                .option_wizards
+                .iter_mut()
+                .find(|wizard| wizard.id == id)
+        }) {
+            Some(wizard) => wizard,
+            None => return no_option_wizard(),
+        };
+        if wizard.user.id != user.id {
+            let msg = format!(
+                "{} is choosing these options. Tap on an item to order it yourself.",
+                wizard.user.first_name
+            );
+            return (CommandResult::failure(msg.clone()), msg);
+        }
+        let answer = match choice {
+            Some(index) => wizard.choose(index),
+            None => wizard.finish_group().map(|_| "Done.".to_string()),
+        };
+        let answer = match answer {
+            Ok(answer) => answer,
+            Err(msg) => return (CommandResult::failure(msg.clone()), msg),
+        };
+        if !wizard.is_complete() {
+            let res = CommandResult {
+                success: true,
+                response: wizard.prompt(),
+                reply_markup: Some(wizard.generate_reply_markup()),
+            };
+            self.save(chat);
+            return (res, answer);
+        }
+
+        let conversation_orders = self.storage.get_mut(chat).unwrap();
+        let index = conversation_orders
+            .option_wizards
+            .iter()
+            .position(|wizard| wizard.id == id)
+            .unwrap();
+        let wizard = conversation_orders.option_wizards.remove(index);
+        let answer = format!(
+            "Updated order for {} to {} with {}.",
+            wizard.order_name,
+            wizard.item,
+            menu::describe_selections(&wizard.selections)
+        );
+        let request = ItemRequest {
+            options: wizard.selections,
+            ..ItemRequest::new(&wizard.item)
+        };
+        let order_name = &wizard.order_name;
+        let res = self.add_item(chat, user, order_name, request);
+        self.save(chat);
+        if !res.success {
+            // the wizard is gone, so its message shows why the item wasn't ordered, above the order if it is still active
+            let order = self
+                .storage
+                .get(chat)
+                .and_then(|conversation_orders| conversation_orders.orders.get(order_name));
+            return match order {
+                Some(order) => {
+                    let (shown, _) = self.callback_result(
+                        chat,
+                        order_result(order),
+                        String::new(),
+                        is_message_output_of_view,
+                    );
+                    let response = format!("{}\n\n{}", res.response, shown.response);
+                    (CommandResult { response, ..shown }, res.response)
+                }
+                None => (CommandResult::success(res.response.clone()), res.response),
+            };
+        }
+        self.callback_result(chat, res, answer, is_message_output_of_view)
+    }
+
+    /// Stops choosing an item's options without ordering it, and shows the order again
+    fn cancel_options(
+        &mut self,
+        chat: ChatId,
+        user: &User,
+        id: u64,
+        is_message_output_of_view: bool,
+    ) -> (CommandResult, String) {
+        let conversation_orders = match self.storage.get_mut(chat) {
+            Some(conversation_orders) => conversation_orders,
+            None => return no_option_wizard(),
+        };
+        let index = match conversation_orders
+            .option_wizards
+            .iter()
+            .position(|wizard| wizard.id == id)
+        {
+            Some(index) => index,
+            None => return no_option_wizard(),
+        };
+        let wizard = &conversation_orders.option_wizards[index];
+        if wizard.user.id != user.id {
+            let msg = format!(
+                "{} is choosing these options. Tap on an item to order it yourself.",
+                wizard.user.first_name
+            );
+            return (CommandResult::failure(msg.clone()), msg);
+        }
+        let wizard = conversation_orders.option_wizards.remove(index);
+        let res = match conversation_orders.orders.get(&wizard.order_name) {
+            Some(order) => order_result(order),
+            None => CommandResult::failure(format!("{} has ended.", wizard.order_name)),
+        };
+        self.save(chat);
+        let answer = format!("Cancelled ordering {}.", wizard.item);
+        self.callback_result(chat, res, answer, is_message_output_of_view)
+    }
+
+    /// Pairs the result of a callback query with the answer shown to the user, which is the error if it failed
+    fn callback_result(
+        &self,
+        chat: ChatId,
+        res: CommandResult,
+        answer: String,
+        is_message_output_of_view: bool,
+    ) -> (CommandResult, String) {
+        if res.success {
+            if is_message_output_of_view {
+                // the response in res only contains info about the current order being edited
+                // since the message associated with the callback query contains all orders,
+                // we need to retrieve info about all orders to correctly edit it
+                (self.view_orders(chat), answer)
+            } else {
+                (res, answer)
+            }
+        } else {
+            let answer = res.response.clone();
+            (res, answer)
+        }
+    }
+
+    pub fn get_active_order_names(&self, chat: ChatId) -> Vec<&str> {
+        match self.storage.get(chat) {
+            Some(active_orders) => active_orders.orders.keys().map(|k| k.as_ref()).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn has_active_orders(&self) -> bool {
+        self.storage.chats().into_iter().any(|chat| {
+            self.storage
+                .get(chat)
+                .is_some_and(|conversation_orders| !conversation_orders.orders.is_empty())
+        })
+    }
+}
+
+/// Shows an ended order and who has yet to pay for it, with a button to record payment while anyone still owes money
+fn payment_result(ended_order: &ArchivedOrder) -> CommandResult {
+    let response = format!("{}\n\n{}", ended_order.order, ended_order.payment_status());
+    if ended_order.unpaid().is_empty() {
+        return CommandResult::success(response);
+    }
+    let mut reply_markup = InlineKeyboardMarkup::new();
+    reply_markup.add_row(vec![InlineKeyboardButton::callback(
+        "I paid",
+        CallbackAction::MarkPaid(ended_order.id).to_data(),
+    )]);
+    CommandResult {
+        success: true,
+        response: format!("{}\nTap I paid or use /paid once you have paid.", response),
+        reply_markup: Some(reply_markup),
+    }
+}
+
+/// Shows an active order with buttons to order its items, such as in place of an option wizard
+fn order_result(order: &Order) -> CommandResult {
+    CommandResult {
+        success: true,
+        response: format!(
+            "{}\nUse /order <item> to order, and /end when done.\nYou can also tap on an existing item to update or cancel your order.",
+            order
+        ),
+        reply_markup: Some(order.generate_reply_markup()),
+    }
+}
+
+/// Explains that the options of a tapped item are no longer being chosen, such as after they were chosen
+fn no_option_wizard() -> (CommandResult, String) {
+    let msg =
+        "These options are no longer being chosen. Tap on the item again to order it.".to_string();
+    (CommandResult::failure(msg.clone()), msg)
+}
+
+/// Ensures that an order name fits in the callback data of its inline keyboard buttons
+fn check_order_name(order_name: &str) -> Result<(), CommandResult> {
+    if order_name.len() > 30 {
+        return Err(CommandResult::failure(
+            "Order names must not exceed 30 characters.".to_string(),
+        ));
+    }
+    // ':' separates the action from its arguments in callback data
+    if order_name.contains(':') {
+        return Err(CommandResult::failure(
+            "Order names must not contain ':'.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Explains that a guest can't manage an order, since they can't send commands
+fn guest_cannot_manage(guest: &User) -> CommandResult {
+    CommandResult::failure(format!(
+        "{} is a guest who isn't on Telegram, so they can't manage orders.",
+        guest.first_name
+    ))
+}
+
+/// Explains that a user named in a command couldn't be found
+fn unknown_user(name: &str) -> CommandResult {
+    CommandResult::failure(format!(
+        "{} hasn't ordered anything in this chat yet, so they can't be found. Use their username or first name.",
+        name
+    ))
+}
+
+/// Ensures that the item name isn't too long so that callback queries are <= 64 bytes, per Telegram limits
+/// The longest callback queries are in the form "inc:<order_name> <item>", so their lengths must not exceed 59
+fn check_item_name_length(order_name: &str, item: &str) -> Result<(), String> {
+    if order_name.len() + item.len() > 59 {
+        Err("The sum of the lengths of order and item names must not exceed 59 characters, per Telegram limits.".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{menu::Selection, test_utils::user};
+
+    fn chat() -> ChatId {
+        ChatId::new(-1)
+    }
+
+    #[test]
+    fn order_lifecycle() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        assert!(!bot.has_active_orders());
+
+        assert!(
+            bot.start_order(
+                chat(),
+                alice.clone(),
+                "waffles".into(),
+                StartOptions::default()
+            )
+            .success
+        );
+        assert!(
+            !bot.start_order(
+                chat(),
+                bob.clone(),
+                "waffles".into(),
+                StartOptions::default()
+            )
+            .success,
+            "orders with the same name can't be started twice"
+        );
+        assert_eq!(bot.get_active_order_names(chat()), vec!["waffles"]);
+
+        assert!(
+            bot.add_item(
+                chat(),
+                alice.clone(),
+                "waffles",
+                ItemRequest::new("chocolate")
+            )
+            .success
+        );
+        assert!(
+            bot.add_item(
+                chat(),
+                bob.clone(),
+                "waffles",
+                ItemRequest::new("chocolate")
+            )
+            .success
+        );
+        assert!(bot.remove_item(chat(), &bob, "waffles", None).success);
+        assert!(
+            !bot.remove_item(chat(), &bob, "waffles", None).success,
+            "Bob no longer has an item to cancel"
+        );
+
+        let res = bot.end_order(chat(), &alice, "waffles");
+        assert!(res.success);
+        assert_eq!(res.response, "1 orders for waffles:\n\n1 chocolate: Alice");
+        assert!(!bot.has_active_orders());
+    }
+
+    #[test]
+    fn callback_query_toggles_item() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(
+            chat(),
+            alice.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+
+        let (res, answer) =
+            bot.handle_callback_query(chat(), alice.clone(), "waffles chocolate", false);
+        assert!(res.success);
+        assert_eq!(answer, "Cancelled order of chocolate for waffles.");
+
+        let (res, answer) =
+            bot.handle_callback_query(chat(), alice.clone(), "waffles chocolate", false);
+        assert!(res.success);
+        assert_eq!(answer, "Updated order for waffles to chocolate.");
+    }
+
+    #[test]
+    fn ended_orders_are_archived() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        assert!(!bot.view_history(chat(), 5).success);
+
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(
+            chat(),
+            alice.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        bot.end_order(chat(), &alice, "waffles");
+
+        let res = bot.view_history(chat(), 5);
+        assert!(res.success);
+        assert!(res.response.contains("1. waffles by Alice"));
+
+        let (res, _) = bot.handle_callback_query(chat(), alice.clone(), "history:1", false);
+        assert!(res.success);
+        assert!(res
+            .response
+            .ends_with("1 orders for waffles:\n\n1 chocolate: Alice"));
+    }
+
+    #[test]
+    fn start_order_again_offers_previous_items() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        assert!(
+            !bot.start_order_again(
+                chat(),
+                alice.clone(),
+                "waffles".into(),
+                StartOptions::default()
+            )
+            .success
+        );
+
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(
+            chat(),
+            alice.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        bot.add_item(chat(), alice.clone(), "waffles", ItemRequest::new("plain"));
+        bot.end_order(chat(), &alice, "waffles");
+
+        let res = bot.start_order_again(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        assert!(res.success);
+        let order = &bot.storage.get(chat()).unwrap().orders["waffles"];
+        let mut items: Vec<&String> = order.items.keys().collect();
+        items.sort();
+        assert_eq!(items, vec!["chocolate", "plain"]);
+        assert!(order.participants().is_empty());
+    }
+
+    #[test]
+    fn prices_are_shown_when_order_ends() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        let request = ItemRequest {
+            quantity: 2,
+            price: Some(Money::from_cents(450)),
+            ..ItemRequest::new("chocolate")
+        };
+        assert!(
+            bot.add_item(chat(), alice.clone(), "waffles", request)
+                .success
+        );
+        assert!(
+            bot.set_price(chat(), &alice, "waffles", "plain", Money::from_cents(300))
+                .success
+        );
+        bot.handle_callback_query(chat(), bob.clone(), "waffles plain", false);
+
+        let res = bot.end_order(chat(), &alice, "waffles");
+        assert_eq!(
+            res.response,
+            "3 orders for waffles:\n\n1 plain @ 3.00 = 3.00: Bob\n2 chocolate @ 4.50 = 9.00: Alice x2\n\nAmounts owed:\nAlice: 9.00\nBob: 3.00\n\nTotal: 12.00\n\nStill to pay Alice:\nBob: 3.00\nTap I paid or use /paid once you have paid."
+        );
+    }
+
+    #[test]
+    fn only_the_creater_may_set_charges() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        let tip = Charge::Tip(Money::from_cents(200));
+        assert!(
+            !bot.set_charge(chat(), &bob, "waffles", tip).success,
+            "only the creater of the order may change charges"
+        );
+        let tip = Charge::Tip(Money::from_cents(200));
+        let res = bot.set_charge(chat(), &alice, "waffles", tip);
+        assert!(res.success);
+        assert!(res.response.starts_with("The tip is now 2.00."));
+
+        bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        let res = bot.end_order(chat(), &alice, "waffles");
+        assert!(res
+            .response
+            .contains("Amounts owed:\nBob: 2.00\n\nTotal: 2.00"));
+    }
+
+    #[test]
+    fn orders_are_archived_once_everyone_has_paid() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        let request = ItemRequest {
+            price: Some(Money::from_cents(450)),
+            ..ItemRequest::new("chocolate")
+        };
+        bot.add_item(chat(), alice.clone(), "waffles", request);
+        bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+
+        let res = bot.end_order(chat(), &alice, "waffles");
+        assert!(res.response.contains("Still to pay Alice:\nBob: 4.50"));
+        assert!(res.reply_markup.is_some());
+        assert!(
+            !bot.view_history(chat(), 5).success,
+            "orders are only archived once everyone has paid"
+        );
+        assert!(bot.view_unpaid(chat()).success);
+        assert!(
+            !bot.mark_paid(chat(), &alice, None).success,
+            "the creater of the order doesn't owe anything"
+        );
+
+        let (res, _) = bot.handle_callback_query(chat(), bob.clone(), "paid:1", false);
+        assert!(res.success);
+        assert!(res.response.ends_with("Everyone has paid Alice."));
+        assert!(res.reply_markup.is_none());
+        assert!(!bot.mark_paid(chat(), &bob, None).success);
+        assert!(!bot.view_unpaid(chat()).success);
+        assert!(bot.view_history(chat(), 5).success);
+    }
+
+    #[test]
+    fn only_owners_and_admins_end_orders() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "pizza".into(),
+            StartOptions::default(),
+        );
+        assert!(bot.needs_admin_to_end(chat(), &bob, "waffles"));
+        assert!(!bot.needs_admin_to_end(chat(), &alice, "waffles"));
+        assert!(!bot.needs_admin_to_end(chat(), &bob, "burgers"));
+
+        let res = bot.end_order(chat(), &bob, "waffles");
+        assert!(!res.success);
+        assert_eq!(
+            res.response,
+            "Only Alice, who started waffles, or an administrator of this chat may end it."
+        );
+        assert!(bot.end_order_as_admin(chat(), &bob, "waffles").success);
+        assert!(bot.end_order(chat(), &alice, "pizza").success);
+        assert!(bot.get_active_order_names(chat()).is_empty());
+    }
+
+    #[test]
+    fn co_owners_manage_orders() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = User {
+            username: Some("Bobby".into()),
+            ..user(2, "Bob")
+        };
+        let carol = user(3, "Carol");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("plain"));
+        assert_eq!(
+            bot.add_owner(chat(), &alice, "waffles", "carol").response,
+            "carol hasn't ordered anything in this chat yet, so they can't be found. Use their username or first name."
+        );
+        assert!(!bot.add_owner(chat(), &bob, "waffles", "@bobby").success);
+        assert_eq!(
+            bot.add_owner(chat(), &alice, "waffles", "@bobby").response,
+            "Bob may now manage waffles. It is managed by Alice or Bob."
+        );
+        assert_eq!(
+            bot.list_owners(chat(), "waffles").response,
+            "waffles is managed by Alice, with Bob as co-owners.\nUse /owners add <name> or /owners remove <name> to change who manages it, or /handover <name> to hand it over."
+        );
+        assert!(
+            bot.set_charge(chat(), &bob, "waffles", Charge::Tip(Money::from_cents(100)))
+                .success
+        );
+        assert!(!bot.remove_owner(chat(), &bob, "waffles", "alice").success);
+
+        bot.add_item(chat(), carol.clone(), "waffles", ItemRequest::new("plain"));
+        assert_eq!(
+            bot.set_charge(
+                chat(),
+                &carol,
+                "waffles",
+                Charge::Tip(Money::from_cents(100))
+            )
+            .response,
+            "Only Alice or Bob may change this for waffles."
+        );
+        assert_eq!(
+            bot.hand_over(chat(), &bob, "waffles", "alice").response,
+            "Alice already manages waffles."
+        );
+        bot.add_item(
+            chat(),
+            carol.clone(),
+            "waffles",
+            ItemRequest {
+                recipient: Some(Recipient::Guest("Dan".into())),
+                ..ItemRequest::new("plain")
+            },
+        );
+        assert_eq!(
+            bot.add_owner(chat(), &alice, "waffles", "dan").response,
+            "Dan is a guest who isn't on Telegram, so they can't manage orders."
+        );
+        assert_eq!(
+            bot.hand_over(chat(), &alice, "waffles", "dan").response,
+            bot.add_owner(chat(), &alice, "waffles", "dan").response
+        );
+        assert_eq!(
+            bot.hand_over(chat(), &alice, "waffles", "carol").response,
+            "Carol now owns waffles, and will be paid for it."
+        );
+        assert!(!bot.end_order(chat(), &alice, "waffles").success);
+        assert_eq!(
+            bot.remove_owner(chat(), &carol, "waffles", "bob").response,
+            "Bob no longer manages waffles."
+        );
+        assert!(!bot.end_order(chat(), &bob, "waffles").success);
+        assert!(bot.end_order(chat(), &carol, "waffles").success);
+    }
+
+    #[test]
+    fn locked_orders_refuse_changes() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("plain"));
+        assert!(
+            !bot.change_state(chat(), &bob, "waffles", OrderState::Locked)
+                .success
+        );
+        assert!(
+            bot.change_state(chat(), &alice, "waffles", OrderState::Locked)
+                .success
+        );
+
+        let refusal = "waffles is locked, so orders can't be changed. Ask Alice to /unlock it.";
+        let res = bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        assert_eq!(res.response, refusal);
+        assert_eq!(
+            bot.adjust_quantity(chat(), bob.clone(), "waffles", "plain", 1)
+                .response,
+            refusal
+        );
+        assert_eq!(
+            bot.remove_item(chat(), &bob, "waffles", None).response,
+            refusal
+        );
+        assert!(
+            bot.set_price(chat(), &alice, "waffles", "plain", Money::from_cents(300))
+                .success,
+            "prices may be set once the vendor has been called"
+        );
+
+        assert!(
+            bot.change_state(chat(), &alice, "waffles", OrderState::Placed)
+                .success
+        );
+        assert_eq!(
+            bot.change_state(chat(), &alice, "waffles", OrderState::Open)
+                .response,
+            "waffles is placed, so it can't be unlocked."
+        );
+        assert_eq!(
+            bot.change_state(chat(), &alice, "waffles", OrderState::Delivered).response,
+            "waffles has arrived!\n\n1 orders for waffles (delivered):\n\n1 plain @ 3.00 = 3.00: Bob\n\nAmounts owed:\nBob: 3.00\n\nTotal: 3.00\n\nUse /end waffles to end it."
+        );
+        assert!(bot.end_order(chat(), &alice, "waffles").success);
+    }
+
+    #[test]
+    fn ended_orders_can_be_reopened() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        let priced = ItemRequest {
+            price: Some(Money::from_cents(500)),
+            ..ItemRequest::new("chocolate")
+        };
+        bot.add_item(chat(), bob.clone(), "waffles", priced);
+        let res = bot.end_order(chat(), &alice, "waffles");
+        let id = bot
+            .storage
+            .get(chat())
+            .unwrap()
+            .last_ended_order_id(None)
+            .unwrap();
+        let undo = res.reply_markup.unwrap();
+        assert_eq!(
+            serde_json::to_value(&undo).unwrap()["inline_keyboard"][1][0]["callback_data"],
+            format!("reopen:{}", id)
+        );
+        assert!(bot.view_unpaid(chat()).success);
+
+        assert_eq!(
+            bot.reopen_order(chat(), &carol, None).response,
+            "Only Alice or whoever ended waffles may reopen it."
+        );
+        let (res, _) =
+            bot.handle_callback_query(chat(), alice.clone(), &format!("reopen:{}", id), false);
+        assert!(res
+            .response
+            .starts_with("Reopened waffles.\n\n1 orders for waffles:"));
+        assert_eq!(bot.get_active_order_names(chat()), vec!["waffles"]);
+        assert!(!bot.view_unpaid(chat()).success, "debts are cancelled");
+        assert!(!bot.view_balances(chat()).success);
+        assert_eq!(
+            bot.reopen_order(chat(), &alice, Some("pizza")).response,
+            "No ended order for pizza was found."
+        );
+
+        assert!(
+            bot.add_item(
+                chat(),
+                carol.clone(),
+                "waffles",
+                ItemRequest::new("chocolate")
+            )
+            .success
+        );
+        bot.end_order(chat(), &alice, "waffles");
+        let conversation_orders = bot.storage.get_mut(chat()).unwrap();
+        assert_ne!(
+            conversation_orders.last_ended_order_id(None),
+            Some(id),
+            "ids of reopened orders aren't reused"
+        );
+        conversation_orders.awaiting_payment[0].ended_at -= chrono::Duration::minutes(11);
+        assert_eq!(
+            bot.reopen_order(chat(), &alice, Some("waffles")).response,
+            "waffles ended more than 10 minutes ago, so it can't be reopened. Use /start again waffles to start a new order with the same items."
+        );
+    }
+
+    #[test]
+    fn changes_are_undone() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        bot.handle_callback_query(chat(), bob.clone(), "waffles plain", false);
+        bot.set_price(chat(), &alice, "waffles", "plain", Money::from_cents(300));
+
+        let res = bot.undo(chat(), &bob);
+        assert_eq!(
+            res.response,
+            "Undid ordering plain for waffles.\n\n1 orders for waffles:\n\n1 chocolate: Bob"
+        );
+        assert!(res.reply_markup.is_some());
+        let res = bot.undo(chat(), &alice);
+        assert_eq!(
+            res.response,
+            "Undid setting the price of plain for waffles.\n\n1 orders for waffles:\n\n1 chocolate: Bob"
+        );
+        assert_eq!(
+            serde_json::to_value(res.reply_markup.unwrap()).unwrap()["inline_keyboard"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1,
+            "items which nobody ordered are no longer offered once their price is removed"
+        );
+        assert_eq!(
+            bot.undo(chat(), &alice).response,
+            "Others have ordered from waffles since you started it, so use /end waffles instead."
+        );
+        assert_eq!(
+            bot.undo(chat(), &alice).response,
+            "You have nothing to undo."
+        );
+
+        bot.end_order(chat(), &alice, "waffles");
+        assert!(bot
+            .undo(chat(), &alice)
+            .response
+            .starts_with("Undid ending waffles.\n\n1 orders for waffles:"));
+        assert_eq!(bot.get_active_order_names(chat()), vec!["waffles"]);
+
+        assert_eq!(
+            bot.undo(chat(), &bob).response,
+            "Undid ordering chocolate for waffles.\n\nOrders for waffles:\n\nNone"
+        );
+        assert_eq!(bot.undo(chat(), &bob).response, "You have nothing to undo.");
+
+        bot.start_order(chat(), bob.clone(), "pizza".into(), StartOptions::default());
+        assert_eq!(bot.undo(chat(), &bob).response, "Undid starting pizza.");
+        assert_eq!(bot.get_active_order_names(chat()), vec!["waffles"]);
+    }
+
+    #[test]
+    fn options_are_chosen_when_tapping_items() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        let option_group = |name: &str, choices: &[&str], multiple| OptionGroup {
+            name: name.into(),
+            choices: choices.iter().map(|choice| choice.to_string()).collect(),
+            multiple,
+        };
+        let options = StartOptions {
+            menu: vec![MenuItem {
+                options: vec![
+                    option_group("size", &["s", "m", "l"], false),
+                    option_group("toppings", &["pearls", "jelly"], true),
+                ],
+                ..MenuItem::new("thai tea".into(), None)
+            }],
+            ..StartOptions::default()
+        };
+        bot.start_order(chat(), alice.clone(), "bubble-tea".into(), options);
+        let mut tap = |user: &User, data: &str| {
+            let (res, answer) = bot.handle_callback_query(chat(), user.clone(), data, false);
+            (res.response, answer)
+        };
+
+        assert_eq!(
+            tap(&bob, "bubble-tea thai tea"),
+            (
+                "Bob, choose the size of your thai tea from bubble-tea.".to_string(),
+                "Choose the options of your thai tea.".to_string()
+            )
+        );
+        assert_eq!(
+            tap(&alice, "option:1 1").1,
+            "Bob is choosing these options. Tap on an item to order it yourself."
+        );
+        assert_eq!(tap(&bob, "option:1 1").1, "Chose m.");
+        assert_eq!(tap(&bob, "option:1 0").1, "Added pearls.");
+        let (response, answer) = tap(&bob, "option:1 done");
+        assert_eq!(
+            answer,
+            "Updated order for bubble-tea to thai tea with size m, toppings pearls."
+        );
+        assert!(response.starts_with(
+            "1 orders for bubble-tea:\n\n1 thai tea: Bob\n  1 with size m, toppings pearls: Bob\n"
+        ));
+        assert_eq!(
+            tap(&bob, "option:1 cancel").1,
+            "These options are no longer being chosen. Tap on the item again to order it."
+        );
+
+        tap(&alice, "bubble-tea thai tea");
+        tap(&alice, "option:2 1");
+        tap(&alice, "option:2 0");
+        tap(&alice, "option:2 done");
+        tap(&carol, "bubble-tea thai tea");
+        tap(&carol, "option:3 2");
+        let (response, _) = tap(&carol, "option:3 done");
+        assert!(
+            response.starts_with("3 orders for bubble-tea:\n\n3 thai tea: Alice, Bob, Carol\n  1 with size l: Carol\n  2 with size m, toppings pearls: Alice, Bob\n"),
+            "identical choices are counted together: {}",
+            response
+        );
+
+        assert_eq!(
+            tap(&bob, "inc:bubble-tea thai tea").1,
+            "Added one thai tea to your order for bubble-tea.",
+            "adding one more keeps the options already chosen"
+        );
+        assert!(bot
+            .undo(chat(), &bob)
+            .response
+            .contains("3 thai tea: Alice, Bob, Carol\n  1 with size l: Carol\n  2 with size m, toppings pearls: Alice, Bob"),
+            "options are kept when changes are undone"
+        );
+        let large = ItemRequest {
+            options: vec![Selection {
+                group: "size".into(),
+                choices: vec!["l".into()],
+            }],
+            ..ItemRequest::new("thai tea")
+        };
+        assert_eq!(
+            bot.add_item(chat(), bob.clone(), "bubble-tea", large).response,
+            "Bob already ordered thai tea with size m, toppings pearls, and only one choice of options may be ordered for each item. Cancel it first to order it with size l instead."
+        );
+        let two = ItemRequest {
+            quantity: 2,
+            ..ItemRequest::new("thai tea")
+        };
+        let res = bot.add_item(chat(), bob.clone(), "bubble-tea", two);
+        assert!(
+            res.response.contains("4 thai tea: Alice, Bob x2, Carol\n  1 with size l: Carol\n  3 with size m, toppings pearls: Alice, Bob"),
+            "ordering an item again without options keeps those chosen: {}",
+            res.response
+        );
+
+        let mut tap = |user: &User, data: &str| {
+            let (res, answer) = bot.handle_callback_query(chat(), user.clone(), data, false);
+            (res.response, answer)
+        };
+        assert!(
+            tap(&carol, "bubble-tea thai tea")
+                .0
+                .starts_with("3 orders for bubble-tea:"),
+            "tapping an item which was ordered cancels it, without choosing options"
+        );
+        tap(&carol, "bubble-tea thai tea");
+        let (response, answer) = tap(&carol, "option:4 cancel");
+        assert_eq!(answer, "Cancelled ordering thai tea.");
+        assert!(response.starts_with("3 orders for bubble-tea:"));
+
+        assert_eq!(
+            tap(&carol, "inc:bubble-tea thai tea").0,
+            "Carol, choose the size of your thai tea from bubble-tea.",
+            "tapping + on an item with options which wasn't ordered chooses its options first"
+        );
+        tap(&carol, "option:5 0");
+        bot.change_state(chat(), &alice, "bubble-tea", OrderState::Locked);
+        let mut tap = |user: &User, data: &str| {
+            let (res, answer) = bot.handle_callback_query(chat(), user.clone(), data, false);
+            (
+                res.success,
+                res.response,
+                res.reply_markup.is_some(),
+                answer,
+            )
+        };
+        let (success, response, has_buttons, answer) = tap(&carol, "option:5 done");
+        assert_eq!(
+            answer,
+            "bubble-tea is locked, so orders can't be changed. Ask Alice to /unlock it."
+        );
+        assert!(
+            success && has_buttons,
+            "the order's buttons are shown again"
+        );
+        assert!(
+            response.starts_with("bubble-tea is locked, so orders can't be changed. Ask Alice to /unlock it.\n\n3 orders for bubble-tea (locked):"),
+            "why the item couldn't be ordered is shown above the order: {}",
+            response
+        );
+
+        bot.change_state(chat(), &alice, "bubble-tea", OrderState::Open);
+        bot.handle_callback_query(chat(), carol.clone(), "bubble-tea thai tea", false);
+        bot.handle_callback_query(chat(), carol.clone(), "option:6 0", false);
+        bot.end_order(chat(), &alice, "bubble-tea");
+        let mut tap = |user: &User, data: &str| {
+            let (res, _) = bot.handle_callback_query(chat(), user.clone(), data, false);
+            (res.success, res.response, res.reply_markup.is_some())
+        };
+        assert_eq!(
+            tap(&carol, "option:6 done"),
+            (true, "Order bubble-tea not found.".to_string(), false),
+            "the buttons are replaced with why the item couldn't be ordered once the order has ended"
+        );
+    }
+
+    #[test]
+    fn items_are_ordered_for_others() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        let for_recipient = |recipient, item: &str| ItemRequest {
+            recipient: Some(recipient),
+            ..ItemRequest::new(item)
+        };
+        assert_eq!(
+            bot.add_item(
+                chat(),
+                bob.clone(),
+                "waffles",
+                for_recipient(Recipient::Mention("carol".into()), "plain"),
+            )
+            .response,
+            "carol hasn't ordered anything in this chat yet, so they can't be found. Use their username or first name, or put a guest's name in quotes. For example, /order for \"Bob (guest)\" waffle"
+        );
+        bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("plain"));
+        bot.add_item(
+            chat(),
+            alice.clone(),
+            "waffles",
+            for_recipient(Recipient::Mention("bob".into()), "chocolate"),
+        );
+        bot.add_item(
+            chat(),
+            alice.clone(),
+            "waffles",
+            for_recipient(Recipient::Guest("Dan (guest)".into()), "plain"),
+        );
+        let res = bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            for_recipient(Recipient::Guest("dan (GUEST)".into()), "chocolate"),
+        );
+        assert!(
+            res.response.starts_with(
+                "2 orders for waffles:\n\n2 chocolate: Bob (added by Alice), Dan (guest) (added by Bob)"
+            ),
+            "guests are told apart by name, whatever its case: {}",
+            res.response
+        );
+
+        assert_eq!(
+            bot.undo(chat(), &bob).response,
+            "Undid ordering chocolate for Dan (guest) in waffles.\n\n2 orders for waffles:\n\n1 chocolate: Bob (added by Alice)\n1 plain: Dan (guest) (added by Alice)"
+        );
+        assert_eq!(
+            bot.undo(chat(), &alice).response,
+            "Undid ordering plain for Dan (guest) in waffles.\n\n1 orders for waffles:\n\n1 chocolate: Bob (added by Alice)"
+        );
+        assert_eq!(
+            bot.undo(chat(), &alice).response,
+            "Undid ordering chocolate for Bob in waffles.\n\n1 orders for waffles:\n\n1 plain: Bob"
+        );
+    }
+
+    #[test]
+    fn debts_are_settled_across_orders() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        let priced = |item: &str, cents| ItemRequest {
+            price: Some(Money::from_cents(cents)),
+            ..ItemRequest::new(item)
+        };
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(chat(), bob.clone(), "waffles", priced("chocolate", 500));
+        bot.end_order(chat(), &alice, "waffles");
+        bot.start_order(chat(), bob.clone(), "pizza".into(), StartOptions::default());
+        bot.add_item(chat(), alice.clone(), "pizza", priced("hawaiian", 300));
+        bot.add_item(chat(), carol.clone(), "pizza", priced("margherita", 200));
+        bot.end_order(chat(), &bob, "pizza");
+
+        assert_eq!(
+            bot.view_balances(chat()).response,
+            "Balances:\nAlice is owed 2.00\nCarol owes 2.00\n\nUse /settle to see who should pay whom."
+        );
+        let res = bot.settle_up(chat());
+        assert!(res.response.contains("Carol pays Alice 2.00"));
+
+        let data =
+            CallbackAction::RecordTransfer(carol.id, alice.id, Money::from_cents(200)).to_data();
+        let (res, _) = bot.handle_callback_query(chat(), bob.clone(), &data, false);
+        assert!(!res.success, "only Carol or Alice may record this payment");
+        let (res, _) = bot.handle_callback_query(chat(), carol.clone(), &data, false);
+        assert!(res.success);
+        assert!(res.response.ends_with("Everyone is settled up."));
+        assert!(
+            !bot.view_unpaid(chat()).success,
+            "settled debts count as paid"
+        );
+    }
+
+    #[test]
+    fn debts_are_only_settled_by_payments() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let priced = |item: &str, cents| ItemRequest {
+            price: Some(Money::from_cents(cents)),
+            ..ItemRequest::new(item)
+        };
+        bot.start_order(
+            chat(),
+            bob.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(chat(), alice.clone(), "waffles", priced("chocolate", 1000));
+        bot.end_order(chat(), &bob, "waffles");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "pizza".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(chat(), bob.clone(), "pizza", priced("hawaiian", 500));
+        let res = bot.end_order(chat(), &alice, "pizza");
+        assert!(
+            res.response.contains("Still to pay Alice:\nBob: 5.00"),
+            "Bob hasn't paid, even though Alice owes him more: {}",
+            res.response
+        );
+
+        let res = bot.settle_up(chat());
+        assert!(res.response.contains("Alice pays Bob 5.00"));
+        let data =
+            CallbackAction::RecordTransfer(alice.id, bob.id, Money::from_cents(300)).to_data();
+        bot.handle_callback_query(chat(), alice.clone(), &data, false);
+        let unpaid = bot.view_unpaid(chat()).response;
+        assert!(
+            unpaid.contains("Bob: 5.00") && unpaid.contains("Alice: 10.00"),
+            "partial payments don't settle either order: {}",
+            unpaid
+        );
+        let data =
+            CallbackAction::RecordTransfer(alice.id, bob.id, Money::from_cents(200)).to_data();
+        bot.handle_callback_query(chat(), alice.clone(), &data, false);
+        assert!(
+            !bot.view_unpaid(chat()).success,
+            "both orders are settled once Alice has paid the difference"
+        );
+    }
+
+    #[test]
+    fn menus_are_offered_from_the_start() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let options = StartOptions {
+            menu: vec![MenuItem::new(
+                "chocolate".into(),
+                Some(Money::from_cents(450)),
+            )],
+            ..StartOptions::default()
+        };
+        let res = bot.start_order(chat(), alice.clone(), "waffles".into(), options);
+        assert!(res.success);
+        assert!(res.reply_markup.is_some());
+
+        let (res, _) = bot.handle_callback_query(chat(), alice.clone(), "waffles chocolate", false);
+        assert!(res
+            .response
+            .starts_with("1 orders for waffles:\n\n1 chocolate @ 4.50 = 4.50: Alice"));
+    }
+
+    #[test]
+    fn saved_menus_are_offered() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        assert!(!bot.list_menus(chat()).success);
+        let items = vec![MenuItem::new(
+            "chocolate".into(),
+            Some(Money::from_cents(450)),
+        )];
+        assert!(bot.save_menu(chat(), "waffles-house".into(), items).success);
+        assert_eq!(
+            bot.show_menu(chat(), "waffles-house").response,
+            "Menu waffles-house:\nchocolate 4.50"
+        );
+
+        let options = StartOptions {
+            saved_menu: Some("pancake-place".into()),
+            ..StartOptions::default()
+        };
+        assert!(
+            !bot.start_order(chat(), alice.clone(), "waffles".into(), options)
+                .success
+        );
+        let options = StartOptions {
+            saved_menu: Some("waffles-house".into()),
+            ..StartOptions::default()
+        };
+        assert!(
+            bot.start_order(chat(), alice.clone(), "waffles".into(), options)
+                .success
+        );
+        let res = bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles-house".into(),
+            StartOptions::default(),
+        );
+        assert!(
+            res.reply_markup.is_some(),
+            "menus with the same name as the order are offered"
+        );
+        let order = &bot.storage.get(chat()).unwrap().orders["waffles"];
+        assert_eq!(order.prices["chocolate"], Money::from_cents(450));
+
+        assert!(bot.delete_menu(chat(), "waffles-house").success);
+        assert!(!bot.show_menu(chat(), "waffles-house").success);
+    }
+
+    #[test]
+    fn orders_can_be_limited_to_their_menu() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let options = StartOptions {
+            menu_only: true,
+            ..StartOptions::default()
+        };
+        assert!(
+            !bot.start_order(chat(), alice.clone(), "waffles".into(), options)
+                .success,
+            "orders without a menu can't be limited to it"
+        );
+        let options = StartOptions {
+            menu: vec![MenuItem::new("chocolate".into(), None)],
+            ..StartOptions::default()
+        };
+        bot.start_order(chat(), alice.clone(), "waffles".into(), options);
+        assert!(
+            !bot.toggle_menu_only(chat(), &bob, "waffles").success,
+            "only the creater of the order may change this"
+        );
+        assert!(bot.toggle_menu_only(chat(), &alice, "waffles").success);
+
+        let res = bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("choclate"));
+        assert_eq!(
+            res.response,
+            "choclate is not on the menu for waffles. Did you mean chocolate?"
+        );
+        assert!(
+            !bot.set_price(chat(), &alice, "waffles", "plain", Money::from_cents(300))
+                .success
+        );
+        assert!(
+            bot.add_item(
+                chat(),
+                bob.clone(),
+                "waffles",
+                ItemRequest::new("chocolate")
+            )
+            .success
+        );
+        let order = &bot.storage.get(chat()).unwrap().orders["waffles"];
+        assert_eq!(order.items.len(), 1);
+
+        assert!(bot.toggle_menu_only(chat(), &alice, "waffles").success);
+        assert!(
+            bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("plain"))
+                .success
+        );
+    }
+
+    #[test]
+    fn orders_end_at_their_deadline() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let options = StartOptions {
+            deadline: NaiveTime::from_hms_opt(11, 30, 0),
+            ..StartOptions::default()
+        };
+        let res = bot.start_order(chat(), alice.clone(), "waffles".into(), options);
+        assert!(res.response.contains("The order ends automatically at"));
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "pizza".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        assert!(
+            !bot.set_deadline(chat(), &bob, "pizza", NaiveTime::from_hms_opt(12, 0, 0))
+                .success,
+            "only the creater of the order may change its deadline"
+        );
+        let deadline = bot.storage.get(chat()).unwrap().orders["waffles"]
+            .deadline
+            .unwrap();
+
+        assert!(bot
+            .end_overdue_orders(deadline - chrono::Duration::seconds(1))
+            .is_empty());
+        let ended = bot.end_overdue_orders(deadline);
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].0, chat());
+        assert_eq!(
+            ended[0].1.response,
+            "The deadline for waffles has passed, so it has ended.\n\n1 orders for waffles:\n\n1 chocolate: Bob"
+        );
+        assert_eq!(bot.get_active_order_names(chat()), vec!["pizza"]);
+        assert!(bot.end_overdue_orders(deadline).is_empty());
+    }
+
+    #[test]
+    fn reminders_invite_recent_participants() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        bot.start_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            StartOptions::default(),
+        );
+        bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("plain"));
+        bot.add_item(chat(), carol.clone(), "waffles", ItemRequest::new("plain"));
+        bot.end_order(chat(), &alice, "waffles");
+
+        let options = StartOptions {
+            deadline: NaiveTime::from_hms_opt(12, 0, 0),
+            ..StartOptions::default()
+        };
+        bot.start_order(chat(), alice.clone(), "lunch".into(), options);
+        bot.add_item(chat(), bob.clone(), "lunch", ItemRequest::new("noodles"));
+        assert!(
+            !bot.set_reminders(chat(), &bob, "lunch", vec![10]).success,
+            "only the creater of the order may change its reminders"
+        );
+        assert_eq!(
+            bot.set_reminders(chat(), &alice, "lunch", vec![30, 10])
+                .response,
+            "Reminders for lunch will be posted 30 and 10 minutes before it ends."
+        );
+        let deadline = bot.storage.get(chat()).unwrap().orders["lunch"]
+            .deadline
+            .unwrap();
+
+        assert!(bot
+            .post_reminders(deadline - chrono::Duration::minutes(31))
+            .is_empty());
+        let reminders = bot.run_timers(deadline - chrono::Duration::minutes(10));
+        assert_eq!(reminders.len(), 1, "only the latest due reminder is posted");
+        assert_eq!(
+            reminders[0].1.response,
+            format!(
+                "lunch ends in 10 minutes, at {}.\n\n1 orders for lunch:\n\n1 noodles: Bob\n\nCarol, you haven't ordered yet. Tap on an item or use /order <item> to order.",
+                format_time(&deadline)
+            )
+        );
+        assert!(bot
+            .post_reminders(deadline - chrono::Duration::minutes(5))
+            .is_empty());
+    }
+
+    #[test]
+    fn scheduled_orders_are_started() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let every_day = Recurrence::parse("daily", "10:00").unwrap();
+        let options = StartOptions {
+            saved_menu: Some("waffles-house".into()),
+            ..StartOptions::default()
+        };
+        assert!(
+            !bot.schedule_order(
+                chat(),
+                alice.clone(),
+                "waffles".into(),
+                every_day.clone(),
+                options.clone()
+            )
+            .success,
+            "saved menus must exist"
+        );
+        bot.save_menu(
+            chat(),
+            "waffles-house".into(),
+            vec![MenuItem::new("chocolate".into(), None)],
+        );
+        let res = bot.schedule_order(
+            chat(),
+            alice.clone(),
+            "waffles".into(),
+            every_day.clone(),
+            options,
+        );
+        assert!(res
+            .response
+            .starts_with("Scheduled waffles every day at 10:00 with menu waffles-house. Next on "));
+        bot.schedule_order(
+            chat(),
+            alice.clone(),
+            "lunch".into(),
+            Recurrence::parse("mon", "12:00").unwrap(),
+            StartOptions::default(),
+        );
+        let res = bot.list_schedules(chat());
+        assert!(res.response.contains("\n1. waffles every day at 10:00"));
+        assert!(res.response.contains("\n2. lunch every Mon at 12:00"));
+
+        let start = bot.storage.get(chat()).unwrap().schedules[0].next_start;
+        assert!(bot
+            .start_scheduled_orders(start - chrono::Duration::seconds(1))
+            .is_empty());
+        let started = bot.run_timers(start);
+        assert_eq!(started.len(), 1);
+        assert!(started[0]
+            .1
+            .response
+            .starts_with("Order started for waffles."));
+        assert!(
+            started[0].1.reply_markup.is_some(),
+            "the saved menu is offered"
+        );
+        assert_eq!(bot.get_active_order_names(chat()), vec!["waffles"]);
+        assert!(bot.start_scheduled_orders(start).is_empty());
+
+        let res = bot.delete_schedule(chat(), &alice, 2);
+        assert!(res
+            .response
+            .starts_with("Deleted scheduled order 2. lunch.\n\nScheduled orders:\n\n1. waffles"));
+        bot.schedule_order(
+            chat(),
+            alice.clone(),
+            "lunch".into(),
+            Recurrence::parse("tue", "12:00").unwrap(),
+            StartOptions::default(),
+        );
+        assert!(
+            bot.list_schedules(chat())
+                .response
+                .contains("\n3. lunch every Tue at 12:00"),
+            "numbers of deleted schedules aren't reused"
+        );
+        assert_eq!(
+            bot.delete_schedule(chat(), &user(2, "Bob"), 3).response,
+            "Only Alice, who scheduled lunch, may delete it."
+        );
+        assert!(bot.delete_schedule(chat(), &alice, 3).success);
+        assert!(bot.delete_schedule(chat(), &alice, 1).success);
+        assert!(!bot.list_schedules(chat()).success);
+        assert!(!bot.delete_schedule(chat(), &alice, 1).success);
+    }
+
+    #[test]
+    fn menus_are_imported_from_files() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let res = bot.import_menu(
+            chat(),
+            "thai-place".into(),
+            "menu.csv",
+            b"name,price\npad thai,8.50\nthai tea,three",
+        );
+        assert_eq!(
+            res.response,
+            "Menu thai-place was not saved, since menu.csv has errors:\nRow 3: three is not a valid amount. For example, use 4.50"
+        );
+        assert!(!bot.list_menus(chat()).success);
+
+        let res = bot.import_menu(
+            chat(),
+            "thai-place".into(),
+            "menu.csv",
+            b"name,price,category\npad thai,8.50,mains\nthai tea,3,drinks",
+        );
+        assert!(res.success);
+        assert_eq!(
+            bot.show_menu(chat(), "thai-place").response,
+            "Menu thai-place:\nmains:\npad thai 8.50\n\ndrinks:\nthai tea 3.00"
+        );
+    }
+
+    #[test]
+    fn multiple_items_are_toggled_individually() {
+        let mut bot = Bot::new(MemoryStorage::default());
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let options = StartOptions {
+            allow_multiple_items: true,
+            ..StartOptions::default()
+        };
+        bot.start_order(chat(), alice.clone(), "waffles".into(), options);
+        bot.add_item(
+            chat(),
+            bob.clone(),
+            "waffles",
+            ItemRequest::new("chocolate"),
+        );
+        bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("coffee"));
+        assert!(
+            !bot.remove_item(chat(), &bob, "waffles", None).success,
+            "the item to cancel must be specified when there are several"
+        );
+
+        let (res, _) = bot.handle_callback_query(chat(), bob.clone(), "waffles coffee", false);
+        assert!(res.success);
+        assert!(res
+            .response
+            .starts_with("1 orders for waffles:\n\n1 chocolate: Bob"));
+
+        assert!(
+            !bot.toggle_multiple_items(chat(), &bob, "waffles").success,
+            "only the creater of the order may change this"
+        );
+        assert!(bot.toggle_multiple_items(chat(), &alice, "waffles").success);
+        bot.add_item(chat(), bob.clone(), "waffles", ItemRequest::new("coffee"));
+        assert!(bot.remove_item(chat(), &bob, "waffles", None).success);
     }
 }
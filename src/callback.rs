@@ -0,0 +1,172 @@
+use telegram_bot::types::UserId;
+
+use crate::money::Money;
+
+/// Actions triggered by tapping on inline keyboard buttons
+/// Telegram limits callback data to 64 bytes, so actions are encoded as compactly as possible
+#[derive(Debug, Eq, PartialEq)]
+pub enum CallbackAction {
+    /// orders an item, or cancels it if the user already ordered it
+    ToggleItem(String, String),
+    /// orders one more of an item
+    IncrementItem(String, String),
+    /// orders one less of an item, cancelling it when none are left
+    DecrementItem(String, String),
+    /// shows the summary of an archived order
+    ShowArchivedOrder(u64),
+    /// records that the user has paid for an ended order
+    MarkPaid(u64),
+    /// records that a user paid another an amount, as suggested by /settle
+    RecordTransfer(UserId, UserId, Money),
+    /// stops starting a scheduled order
+    DeleteSchedule(u64),
+    /// reopens an order which was just ended
+    ReopenOrder(u64),
+    /// makes a choice for an item's option group, by the id of the wizard and the index of the choice
+    ChooseOption(u64, usize),
+    /// finishes choosing for an option group which allows multiple choices
+    FinishOptionGroup(u64),
+    /// stops choosing an item's options without ordering it
+    CancelOptions(u64),
+}
+
+impl CallbackAction {
+    /// Encodes this action as callback data, which parse_callback_data can decode
+    pub fn to_data(&self) -> String {
+        use CallbackAction::*;
+        match self {
+            // this predates other actions, so it has no prefix to remain compatible with existing buttons
+            ToggleItem(order_name, item) => format!("{} {}", order_name, item),
+            IncrementItem(order_name, item) => format!("inc:{} {}", order_name, item),
+            DecrementItem(order_name, item) => format!("dec:{} {}", order_name, item),
+            ShowArchivedOrder(id) => format!("history:{}", id),
+            MarkPaid(id) => format!("paid:{}", id),
+            RecordTransfer(from, to, amount) => {
+                format!("settle:{} {} {}", from, to, amount.cents())
+            }
+            DeleteSchedule(id) => format!("unschedule:{}", id),
+            ReopenOrder(id) => format!("reopen:{}", id),
+            ChooseOption(id, index) => format!("option:{} {}", id, index),
+            FinishOptionGroup(id) => format!("option:{} done", id),
+            CancelOptions(id) => format!("option:{} cancel", id),
+        }
+    }
+}
+
+/// Decodes callback data created by CallbackAction::to_data
+/// Order names can't contain ':', so prefixed actions are never confused with items being toggled
+pub fn parse_callback_data(data: &str) -> Option<CallbackAction> {
+    use CallbackAction::*;
+    let normalized_data = data.to_lowercase().trim().replace("@food_ordering_bot", "");
+    let first_word = normalized_data.split(' ').next().unwrap_or("");
+    if let Some(sep) = first_word.find(':') {
+        let action = &normalized_data[..sep];
+        let args = &normalized_data[sep + 1..];
+        match action {
+            "inc" => split_order_and_item(args).map(|(order, item)| IncrementItem(order, item)),
+            "dec" => split_order_and_item(args).map(|(order, item)| DecrementItem(order, item)),
+            "history" => args.parse().ok().map(ShowArchivedOrder),
+            "paid" => args.parse().ok().map(MarkPaid),
+            "settle" => parse_transfer(args),
+            "unschedule" => args.parse().ok().map(DeleteSchedule),
+            "reopen" => args.parse().ok().map(ReopenOrder),
+            "option" => parse_option_choice(args),
+            _ => None,
+        }
+    } else {
+        split_order_and_item(&normalized_data).map(|(order, item)| ToggleItem(order, item))
+    }
+}
+
+/// Parses "<from user id> <to user id> <amount in cents>"
+fn parse_transfer(args: &str) -> Option<CallbackAction> {
+    let numbers: Vec<i64> = args
+        .split(' ')
+        .map(|arg| arg.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers.as_slice() {
+        &[from, to, cents] => Some(CallbackAction::RecordTransfer(
+            UserId::new(from),
+            UserId::new(to),
+            Money::from_cents(cents),
+        )),
+        _ => None,
+    }
+}
+
+/// Parses "<wizard id> <choice index>", "<wizard id> done" or "<wizard id> cancel"
+fn parse_option_choice(args: &str) -> Option<CallbackAction> {
+    let (id, choice) = args.split_once(' ')?;
+    let id = id.parse().ok()?;
+    match choice {
+        "done" => Some(CallbackAction::FinishOptionGroup(id)),
+        "cancel" => Some(CallbackAction::CancelOptions(id)),
+        index => index
+            .parse()
+            .ok()
+            .map(|index| CallbackAction::ChooseOption(id, index)),
+    }
+}
+
+/// Splits "<order_name> <item>" into its parts
+fn split_order_and_item(args: &str) -> Option<(String, String)> {
+    let sep = args.find(' ')?;
+    Some((args[..sep].to_string(), args[sep + 1..].to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CallbackAction::*;
+
+    #[test]
+    fn parse_toggle_item() {
+        assert_eq!(
+            parse_callback_data("waffles large chocolate"),
+            Some(ToggleItem("waffles".into(), "large chocolate".into()))
+        );
+        assert_eq!(parse_callback_data("waffles"), None);
+    }
+
+    #[test]
+    fn parse_quantity_changes() {
+        assert_eq!(
+            parse_callback_data("inc:waffles large chocolate"),
+            Some(IncrementItem("waffles".into(), "large chocolate".into()))
+        );
+        assert_eq!(
+            parse_callback_data("dec:waffles chocolate"),
+            Some(DecrementItem("waffles".into(), "chocolate".into()))
+        );
+        assert_eq!(parse_callback_data("inc:waffles"), None);
+    }
+
+    #[test]
+    fn parse_show_archived_order() {
+        assert_eq!(
+            parse_callback_data("history:12"),
+            Some(ShowArchivedOrder(12))
+        );
+        assert_eq!(parse_callback_data("history:abc"), None);
+        assert_eq!(parse_callback_data("unknown:12"), None);
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        for action in [
+            ToggleItem("ice-cream".into(), "chocolate cone".into()),
+            IncrementItem("ice-cream".into(), "chocolate cone".into()),
+            DecrementItem("ice-cream".into(), "chocolate cone".into()),
+            ShowArchivedOrder(3),
+            MarkPaid(4),
+            RecordTransfer(UserId::new(1), UserId::new(-2), Money::from_cents(450)),
+            DeleteSchedule(5),
+            ReopenOrder(6),
+            ChooseOption(7, 2),
+            FinishOptionGroup(8),
+            CancelOptions(9),
+        ] {
+            assert_eq!(parse_callback_data(&action.to_data()), Some(action));
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::money::{Money, Percentage};
+
+/// How extra charges are divided among the participants of an order
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SplitMethod {
+    /// everyone pays the same share
+    Evenly,
+    /// everyone pays in proportion to the price of what they ordered
+    #[default]
+    Proportionally,
+}
+
+impl FromStr for SplitMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "evenly" | "even" | "equally" => Ok(SplitMethod::Evenly),
+            "proportionally" | "proportional" => Ok(SplitMethod::Proportionally),
+            _ => Err(format!(
+                "{} is not a way of splitting charges. Use evenly or proportionally.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SplitMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitMethod::Evenly => write!(f, "evenly"),
+            SplitMethod::Proportionally => write!(f, "proportionally"),
+        }
+    }
+}
+
+/// Charges added on top of the price of the items in an order, which participants share
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Charges {
+    pub delivery_fee: Money,
+    /// service charge or tax, applied to the price of all items
+    pub tax: Percentage,
+    pub tip: Money,
+    pub split: SplitMethod,
+}
+
+/// A change to one of an order's charges
+#[derive(Debug, Eq, PartialEq)]
+pub enum Charge {
+    DeliveryFee(Money),
+    Tax(Percentage),
+    Tip(Money),
+    Split(SplitMethod),
+}
+
+impl fmt::Display for Charge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Charge::DeliveryFee(fee) => write!(f, "The delivery fee is now {}.", fee),
+            Charge::Tax(tax) => write!(f, "The service charge or tax is now {}.", tax),
+            Charge::Tip(tip) => write!(f, "The tip is now {}.", tip),
+            Charge::Split(split) => write!(f, "Extra charges are now split {}.", split),
+        }
+    }
+}
+
+impl Charges {
+    /// Returns whether there are no charges on top of the price of items
+    pub fn is_empty(&self) -> bool {
+        self.delivery_fee.is_zero() && self.tax.is_zero() && self.tip.is_zero()
+    }
+
+    pub fn apply(&mut self, charge: Charge) {
+        match charge {
+            Charge::DeliveryFee(fee) => self.delivery_fee = fee,
+            Charge::Tax(tax) => self.tax = tax,
+            Charge::Tip(tip) => self.tip = tip,
+            Charge::Split(split) => self.split = split,
+        }
+    }
+
+    /// Returns the sum of all charges for items costing `subtotal`
+    pub fn total(&self, subtotal: Money) -> Money {
+        self.delivery_fee + self.tax.of(subtotal) + self.tip
+    }
+
+    /// Divides the charges for items costing `subtotals` among the participants who ordered them
+    pub fn shares(&self, subtotals: &[Money]) -> Vec<Money> {
+        let total = self.total(subtotals.iter().cloned().sum());
+        let weights: Vec<i64> = match self.split {
+            SplitMethod::Evenly => vec![1; subtotals.len()],
+            SplitMethod::Proportionally => {
+                subtotals.iter().map(|subtotal| subtotal.cents()).collect()
+            }
+        };
+        total.split(&weights)
+    }
+
+    /// Returns a line describing each charge, for items costing `subtotal`
+    pub fn describe(&self, subtotal: Money) -> Vec<String> {
+        let mut lines = vec![];
+        if !self.delivery_fee.is_zero() {
+            lines.push(format!("Delivery fee: {}", self.delivery_fee));
+        }
+        if !self.tax.is_zero() {
+            lines.push(format!(
+                "Service charge/tax ({}): {}",
+                self.tax,
+                self.tax.of(subtotal)
+            ));
+        }
+        if !self.tip.is_zero() {
+            lines.push(format!("Tip: {}", self.tip));
+        }
+        lines
+    }
+}
@@ -1,20 +1,153 @@
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    charges::Charge,
+    deadline,
+    menu::{self, MenuItem, Selection},
+    money::Money,
+    order::{OrderState, MAX_QUANTITY},
+    schedule::Recurrence,
+};
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Command {
     /// starts a new order for this conversation
-    StartOrder(String),
+    StartOrder(String, StartOptions),
+    /// starts a new order offering the items of the last order with the same name
+    StartOrderAgain(String, StartOptions),
     /// ends an order
     EndOrder(String),
+    /// reopens a recently ended order, which may be specified
+    Reopen(Option<String>),
+    /// reverts the last change the user made
+    Undo,
     /// adds an item to the currently active order
-    AddItem(String, String),
-    /// Cancels the currently selected item
-    RemoveItem(String),
+    AddItem(String, ItemRequest),
+    /// Cancels the specified item, or the currently selected one if not specified
+    RemoveItem(String, Option<String>),
+    /// allows or disallows ordering several different items in an order
+    ToggleMultipleItems(String),
+    /// limits ordering to the items on an order's menu, or allows any item again
+    ToggleMenuOnly(String),
+    /// shows who manages an order
+    ListOwners(String),
+    /// lets another user, given by their username or first name, manage an order
+    AddOwner(String, String),
+    /// stops a co-owner, given by their username or first name, from managing an order
+    RemoveOwner(String, String),
+    /// hands over the user's management of an order to another user, given by their username or first name
+    HandOver(String, String),
+    /// locks or unlocks an order, or records that it was placed or has arrived
+    ChangeState(String, OrderState),
+    /// sets the price of an item in an order
+    SetPrice(String, String, Money),
+    /// sets a delivery fee, service charge or tax, tip or how they are split for an order
+    SetCharge(String, Charge),
+    /// sets the time at which an order is automatically ended, or removes it
+    SetDeadline(String, Option<NaiveTime>),
+    /// sets how many minutes before an order's deadline reminders are posted, with none stopping them
+    SetReminders(String, Vec<u32>),
+    /// records that the user has paid for an ended order, which may be specified
+    Paid(Option<String>),
+    /// shows who has yet to pay for ended orders
+    ViewUnpaid,
+    /// shows how much everyone owes or is owed across ended orders
+    ViewBalances,
+    /// suggests payments which settle everyone's balances
+    SettleUp,
+    /// saves a menu for this conversation under the given name, replacing any menu with that name
+    SaveMenu(String, Vec<MenuItem>),
+    /// saves a menu from the CSV or JSON file this command is the caption of
+    ImportMenu(String),
+    /// lists the menus saved for this conversation
+    ListMenus,
+    /// shows the items of a saved menu
+    ShowMenu(String),
+    /// deletes a saved menu
+    DeleteMenu(String),
+    /// starts an order with the given options whenever it is due, such as every Friday at 10:00
+    ScheduleOrder(String, Recurrence, StartOptions),
+    /// lists the orders scheduled for this conversation
+    ListSchedules,
+    /// stops starting a scheduled order
+    DeleteSchedule(u64),
     /// view the current order
     ViewOrders,
+    /// lists the given number of recently ended orders
+    History(usize),
     Help,
 }
 
+/// Options given when starting an order, such as /start waffles --multi
+/// These are saved with scheduled orders, so that each order started has them
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartOptions {
+    /// whether users may order several different items
+    pub allow_multiple_items: bool,
+    /// items offered from the start, listed on the lines following the command
+    pub menu: Vec<MenuItem>,
+    /// the name of a saved menu whose items are offered from the start
+    pub saved_menu: Option<String>,
+    /// whether only items on the menu may be ordered
+    pub menu_only: bool,
+    /// the time at which the order is automatically ended
+    pub deadline: Option<NaiveTime>,
+}
+
+/// An item to order, such as /order 2 chocolate @4.50
+#[derive(Debug, Eq, PartialEq)]
+pub struct ItemRequest {
+    pub item: String,
+    pub quantity: u32,
+    /// the price of one of this item, if specified
+    pub price: Option<Money>,
+    /// who the item is ordered for, if not whoever sent the request
+    pub recipient: Option<Recipient>,
+    /// how the item should be customised, such as no cream
+    pub note: Option<String>,
+    /// the choices made for the item's option groups, such as its size
+    pub options: Vec<Selection>,
+}
+
+/// Someone an item is ordered for, such as /order for @alice chocolate
+#[derive(Debug, Eq, PartialEq)]
+pub enum Recipient {
+    /// a user of the chat, by username or first name
+    Mention(String),
+    /// someone who isn't on Telegram, by the name given in quotes
+    Guest(String),
+}
+
+impl ItemRequest {
+    /// Creates a request for one of an item, without a price
+    pub fn new(item: &str) -> Self {
+        Self {
+            item: item.to_string(),
+            quantity: 1,
+            price: None,
+            recipient: None,
+            note: None,
+            options: vec![],
+        }
+    }
+}
+
 type ParseResult = std::result::Result<Command, String>;
 
+/// The number of ended orders shown by /history if not specified
+const DEFAULT_HISTORY_LENGTH: usize = 5;
+
+/// The most ended orders shown by /history, so that its reply fits in a single message
+const MAX_HISTORY_LENGTH: usize = 20;
+
+/// The most minutes before a deadline that a reminder may be posted, which is a day
+const MAX_REMINDER_MINUTES: u32 = 24 * 60;
+
+/// The longest note that may be added to an item, in characters
+const MAX_NOTE_LENGTH: usize = 200;
+
 pub fn parse_command(message: &str, active_orders: &[&str]) -> ParseResult {
     use Command::*;
     if !message.starts_with('/') {
@@ -31,19 +164,26 @@ pub fn parse_command(message: &str, active_orders: &[&str]) -> ParseResult {
     match command {
         "/help" => Ok(Help),
         "/start" => {
+            // only the first line contains the order name, since the menu may follow it
+            let mut lines = normalized_message.lines();
+            let first_line: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+            let (args, mut options) = parse_start_options(&first_line[1..])?;
+            options.menu = menu::parse_menu(lines)?;
             if args.len() == 1 {
-                Ok(StartOrder(args[0].to_string()))
+                Ok(StartOrder(args[0].to_string(), options))
+            } else if args.len() > 1 && args[0] == "again" {
+                Ok(StartOrderAgain(args[1..].join("-"), options))
             } else if args.is_empty() {
                 Err("Specify the name of the order. For example, /start waffles".into())
             } else {
                 let order_name_with_spaces_replaced = args.join("-");
-                Ok(StartOrder(order_name_with_spaces_replaced))
+                Ok(StartOrder(order_name_with_spaces_replaced, options))
             }
         }
         "/end" => {
             if active_orders.is_empty() {
                 Err("There are no active orders. Start one by using /start <order name>.".into())
-            } else if let Some(order_name) = infer_order_name(args, &active_orders) {
+            } else if let Some(order_name) = infer_order_name(args, active_orders) {
                 Ok(EndOrder(order_name))
             } else if args.is_empty() {
                 Err("Since there are multiple active orders, Specify the name of the order. For example, /end waffles".into())
@@ -51,6 +191,14 @@ pub fn parse_command(message: &str, active_orders: &[&str]) -> ParseResult {
                 Err(format!("Order {} not found.", args[0]))
             }
         }
+        "/undo" => Ok(Undo),
+        "/reopen" => {
+            if args.is_empty() {
+                Ok(Reopen(None))
+            } else {
+                Ok(Reopen(Some(args.join("-"))))
+            }
+        }
         "/order" => {
             if active_orders.is_empty() {
                 Err("There are no active orders. Start one by using /start <order name>.".into())
@@ -59,10 +207,9 @@ pub fn parse_command(message: &str, active_orders: &[&str]) -> ParseResult {
                     Err("Specify the name of the item you wish to order. For example, /order chocolate".into())
                 } else if active_orders.contains(&args[0]) {
                     let order_name = args[0];
-                    let item = args[1..].join(" ");
-                    Ok(AddItem(order_name.to_string(), item))
+                    Ok(AddItem(order_name.to_string(), parse_order(&args[1..], message)?))
                 } else {
-                    Ok(AddItem(active_orders[0].to_string(), args.join(" ")))
+                    Ok(AddItem(active_orders[0].to_string(), parse_order(args, message)?))
                 }
             } else {
                 // multiple active orders
@@ -70,8 +217,7 @@ pub fn parse_command(message: &str, active_orders: &[&str]) -> ParseResult {
                     Err("Specify the order name and item you wish to order. For example, /order waffles chocolate".into())
                 } else if active_orders.contains(&args[0]) {
                     let order_name = args[0];
-                    let item = args[1..].join(" ");
-                    Ok(AddItem(order_name.to_string(), item))
+                    Ok(AddItem(order_name.to_string(), parse_order(&args[1..], message)?))
                 } else {
                     Err(format!("Order {} not found. Specify the order name and item you wish to order. For example, /order waffles chocolate", args[0]))
                 }
@@ -80,19 +226,472 @@ pub fn parse_command(message: &str, active_orders: &[&str]) -> ParseResult {
         "/cancel" => {
             if active_orders.is_empty() {
                 Err("There are no active orders. Start one by using /start <order name>.".into())
-            } else if let Some(order_name) = infer_order_name(args, &active_orders) {
-                Ok(RemoveItem(order_name))
+            } else if let Some(order_name) =
+                infer_order_name(&args[..args.len().min(1)], active_orders)
+            {
+                // the item to cancel may follow the order name
+                let item = if args.len() > 1 {
+                    Some(args[1..].join(" "))
+                } else {
+                    None
+                };
+                Ok(RemoveItem(order_name, item))
+            } else if active_orders.len() == 1 {
+                // the only active order isn't named, so all arguments are the item
+                Ok(RemoveItem(active_orders[0].to_string(), Some(args.join(" "))))
             } else if args.is_empty() {
                 Err("As there are multiple active orders, Specify the name of the order. For example, /cancel waffles".into())
             } else {
                 Err(format!("Order {} not found.", args[0]))
             }
         }
+        "/price" => {
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if args.len() < 2 {
+                Err("Specify the item and its price. For example, /price chocolate 4.50".into())
+            } else {
+                let price: Money = args[args.len() - 1].parse()?;
+                let item_args = &args[..args.len() - 1];
+                if item_args.len() > 1 && active_orders.contains(&item_args[0]) {
+                    Ok(SetPrice(
+                        item_args[0].to_string(),
+                        item_args[1..].join(" "),
+                        price,
+                    ))
+                } else if active_orders.len() == 1 {
+                    Ok(SetPrice(
+                        active_orders[0].to_string(),
+                        item_args.join(" "),
+                        price,
+                    ))
+                } else {
+                    Err("Specify the order name, item and its price. For example, /price waffles chocolate 4.50".into())
+                }
+            }
+        }
+        "/delivery" | "/tax" | "/tip" | "/split" => {
+            let (description, example) = match command {
+                "/delivery" => ("the delivery fee", "/delivery 5"),
+                "/tax" => ("the service charge or tax", "/tax 10%"),
+                "/tip" => ("the tip", "/tip 2"),
+                _ => ("how to split charges", "/split evenly"),
+            };
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if args.is_empty() {
+                Err(format!("Specify {}. For example, {}", description, example))
+            } else {
+                let value = args[args.len() - 1];
+                let charge = match command {
+                    "/delivery" => Charge::DeliveryFee(value.parse()?),
+                    "/tax" => Charge::Tax(value.parse()?),
+                    "/tip" => Charge::Tip(value.parse()?),
+                    _ => Charge::Split(value.parse()?),
+                };
+                match infer_order_name(&args[..args.len() - 1], active_orders) {
+                    Some(order_name) => Ok(SetCharge(order_name, charge)),
+                    None if args.len() == 1 => Err(format!("As there are multiple active orders, Specify the name of the order. For example, {}", example.replacen(' ', " waffles ", 1))),
+                    None => Err(format!("Order {} not found.", args[0])),
+                }
+            }
+        }
+        "/deadline" => {
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if args.is_empty() {
+                Err("Specify when the order ends, or none to remove its deadline. For example, /deadline 11:30".into())
+            } else {
+                let time = match args[args.len() - 1] {
+                    "none" | "off" => None,
+                    time => Some(deadline::parse_time(time)?),
+                };
+                match infer_order_name(&args[..args.len() - 1], active_orders) {
+                    Some(order_name) => Ok(SetDeadline(order_name, time)),
+                    None if args.len() == 1 => Err("As there are multiple active orders, Specify the name of the order. For example, /deadline waffles 11:30".into()),
+                    None => Err(format!("Order {} not found.", args[0])),
+                }
+            }
+        }
+        "/remind" => {
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else {
+                let is_reminder =
+                    |arg: &str| arg == "none" || arg == "off" || arg.parse::<u32>().is_ok();
+                let (order_args, reminder_args) = match args.first() {
+                    Some(arg) if !is_reminder(arg) => args.split_at(1),
+                    _ => args.split_at(0),
+                };
+                let reminders = parse_reminders(reminder_args)?;
+                match infer_order_name(order_args, active_orders) {
+                    Some(order_name) => Ok(SetReminders(order_name, reminders)),
+                    None if order_args.is_empty() => Err("As there are multiple active orders, Specify the name of the order. For example, /remind waffles 15 5".into()),
+                    None => Err(format!("Order {} not found.", order_args[0])),
+                }
+            }
+        }
+        "/owners" => {
+            // the order name may precede add or remove
+            let (order_args, action_args) = match args.iter().position(|&arg| arg == "add" || arg == "remove") {
+                Some(position) => args.split_at(position),
+                None => args.split_at(args.len()),
+            };
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if let Some(order_name) = infer_order_name(order_args, active_orders) {
+                match action_args {
+                    [] => Ok(ListOwners(order_name)),
+                    [_] => Err("Specify the username or first name of the user. For example, /owners add @alice".into()),
+                    ["add", name @ ..] => Ok(AddOwner(order_name, name.join(" "))),
+                    [_, name @ ..] => Ok(RemoveOwner(order_name, name.join(" "))),
+                }
+            } else if order_args.is_empty() {
+                Err("As there are multiple active orders, Specify the name of the order. For example, /owners waffles add @alice".into())
+            } else {
+                Err(format!("Order {} not found.", order_args[0]))
+            }
+        }
+        "/handover" => {
+            // the order name may precede the name of who to hand it over to
+            let (order_args, name) = if args.len() > 1 && active_orders.contains(&args[0]) {
+                args.split_at(1)
+            } else {
+                args.split_at(0)
+            };
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if name.is_empty() {
+                Err("Specify the username or first name of who to hand the order over to. For example, /handover @alice".into())
+            } else {
+                match infer_order_name(order_args, active_orders) {
+                    Some(order_name) => Ok(HandOver(order_name, name.join(" "))),
+                    None => Err("As there are multiple active orders, Specify the name of the order. For example, /handover waffles @alice".into()),
+                }
+            }
+        }
+        "/lock" | "/unlock" | "/placed" | "/arrived" => {
+            let state = match command {
+                "/lock" => OrderState::Locked,
+                "/unlock" => OrderState::Open,
+                "/placed" => OrderState::Placed,
+                _ => OrderState::Delivered,
+            };
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if let Some(order_name) = infer_order_name(args, active_orders) {
+                Ok(ChangeState(order_name, state))
+            } else if args.is_empty() {
+                Err(format!("As there are multiple active orders, Specify the name of the order. For example, {} waffles", command))
+            } else {
+                Err(format!("Order {} not found.", args[0]))
+            }
+        }
+        "/multi" => {
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if let Some(order_name) = infer_order_name(args, active_orders) {
+                Ok(ToggleMultipleItems(order_name))
+            } else if args.is_empty() {
+                Err("As there are multiple active orders, Specify the name of the order. For example, /multi waffles".into())
+            } else {
+                Err(format!("Order {} not found.", args[0]))
+            }
+        }
+        "/menuonly" => {
+            if active_orders.is_empty() {
+                Err("There are no active orders. Start one by using /start <order name>.".into())
+            } else if let Some(order_name) = infer_order_name(args, active_orders) {
+                Ok(ToggleMenuOnly(order_name))
+            } else if args.is_empty() {
+                Err("As there are multiple active orders, Specify the name of the order. For example, /menuonly waffles".into())
+            } else {
+                Err(format!("Order {} not found.", args[0]))
+            }
+        }
+        "/paid" => {
+            if args.is_empty() {
+                Ok(Paid(None))
+            } else {
+                Ok(Paid(Some(args.join("-"))))
+            }
+        }
+        "/unpaid" => Ok(ViewUnpaid),
+        "/balances" => Ok(ViewBalances),
+        "/settle" => Ok(SettleUp),
+        "/menu" => {
+            let mut lines = normalized_message.lines();
+            let first_line: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+            match &first_line[1..] {
+                ["save", name, items @ ..] => {
+                    // items may follow the menu name, as well as being listed on the following lines
+                    let items_on_first_line = items.join(" ");
+                    let items = menu::parse_menu(std::iter::once(items_on_first_line.as_str()).chain(lines))?;
+                    if items.is_empty() {
+                        Err("List the items of the menu on the following lines, each optionally followed by its price. For example, /menu save waffles-house chocolate 4.50, plain 3".into())
+                    } else {
+                        Ok(SaveMenu(name.to_string(), items))
+                    }
+                }
+                ["import", name] => Ok(ImportMenu(name.to_string())),
+                ["import"] => Err("Specify the name of the menu. For example, send a CSV or JSON file with the caption /menu import thai-place".into()),
+                ["list"] | [] => Ok(ListMenus),
+                ["show", name] => Ok(ShowMenu(name.to_string())),
+                ["delete", name] => Ok(DeleteMenu(name.to_string())),
+                _ => Err("Use /menu save <name> followed by its items, /menu import <name> as the caption of a file, /menu list, /menu show <name> or /menu delete <name>.".into()),
+            }
+        }
+        "/schedule" => {
+            // only the first line contains the schedule, since the menu may follow it
+            let mut lines = normalized_message.lines();
+            let first_line: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+            let (args, mut options) = parse_start_options(&first_line[1..])?;
+            options.menu = menu::parse_menu(lines)?;
+            match args.as_slice() {
+                [order_name, days, time] => Ok(ScheduleOrder(order_name.to_string(), Recurrence::parse(days, time)?, options)),
+                _ => Err("Specify the name of the order, the days and the time to start it. For example, /schedule waffles fri 10:00 --until 11:30".into()),
+            }
+        }
+        "/schedules" => match args {
+            [] | ["list"] => Ok(ListSchedules),
+            ["delete", id] => match id.parse() {
+                Ok(id) => Ok(DeleteSchedule(id)),
+                Err(_) => Err(format!("{} is not the number of a scheduled order. Use /schedules to list them.", id)),
+            },
+            _ => Err("Use /schedules to list scheduled orders, or /schedules delete <number> to delete one.".into()),
+        },
         "/view" => Ok(ViewOrders),
+        "/history" => {
+            if args.is_empty() {
+                Ok(History(DEFAULT_HISTORY_LENGTH))
+            } else {
+                match args[0].parse() {
+                    Ok(count) if args.len() == 1 && (1..=MAX_HISTORY_LENGTH).contains(&count) => {
+                        Ok(History(count))
+                    }
+                    _ => Err(format!(
+                        "Specify the number of orders to show, up to {}. For example, /history 10",
+                        MAX_HISTORY_LENGTH
+                    )),
+                }
+            }
+        }
         _ => Err("Use /help for a list of recognized commands.".to_string()),
     }
 }
 
+/// Separates options such as --multi from the rest of the arguments to /start
+fn parse_start_options<'a>(args: &[&'a str]) -> Result<(Vec<&'a str>, StartOptions), String> {
+    let mut options = StartOptions::default();
+    let mut remaining_args = vec![];
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--multi" => options.allow_multiple_items = true,
+            "--menu-only" => options.menu_only = true,
+            "--until" => match args.next() {
+                Some(time) => options.deadline = Some(deadline::parse_time(time)?),
+                None => {
+                    return Err(
+                        "Specify when the order ends. For example, /start waffles --until 11:30"
+                            .into(),
+                    )
+                }
+            },
+            "--menu" => match args.next() {
+                Some(name) => options.saved_menu = Some(name.to_string()),
+                None => {
+                    return Err(
+                        "Specify the name of the saved menu. For example, /start waffles --menu waffles-house"
+                            .into(),
+                    )
+                }
+            },
+            _ if arg.starts_with("--") => {
+                return Err(format!(
+                    "Unrecognized option {}. Use --multi to allow ordering several items, --menu <name> to offer a saved menu, --menu-only to only allow items on the menu, or --until <time> to end the order automatically.",
+                    arg
+                ))
+            }
+            _ => remaining_args.push(arg),
+        }
+    }
+    Ok((remaining_args, options))
+}
+
+/// Parses an item name, optionally preceded by the quantity to order and followed by its price
+/// For example, 2 chocolate @4.50
+fn parse_item(args: &[&str]) -> Result<ItemRequest, String> {
+    let mut args = args;
+    let mut price = None;
+    if let Some(last_arg) = args.last() {
+        if let Some(amount) = last_arg.strip_prefix('@') {
+            price = Some(amount.parse()?);
+            args = &args[..args.len() - 1];
+        }
+    }
+    let mut quantity = 1;
+    if args.len() > 1 {
+        if let Ok(parsed_quantity) = args[0].parse::<u32>() {
+            if !(1..=MAX_QUANTITY).contains(&parsed_quantity) {
+                return Err(format!(
+                    "Quantities must be between 1 and {}.",
+                    MAX_QUANTITY
+                ));
+            }
+            quantity = parsed_quantity;
+            args = &args[1..];
+        }
+    }
+    if args.is_empty() {
+        return Err(
+            "Specify the name of the item you wish to order. For example, /order chocolate".into(),
+        );
+    }
+    Ok(ItemRequest {
+        item: args.join(" "),
+        quantity,
+        price,
+        recipient: None,
+        note: None,
+        options: vec![],
+    })
+}
+
+/// Parses an item to order, optionally preceded by who it is for, such as for @alice chocolate or for "Bob (guest)" waffle
+/// The item may be followed by a note after --, such as chocolate -- no cream
+/// Guests' names and notes are taken from the original message, so that they keep their case
+fn parse_order(args: &[&str], message: &str) -> Result<ItemRequest, String> {
+    let (args, note) = match args.iter().position(|&arg| arg == "--") {
+        Some(separator) => (&args[..separator], parse_note(message)?),
+        None => (args, None),
+    };
+    let (recipient, args) = match args {
+        ["for", rest @ ..] => {
+            let (recipient, consumed) = parse_recipient(rest, message)?;
+            (Some(recipient), &rest[consumed..])
+        }
+        _ => (None, args),
+    };
+    Ok(ItemRequest {
+        recipient,
+        note,
+        ..parse_item(args)?
+    })
+}
+
+/// Parses the note following the first -- in a message, such as no cream, extra hot
+fn parse_note(message: &str) -> Result<Option<String>, String> {
+    let note = message
+        .split_whitespace()
+        .skip_while(|&word| word != "--")
+        .skip(1)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if note.chars().count() > MAX_NOTE_LENGTH {
+        return Err(format!(
+            "Notes must not be longer than {} characters.",
+            MAX_NOTE_LENGTH
+        ));
+    }
+    Ok(Some(note).filter(|note| !note.is_empty()))
+}
+
+/// Parses a mention such as @alice, or a guest's name in quotes
+/// Returns the recipient and how many arguments they took up
+fn parse_recipient(args: &[&str], message: &str) -> Result<(Recipient, usize), String> {
+    // phones often replace straight quotes with curly ones
+    let is_quote = |c: char| matches!(c, '"' | '\u{201c}' | '\u{201d}');
+    let error = || {
+        "Specify who the item is for, as a username or a guest's name in quotes. For example, /order for @alice chocolate or /order for \"Bob (guest)\" waffle".to_string()
+    };
+    match args.first() {
+        Some(arg) if arg.starts_with(is_quote) => {
+            // the name is read from the message as written, starting at the quote which follows for
+            let words = words_with_offsets(message);
+            let start = words
+                .windows(2)
+                .find(|pair| {
+                    pair[0].1.eq_ignore_ascii_case("for") && pair[1].1.starts_with(is_quote)
+                })
+                .map(|pair| pair[1].0)
+                .ok_or_else(error)?;
+            // a note following -- may contain quotes of its own
+            let end = words
+                .iter()
+                .find(|&&(offset, word)| offset > start && word == "--")
+                .map_or(message.len(), |&(offset, _)| offset);
+            let opening_quote = message[start..].chars().next().unwrap().len_utf8();
+            let name_start = start + opening_quote;
+            let (name_end, closing_quote) = message[name_start..end]
+                .match_indices(is_quote)
+                .next()
+                .map(|(index, quote)| (name_start + index, quote.len()))
+                .ok_or_else(|| {
+                    "Close the guest's name with a quote. For example, /order for \"Bob (guest)\" waffle".to_string()
+                })?;
+            let name = message[name_start..name_end].trim();
+            if name.is_empty() {
+                return Err(error());
+            }
+            let quoted = &message[start..name_end + closing_quote];
+            Ok((
+                Recipient::Guest(name.to_string()),
+                quoted.split_whitespace().count(),
+            ))
+        }
+        Some(name) => Ok((
+            Recipient::Mention(name.trim_start_matches('@').to_string()),
+            1,
+        )),
+        None => Err(error()),
+    }
+}
+
+/// Splits a message into words like split_whitespace, along with where each word starts
+fn words_with_offsets(message: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (index, c) in message.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(word_start)) => {
+                words.push((word_start, &message[word_start..index]));
+                start = None;
+            }
+            (false, None) => start = Some(index),
+            _ => (),
+        }
+    }
+    if let Some(word_start) = start {
+        words.push((word_start, &message[word_start..]));
+    }
+    words
+}
+
+/// Parses how many minutes before a deadline reminders are posted, such as 15 5, or none to stop them
+/// Reminders are returned from earliest to latest
+fn parse_reminders(args: &[&str]) -> Result<Vec<u32>, String> {
+    match args {
+        [] => Err("Specify how many minutes before the deadline to post reminders, or none to stop them. For example, /remind 15 5".into()),
+        ["none"] | ["off"] => Ok(vec![]),
+        _ => {
+            let mut reminders = args
+                .iter()
+                .map(|arg| match arg.parse() {
+                    Ok(minutes) if (1..=MAX_REMINDER_MINUTES).contains(&minutes) => Ok(minutes),
+                    _ => Err(format!(
+                        "{} is not a number of minutes between 1 and {}. For example, /remind 15 5",
+                        arg, MAX_REMINDER_MINUTES
+                    )),
+                })
+                .collect::<Result<Vec<u32>, String>>()?;
+            reminders.sort_unstable_by(|a, b| b.cmp(a));
+            reminders.dedup();
+            Ok(reminders)
+        }
+    }
+}
+
 fn infer_order_name(args: &[&str], active_orders: &[&str]) -> Option<String> {
     if args.is_empty() && active_orders.len() == 1 {
         Some(active_orders[0].to_string()) // order name not specified, but can be infered
@@ -106,6 +705,7 @@ fn infer_order_name(args: &[&str], active_orders: &[&str]) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::charges::SplitMethod;
     use Command::*;
 
     static NO_ORDERS: &[&str] = &[];
@@ -128,7 +728,7 @@ mod tests {
         );
         assert_eq!(
             parse_command("/start waffles", NO_ORDERS),
-            Ok(StartOrder("waffles".into()))
+            Ok(StartOrder("waffles".into(), StartOptions::default()))
         );
         assert_eq!(
             parse_command("/Start WAFFLES ", NO_ORDERS),
@@ -142,14 +742,112 @@ mod tests {
         );
         assert_eq!(
             parse_command("/start ice cream", NO_ORDERS),
-            Ok(StartOrder("ice-cream".into())),
+            Ok(StartOrder("ice-cream".into(), StartOptions::default())),
             "Spaces in orders are automatically replaced with -"
         );
         assert_eq!(
             parse_command("/start ice-cream", NO_ORDERS),
-            Ok(StartOrder("ice-cream".into())),
+            Ok(StartOrder("ice-cream".into(), StartOptions::default())),
             "order names may contain -"
         );
+        assert_eq!(
+            parse_command("/start again waffles", NO_ORDERS),
+            Ok(StartOrderAgain("waffles".into(), StartOptions::default()))
+        );
+        assert_eq!(
+            parse_command("/start again ice cream", NO_ORDERS),
+            Ok(StartOrderAgain("ice-cream".into(), StartOptions::default()))
+        );
+        assert_eq!(
+            parse_command("/start again", NO_ORDERS),
+            Ok(StartOrder("again".into(), StartOptions::default())),
+            "again is treated as the order name when no other name is given"
+        );
+        let multi = StartOptions {
+            allow_multiple_items: true,
+            ..StartOptions::default()
+        };
+        assert_eq!(
+            parse_command("/start ice cream --multi", NO_ORDERS),
+            Ok(StartOrder("ice-cream".into(), multi))
+        );
+        assert_eq!(
+            parse_command("/start waffles --many", NO_ORDERS),
+            Err("Unrecognized option --many. Use --multi to allow ordering several items, --menu <name> to offer a saved menu, --menu-only to only allow items on the menu, or --until <time> to end the order automatically.".into())
+        );
+        assert_eq!(
+            parse_command("/start --multi", NO_ORDERS),
+            Err("Specify the name of the order. For example, /start waffles".into())
+        );
+
+        let with_menu = StartOptions {
+            menu: vec![
+                MenuItem::new("chocolate".into(), Some("4.50".parse().unwrap())),
+                MenuItem::new("plain".into(), None),
+            ],
+            ..StartOptions::default()
+        };
+        assert_eq!(
+            parse_command("/start waffles\nChocolate 4.50\nplain", NO_ORDERS),
+            Ok(StartOrder("waffles".into(), with_menu))
+        );
+        let saved_menu = StartOptions {
+            saved_menu: Some("waffles-house".into()),
+            ..StartOptions::default()
+        };
+        assert_eq!(
+            parse_command("/start waffles --menu waffles-house", NO_ORDERS),
+            Ok(StartOrder("waffles".into(), saved_menu))
+        );
+        let until = StartOptions {
+            deadline: NaiveTime::from_hms_opt(11, 30, 0),
+            ..StartOptions::default()
+        };
+        assert_eq!(
+            parse_command("/start waffles --until 11:30", NO_ORDERS),
+            Ok(StartOrder("waffles".into(), until))
+        );
+        assert_eq!(
+            parse_command("/start waffles --until noon", NO_ORDERS),
+            Err("noon is not a valid time. For example, use 11:30".into())
+        );
+        let menu_only = StartOptions {
+            saved_menu: Some("waffles-house".into()),
+            menu_only: true,
+            ..StartOptions::default()
+        };
+        assert_eq!(
+            parse_command("/start waffles --menu waffles-house --menu-only", NO_ORDERS),
+            Ok(StartOrder("waffles".into(), menu_only))
+        );
+    }
+
+    #[test]
+    fn parse_menu_commands() {
+        let items = vec![
+            MenuItem::new("chocolate".into(), Some("4.50".parse().unwrap())),
+            MenuItem::new("plain".into(), None),
+        ];
+        assert_eq!(
+            parse_command("/menu save waffles-house chocolate 4.50\nplain", NO_ORDERS),
+            Ok(SaveMenu("waffles-house".into(), items.clone()))
+        );
+        assert_eq!(
+            parse_command("/menu save waffles-house\nchocolate 4.50, plain", NO_ORDERS),
+            Ok(SaveMenu("waffles-house".into(), items))
+        );
+        assert!(parse_command("/menu save waffles-house", NO_ORDERS).is_err());
+        assert_eq!(parse_command("/menu list", NO_ORDERS), Ok(ListMenus));
+        assert_eq!(
+            parse_command("/menu show waffles-house", NO_ORDERS),
+            Ok(ShowMenu("waffles-house".into()))
+        );
+        assert!(parse_command("/menu show", NO_ORDERS).is_err());
+        assert_eq!(
+            parse_command("/menu import Thai-Place", NO_ORDERS),
+            Ok(ImportMenu("thai-place".into()))
+        );
+        assert!(parse_command("/menu import", NO_ORDERS).is_err());
     }
 
     #[test]
@@ -225,22 +923,28 @@ mod tests {
         );
         assert_eq!(
             parse_command("/order chocolate", WAFFLES),
-            Ok(AddItem("waffles".into(), "chocolate".into())),
+            Ok(AddItem("waffles".into(), ItemRequest::new("chocolate"))),
             "Order name may be omitted if there is only 1 active order"
         );
         assert_eq!(
             parse_command("/order Large Chocolate ", WAFFLES),
-            Ok(AddItem("waffles".into(), "large chocolate".into())),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest::new("large chocolate")
+            )),
             "capitalization is ignored, and multi-word items are allowed"
         );
         assert_eq!(
             parse_command("/order waffles chocolate", WAFFLES),
-            Ok(AddItem("waffles".into(), "chocolate".into())),
+            Ok(AddItem("waffles".into(), ItemRequest::new("chocolate"))),
             "Order name may be specified even when there is only 1 active order"
         );
         assert_eq!(
             parse_command("/order waffles Large Chocolate", WAFFLES),
-            Ok(AddItem("waffles".into(), "large chocolate".into())),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest::new("large chocolate")
+            )),
             "capitalization is ignored, and multi-word items are allowed"
         );
 
@@ -259,20 +963,270 @@ mod tests {
         );
         assert_eq!(
             parse_command("/order waffles chocolate", WAFFLES_AND_PIZZA),
-            Ok(AddItem("waffles".into(), "chocolate".into())),
+            Ok(AddItem("waffles".into(), ItemRequest::new("chocolate"))),
         );
         assert_eq!(
             parse_command("/order  waffles LARGE  CHOCOLATE ", WAFFLES_AND_PIZZA),
-            Ok(AddItem("waffles".into(), "large chocolate".into())),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest::new("large chocolate")
+            )),
         );
         assert_eq!(
             parse_command("/order pizza Barbecue chicken", WAFFLES_AND_PIZZA),
-            Ok(AddItem("pizza".into(), "barbecue chicken".into())),
+            Ok(AddItem(
+                "pizza".into(),
+                ItemRequest::new("barbecue chicken")
+            )),
         );
         assert_eq!(
             parse_command("/order ice-cream chocolate cone", WAFFLES_AND_PIZZA),
             Err("Order ice-cream not found. Specify the order name and item you wish to order. For example, /order waffles chocolate".into()),
         );
+
+        // quantities
+        assert_eq!(
+            parse_command("/order 2 chocolate", WAFFLES),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    quantity: 2,
+                    ..ItemRequest::new("chocolate")
+                }
+            )),
+        );
+        assert_eq!(
+            parse_command("/order waffles 3 large chocolate", WAFFLES_AND_PIZZA),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    quantity: 3,
+                    ..ItemRequest::new("large chocolate")
+                }
+            )),
+        );
+        assert_eq!(
+            parse_command("/order 7", WAFFLES),
+            Ok(AddItem("waffles".into(), ItemRequest::new("7"))),
+            "a number on its own is an item name rather than a quantity"
+        );
+        assert_eq!(
+            parse_command("/order 0 chocolate", WAFFLES),
+            Err("Quantities must be between 1 and 99.".into()),
+        );
+        assert_eq!(
+            parse_command("/order 100 chocolate", WAFFLES),
+            Err("Quantities must be between 1 and 99.".into()),
+        );
+
+        // prices
+        assert_eq!(
+            parse_command("/order 2 chocolate @4.50", WAFFLES),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    quantity: 2,
+                    price: Some(Money::from_cents(450)),
+                    ..ItemRequest::new("chocolate")
+                }
+            )),
+        );
+        assert_eq!(
+            parse_command("/order chocolate @four", WAFFLES),
+            Err("four is not a valid amount. For example, use 4.50".into()),
+        );
+        assert_eq!(
+            parse_command("/order @4.50", WAFFLES),
+            Err(
+                "Specify the name of the item you wish to order. For example, /order chocolate"
+                    .into()
+            ),
+        );
+
+        // ordering for others
+        assert_eq!(
+            parse_command("/order for @Alice 2 chocolate", WAFFLES),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    quantity: 2,
+                    recipient: Some(Recipient::Mention("alice".into())),
+                    ..ItemRequest::new("chocolate")
+                }
+            )),
+        );
+        assert_eq!(
+            parse_command(
+                "/order waffles for \"Bob (guest)\" plain",
+                WAFFLES_AND_PIZZA
+            ),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    recipient: Some(Recipient::Guest("Bob (guest)".into())),
+                    ..ItemRequest::new("plain")
+                }
+            )),
+        );
+        assert_eq!(
+            parse_command("/order for \u{201c}Bob\u{201d} plain", WAFFLES),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    recipient: Some(Recipient::Guest("Bob".into())),
+                    ..ItemRequest::new("plain")
+                }
+            )),
+            "curly quotes are accepted"
+        );
+        assert_eq!(
+            parse_command("/order for \"Bob plain", WAFFLES),
+            Err("Close the guest's name with a quote. For example, /order for \"Bob (guest)\" waffle".into()),
+        );
+        assert_eq!(
+            parse_command("/order for \"Bob plain -- say \"hi\"", WAFFLES),
+            parse_command("/order for \"Bob plain", WAFFLES),
+            "quotes in notes don't close the guest's name"
+        );
+        assert_eq!(
+            parse_command(
+                "/order \"pizza\" for \"Dan\" large hawaiian",
+                &["\"pizza\""]
+            ),
+            Ok(AddItem(
+                "\"pizza\"".into(),
+                ItemRequest {
+                    recipient: Some(Recipient::Guest("Dan".into())),
+                    ..ItemRequest::new("large hawaiian")
+                }
+            )),
+            "only quotes following for enclose the guest's name"
+        );
+        assert_eq!(
+            parse_command("/order for @alice", WAFFLES),
+            Err(
+                "Specify the name of the item you wish to order. For example, /order chocolate"
+                    .into()
+            ),
+        );
+
+        // notes
+        assert_eq!(
+            parse_command("/order 2 chocolate @4.50 -- No cream,  extra hot", WAFFLES),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    quantity: 2,
+                    price: Some(Money::from_cents(450)),
+                    note: Some("No cream, extra hot".into()),
+                    ..ItemRequest::new("chocolate")
+                }
+            )),
+        );
+        assert_eq!(
+            parse_command("/order for \"Bob\" plain --", WAFFLES),
+            Ok(AddItem(
+                "waffles".into(),
+                ItemRequest {
+                    recipient: Some(Recipient::Guest("Bob".into())),
+                    ..ItemRequest::new("plain")
+                }
+            )),
+            "empty notes are ignored"
+        );
+        assert_eq!(
+            parse_command("/order -- no cream", WAFFLES),
+            Err(
+                "Specify the name of the item you wish to order. For example, /order chocolate"
+                    .into()
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_charges() {
+        assert_eq!(
+            parse_command("/delivery 5", WAFFLES),
+            Ok(SetCharge(
+                "waffles".into(),
+                Charge::DeliveryFee("5".parse().unwrap())
+            ))
+        );
+        assert_eq!(
+            parse_command("/tax pizza 7.5%", WAFFLES_AND_PIZZA),
+            Ok(SetCharge(
+                "pizza".into(),
+                Charge::Tax("7.5".parse().unwrap())
+            ))
+        );
+        assert_eq!(
+            parse_command("/split evenly", WAFFLES),
+            Ok(SetCharge(
+                "waffles".into(),
+                Charge::Split(SplitMethod::Evenly)
+            ))
+        );
+        assert_eq!(
+            parse_command("/tip 2", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /tip waffles 2".into())
+        );
+        assert_eq!(
+            parse_command("/tip", WAFFLES),
+            Err("Specify the tip. For example, /tip 2".into())
+        );
+        assert_eq!(
+            parse_command("/split randomly", WAFFLES),
+            Err("randomly is not a way of splitting charges. Use evenly or proportionally.".into())
+        );
+    }
+
+    #[test]
+    fn parse_paid() {
+        assert_eq!(parse_command("/paid", NO_ORDERS), Ok(Paid(None)));
+        assert_eq!(
+            parse_command("/paid ice cream", NO_ORDERS),
+            Ok(Paid(Some("ice-cream".into())))
+        );
+    }
+
+    #[test]
+    fn parse_price() {
+        assert_eq!(
+            parse_command("/price chocolate 4.50", NO_ORDERS),
+            Err("There are no active orders. Start one by using /start <order name>.".into())
+        );
+        assert_eq!(
+            parse_command("/price large chocolate 4.50", WAFFLES),
+            Ok(SetPrice(
+                "waffles".into(),
+                "large chocolate".into(),
+                Money::from_cents(450)
+            ))
+        );
+        assert_eq!(
+            parse_command("/price waffles chocolate $4", WAFFLES),
+            Ok(SetPrice(
+                "waffles".into(),
+                "chocolate".into(),
+                Money::from_cents(400)
+            ))
+        );
+        assert_eq!(
+            parse_command("/price pizza hawaiian 12", WAFFLES_AND_PIZZA),
+            Ok(SetPrice(
+                "pizza".into(),
+                "hawaiian".into(),
+                Money::from_cents(1200)
+            ))
+        );
+        assert_eq!(
+            parse_command("/price hawaiian 12", WAFFLES_AND_PIZZA),
+            Err("Specify the order name, item and its price. For example, /price waffles chocolate 4.50".into())
+        );
+        assert_eq!(
+            parse_command("/price chocolate", WAFFLES),
+            Err("Specify the item and its price. For example, /price chocolate 4.50".into())
+        );
     }
 
     #[test]
@@ -289,15 +1243,20 @@ mod tests {
         // 1 active order
         assert_eq!(
             parse_command("/cancel", WAFFLES),
-            Ok(RemoveItem("waffles".into()))
+            Ok(RemoveItem("waffles".into(), None))
         );
         assert_eq!(
             parse_command("/cancel Waffles", WAFFLES),
-            Ok(RemoveItem("waffles".into()))
+            Ok(RemoveItem("waffles".into(), None))
         );
         assert_eq!(
-            parse_command("/cancel ice-cream", WAFFLES),
-            Err("Order ice-cream not found.".into())
+            parse_command("/cancel chocolate", WAFFLES),
+            Ok(RemoveItem("waffles".into(), Some("chocolate".into()))),
+            "the only active order is used if the first argument isn't an active order"
+        );
+        assert_eq!(
+            parse_command("/cancel large chocolate", WAFFLES),
+            Ok(RemoveItem("waffles".into(), Some("large chocolate".into())))
         );
 
         // 2 active orders
@@ -307,11 +1266,228 @@ mod tests {
         );
         assert_eq!(
             parse_command("/cancel PIZZA ", WAFFLES_AND_PIZZA),
-            Ok(RemoveItem("pizza".into()))
+            Ok(RemoveItem("pizza".into(), None))
         );
         assert_eq!(
             parse_command("/cancel ice-cream", WAFFLES_AND_PIZZA),
             Err("Order ice-cream not found.".into())
         );
+
+        // specific items
+        assert_eq!(
+            parse_command("/cancel waffles large chocolate", WAFFLES),
+            Ok(RemoveItem("waffles".into(), Some("large chocolate".into())))
+        );
+        assert_eq!(
+            parse_command("/cancel pizza hawaiian", WAFFLES_AND_PIZZA),
+            Ok(RemoveItem("pizza".into(), Some("hawaiian".into())))
+        );
+        assert_eq!(
+            parse_command("/cancel ice-cream chocolate", WAFFLES_AND_PIZZA),
+            Err("Order ice-cream not found.".into())
+        );
+    }
+
+    #[test]
+    fn parse_multi() {
+        assert_eq!(
+            parse_command("/multi", NO_ORDERS),
+            Err("There are no active orders. Start one by using /start <order name>.".into())
+        );
+        assert_eq!(
+            parse_command("/multi", WAFFLES),
+            Ok(ToggleMultipleItems("waffles".into()))
+        );
+        assert_eq!(
+            parse_command("/multi", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /multi waffles".into())
+        );
+        assert_eq!(
+            parse_command("/multi pizza", WAFFLES_AND_PIZZA),
+            Ok(ToggleMultipleItems("pizza".into()))
+        );
+    }
+
+    #[test]
+    fn parse_schedule() {
+        let options = StartOptions {
+            saved_menu: Some("waffles-house".into()),
+            deadline: NaiveTime::from_hms_opt(11, 30, 0),
+            ..StartOptions::default()
+        };
+        assert_eq!(
+            parse_command(
+                "/schedule waffles Fri 10:00 --menu waffles-house --until 11:30",
+                NO_ORDERS
+            ),
+            Ok(ScheduleOrder(
+                "waffles".into(),
+                Recurrence::parse("fri", "10:00").unwrap(),
+                options
+            ))
+        );
+        assert_eq!(
+            parse_command("/schedule waffles 10:00", NO_ORDERS),
+            Err("Specify the name of the order, the days and the time to start it. For example, /schedule waffles fri 10:00 --until 11:30".into())
+        );
+        assert!(parse_command("/schedule waffles someday 10:00", NO_ORDERS).is_err());
+        assert_eq!(parse_command("/schedules", NO_ORDERS), Ok(ListSchedules));
+        assert_eq!(
+            parse_command("/schedules delete 2", NO_ORDERS),
+            Ok(DeleteSchedule(2))
+        );
+        assert!(parse_command("/schedules delete waffles", NO_ORDERS).is_err());
+    }
+
+    #[test]
+    fn parse_owners() {
+        assert_eq!(
+            parse_command("/owners", WAFFLES),
+            Ok(ListOwners("waffles".into()))
+        );
+        assert_eq!(
+            parse_command("/owners add @Bob", WAFFLES),
+            Ok(AddOwner("waffles".into(), "@bob".into()))
+        );
+        assert_eq!(
+            parse_command("/owners pizza remove bob", WAFFLES_AND_PIZZA),
+            Ok(RemoveOwner("pizza".into(), "bob".into()))
+        );
+        assert_eq!(
+            parse_command("/owners add bob", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /owners waffles add @alice".into())
+        );
+        assert!(parse_command("/owners add", WAFFLES).is_err());
+        assert!(parse_command("/owners burgers add bob", WAFFLES_AND_PIZZA).is_err());
+    }
+
+    #[test]
+    fn parse_handover() {
+        assert_eq!(
+            parse_command("/handover @bob", WAFFLES),
+            Ok(HandOver("waffles".into(), "@bob".into()))
+        );
+        assert_eq!(
+            parse_command("/handover waffles bob", WAFFLES),
+            Ok(HandOver("waffles".into(), "bob".into()))
+        );
+        assert_eq!(
+            parse_command("/handover pizza bob", WAFFLES_AND_PIZZA),
+            Ok(HandOver("pizza".into(), "bob".into()))
+        );
+        assert!(parse_command("/handover bob", WAFFLES_AND_PIZZA).is_err());
+        assert!(parse_command("/handover", WAFFLES).is_err());
+    }
+
+    #[test]
+    fn parse_state_changes() {
+        assert_eq!(
+            parse_command("/lock", WAFFLES),
+            Ok(ChangeState("waffles".into(), OrderState::Locked))
+        );
+        assert_eq!(
+            parse_command("/unlock waffles", WAFFLES_AND_PIZZA),
+            Ok(ChangeState("waffles".into(), OrderState::Open))
+        );
+        assert_eq!(
+            parse_command("/placed pizza", WAFFLES_AND_PIZZA),
+            Ok(ChangeState("pizza".into(), OrderState::Placed))
+        );
+        assert_eq!(
+            parse_command("/arrived", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /arrived waffles".into())
+        );
+        assert!(parse_command("/lock", NO_ORDERS).is_err());
+    }
+
+    #[test]
+    fn parse_reopen() {
+        assert_eq!(parse_command("/reopen", NO_ORDERS), Ok(Reopen(None)));
+        assert_eq!(
+            parse_command("/reopen ice cream", WAFFLES),
+            Ok(Reopen(Some("ice-cream".into())))
+        );
+    }
+
+    #[test]
+    fn parse_remind() {
+        assert_eq!(
+            parse_command("/remind 5 15", WAFFLES),
+            Ok(SetReminders("waffles".into(), vec![15, 5]))
+        );
+        assert_eq!(
+            parse_command("/remind pizza none", WAFFLES_AND_PIZZA),
+            Ok(SetReminders("pizza".into(), vec![]))
+        );
+        assert_eq!(
+            parse_command("/remind 10", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /remind waffles 15 5".into())
+        );
+        assert_eq!(
+            parse_command("/remind burgers 10", WAFFLES_AND_PIZZA),
+            Err("Order burgers not found.".into())
+        );
+        assert_eq!(
+            parse_command("/remind 0", WAFFLES),
+            Err(
+                "0 is not a number of minutes between 1 and 1440. For example, /remind 15 5".into()
+            )
+        );
+        assert!(parse_command("/remind", WAFFLES).is_err());
+    }
+
+    #[test]
+    fn parse_deadline() {
+        assert_eq!(
+            parse_command("/deadline 2pm", WAFFLES),
+            Ok(SetDeadline(
+                "waffles".into(),
+                NaiveTime::from_hms_opt(14, 0, 0)
+            ))
+        );
+        assert_eq!(
+            parse_command("/deadline pizza none", WAFFLES_AND_PIZZA),
+            Ok(SetDeadline("pizza".into(), None))
+        );
+        assert_eq!(
+            parse_command("/deadline 11:30", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /deadline waffles 11:30".into())
+        );
+    }
+
+    #[test]
+    fn parse_menu_only() {
+        assert_eq!(
+            parse_command("/menuonly", WAFFLES),
+            Ok(ToggleMenuOnly("waffles".into()))
+        );
+        assert_eq!(
+            parse_command("/menuonly", WAFFLES_AND_PIZZA),
+            Err("As there are multiple active orders, Specify the name of the order. For example, /menuonly waffles".into())
+        );
+        assert_eq!(
+            parse_command("/menuonly pizza", WAFFLES_AND_PIZZA),
+            Ok(ToggleMenuOnly("pizza".into()))
+        );
+    }
+
+    #[test]
+    fn parse_history() {
+        assert_eq!(parse_command("/history", NO_ORDERS), Ok(History(5)));
+        assert_eq!(parse_command("/history 10", WAFFLES), Ok(History(10)));
+        assert_eq!(parse_command("/history 20", WAFFLES), Ok(History(20)));
+        assert_eq!(
+            parse_command("/history 0", NO_ORDERS),
+            Err("Specify the number of orders to show, up to 20. For example, /history 10".into())
+        );
+        assert_eq!(
+            parse_command("/history 21", NO_ORDERS),
+            parse_command("/history 0", NO_ORDERS),
+            "replies listing too many orders wouldn't fit in a message"
+        );
+        assert_eq!(
+            parse_command("/history waffles", NO_ORDERS),
+            parse_command("/history 0", NO_ORDERS)
+        );
     }
 }
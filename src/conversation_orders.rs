@@ -1,61 +1,626 @@
-use std::{collections::HashMap, fmt, string::String};
-use telegram_bot::{
-    types::{chat::User, InlineKeyboardMarkup},
-    InlineKeyboardButton,
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    string::String,
 };
+use telegram_bot::types::{chat::User, InlineKeyboardMarkup, UserId};
 
-use crate::order::Order;
+use crate::{
+    archive::{format_time, ArchivedOrder},
+    charges::Charge,
+    command::{ItemRequest, StartOptions},
+    ledger::{Ledger, Transfer},
+    menu::{MenuItem, OptionGroup},
+    money::Money,
+    order::{Order, OrderState},
+    schedule::{Recurrence, ScheduledOrder},
+    undo::{Inverse, UndoEntry},
+    wizard::OptionWizard,
+};
+
+/// The maximum number of ended orders kept for each conversation
+const MAX_ARCHIVED_ORDERS: usize = 100;
+
+/// How many minutes after an order ends it may be reopened, such as when the wrong order was ended
+pub const REOPEN_GRACE_MINUTES: i64 = 10;
+
+/// The number of changes which can be undone for each conversation, across all users
+const MAX_UNDO_ENTRIES: usize = 50;
+
+/// The number of option wizards kept for each conversation, as wizards which are never finished are otherwise never removed
+const MAX_OPTION_WIZARDS: usize = 20;
 
-/// Active orders for a conversation
+/// The number of recently ended orders whose participants are reminded to order before a deadline
+const RECENT_ORDERS_FOR_REMINDERS: usize = 10;
+
+/// Active and ended orders for a conversation
+#[derive(Default, Serialize, Deserialize)]
 pub struct ConversationOrders {
     /// active orders for this conversation
     pub orders: HashMap<String, Order>,
+    /// ended orders for this conversation, from oldest to newest
+    #[serde(default)]
+    pub history: Vec<ArchivedOrder>,
+    /// ended orders which some participants have yet to pay for, from oldest to newest
+    #[serde(default)]
+    pub awaiting_payment: Vec<ArchivedOrder>,
+    /// who owes whom for ended orders
+    #[serde(default)]
+    pub ledger: Ledger,
+    /// payments which haven't yet covered a whole order, as who paid, who was paid and how much
+    #[serde(default)]
+    pub credits: Vec<(UserId, UserId, Money)>,
+    /// menus saved for this conversation, by name
+    #[serde(default)]
+    pub menus: BTreeMap<String, Vec<MenuItem>>,
+    /// orders started automatically for this conversation, from oldest to newest
+    #[serde(default)]
+    pub schedules: Vec<ScheduledOrder>,
+    /// changes which can be undone with /undo, from oldest to newest
+    #[serde(default)]
+    pub undo_stack: Vec<UndoEntry>,
+    /// items whose options are being chosen, from oldest to newest
+    #[serde(default)]
+    pub option_wizards: Vec<OptionWizard>,
+    /// the id of the last option wizard started, so that buttons of finished wizards are never mistaken for a new one's
+    #[serde(default)]
+    pub last_option_wizard_id: u64,
+    /// the id of the last order to end, so that the buttons of a reopened order never act on an order which ends later
+    #[serde(default)]
+    pub last_ended_order_id: u64,
+    /// the id of the last scheduled order, so that the buttons of a deleted schedule never delete a newer one
+    #[serde(default)]
+    pub last_schedule_id: u64,
 }
 
 impl ConversationOrders {
     /// Adds an order for this conversation, returning whether the addition was successful
-    pub fn add_order(&mut self, creater: User, order_name: String) -> bool {
-        if self.orders.get(&order_name).is_some() {
+    pub fn insert_order(&mut self, order: Order) -> bool {
+        if self.orders.contains_key(&order.name) {
             false // the order already exists
         } else {
-            self.orders.insert(
-                order_name.clone(),
-                Order {
-                    name: order_name,
-                    items: HashMap::new(),
-                    owner: creater,
-                },
-            );
+            self.orders.insert(order.name.clone(), order);
             true
         }
     }
 
     /// Removes or ends an order for this conversation, returning the removed order on success
-    /// Only the creater of the order may remove it
-    pub fn remove_order(&mut self, _user: &User, order_name: &str) -> Result<Order, String> {
+    /// Only the creater of the order or an administrator of the conversation may remove it
+    pub fn remove_order(
+        &mut self,
+        user: &User,
+        is_admin: bool,
+        order_name: &str,
+    ) -> Result<Order, String> {
         match self.orders.get(order_name) {
-            Some(_order) => Ok(self.orders.remove(order_name).unwrap()),
+            Some(order) if !order.is_managed_by(user) && !is_admin => {
+                Err(if order.co_owners.is_empty() {
+                    format!(
+                        "Only {}, who started {}, or an administrator of this chat may end it.",
+                        order.owner.first_name, order_name
+                    )
+                } else {
+                    format!(
+                        "Only {}, who manage {}, or an administrator of this chat may end it.",
+                        order.managers(),
+                        order_name
+                    )
+                })
+            }
+            Some(_) => Ok(self.orders.remove(order_name).unwrap()),
             None => Err(format!("Order {} not found.", order_name)),
         }
     }
 
+    /// Ends an order on behalf of `ended_by`, archiving it unless some participants have yet to pay for it
+    /// Returns the ended order
+    pub fn end_order(&mut self, mut order: Order, ended_by: &User) -> ArchivedOrder {
+        order.state = OrderState::Closed;
+        // ids keep increasing even though orders may be archived out of order or reopened
+        // orders saved before the counter was kept have ids up to the largest one still kept
+        self.last_ended_order_id = self
+            .last_ended_order_id
+            .max(self.last_ended_order_id(None).unwrap_or(0))
+            + 1;
+        let id = self.last_ended_order_id;
+        let ended_order = ArchivedOrder {
+            id,
+            order,
+            ended_at: Utc::now(),
+            paid: vec![],
+            ended_by: Some(ended_by.id),
+        };
+        for (debtor, amount) in ended_order.unpaid() {
+            self.ledger
+                .record_debt(debtor, &ended_order.order.owner, amount);
+        }
+        self.awaiting_payment.push(ended_order);
+        self.archive_paid_orders();
+        self.find_ended_order(id)
+            .cloned()
+            .expect("the order was just ended")
+    }
+
+    /// Records a change which can be undone, forgetting the oldest changes if there are too many
+    pub fn push_undo(&mut self, entry: UndoEntry) {
+        self.undo_stack.push(entry);
+        if self.undo_stack.len() > MAX_UNDO_ENTRIES {
+            let excess = self.undo_stack.len() - MAX_UNDO_ENTRIES;
+            self.undo_stack.drain(..excess);
+        }
+    }
+
+    /// Reverts the last change the user made which hasn't been undone yet
+    /// Returns the description of the change, and the order it affected unless the order was discarded
+    pub fn undo(
+        &mut self,
+        user: &User,
+        now: DateTime<Utc>,
+    ) -> Result<(String, Option<Order>), String> {
+        let index = self
+            .undo_stack
+            .iter()
+            .rposition(|entry| entry.user == user.id)
+            .ok_or_else(|| "You have nothing to undo.".to_string())?;
+        let UndoEntry {
+            description,
+            inverse,
+            order_started_at,
+            ..
+        } = self.undo_stack.remove(index);
+        // a later order with the same name is a different order, whose changes can't be reverted
+        let is_changed_order = |order: &Order| {
+            order_started_at.is_none_or(|started_at| order.started_at == started_at)
+        };
+        let not_found = |order_name: &str| {
+            format!(
+                "{} has ended, so {} can't be undone.",
+                order_name, description
+            )
+        };
+        let order = match inverse {
+            Inverse::RestoreItems {
+                order_name,
+                quantities,
+                recipient,
+                added_by,
+                notes,
+                options,
+            } => {
+                let order = self
+                    .orders
+                    .get_mut(&order_name)
+                    .filter(|order| is_changed_order(order))
+                    .ok_or_else(|| not_found(&order_name))?;
+                order.check_open()?;
+                let owner = recipient.as_ref().unwrap_or(user);
+                order.restore_user_quantities(owner, &quantities, added_by.as_ref());
+                for (item, note) in notes {
+                    order.set_note(owner, &item, Some(note));
+                }
+                for (item, selections) in options {
+                    order.set_options(owner, &item, selections);
+                }
+                Some(order.clone())
+            }
+            Inverse::RestorePrice {
+                order_name,
+                item,
+                price,
+            } => {
+                let order = self
+                    .orders
+                    .get_mut(&order_name)
+                    .filter(|order| is_changed_order(order))
+                    .ok_or_else(|| not_found(&order_name))?;
+                match price {
+                    Some(price) => order.set_price(&item, price),
+                    None => order.remove_price(&item),
+                }
+                Some(order.clone())
+            }
+            Inverse::RestoreCharges {
+                order_name,
+                charges,
+            } => {
+                let order = self
+                    .orders
+                    .get_mut(&order_name)
+                    .filter(|order| is_changed_order(order))
+                    .ok_or_else(|| not_found(&order_name))?;
+                order.charges = charges;
+                Some(order.clone())
+            }
+            Inverse::RemoveOrder { order_name } => {
+                let order = self
+                    .orders
+                    .get(&order_name)
+                    .filter(|order| is_changed_order(order))
+                    .ok_or_else(|| not_found(&order_name))?;
+                if order
+                    .participants()
+                    .iter()
+                    .any(|participant| participant.id != user.id)
+                {
+                    return Err(format!(
+                        "Others have ordered from {} since you started it, so use /end {} instead.",
+                        order_name, order_name
+                    ));
+                }
+                self.orders.remove(&order_name);
+                None
+            }
+            Inverse::ReopenOrder { id } => Some(self.reopen_order(user, id, now)?),
+        };
+        Ok((description, order))
+    }
+
+    /// Returns the id of the most recently ended order, optionally only considering orders with the given name
+    pub fn last_ended_order_id(&self, order_name: Option<&str>) -> Option<u64> {
+        self.history
+            .iter()
+            .chain(&self.awaiting_payment)
+            .filter(|ended_order| order_name.is_none_or(|name| ended_order.order.name == name))
+            .map(|ended_order| ended_order.id)
+            .max()
+    }
+
+    /// Restores an order which ended no more than REOPEN_GRACE_MINUTES before `now`, cancelling the debts recorded when it ended
+    /// Only those who manage the order or whoever ended it may reopen it
+    /// Returns the reopened order, which accepts items again
+    pub fn reopen_order(
+        &mut self,
+        user: &User,
+        id: u64,
+        now: DateTime<Utc>,
+    ) -> Result<Order, String> {
+        let ended_order = self
+            .find_ended_order(id)
+            .ok_or_else(|| "This order is no longer kept, so it can't be reopened.".to_string())?;
+        let order_name = ended_order.order.name.clone();
+        if !ended_order.order.is_managed_by(user) && ended_order.ended_by != Some(user.id) {
+            return Err(format!(
+                "Only {} or whoever ended {} may reopen it.",
+                ended_order.order.managers(),
+                order_name
+            ));
+        }
+        if now - ended_order.ended_at > Duration::minutes(REOPEN_GRACE_MINUTES) {
+            return Err(format!(
+                "{} ended more than {} minutes ago, so it can't be reopened. Use /start again {} to start a new order with the same items.",
+                order_name, REOPEN_GRACE_MINUTES, order_name
+            ));
+        }
+        // reopening cancels what everyone owes, which would undo payments recorded against the order
+        if !ended_order.paid.is_empty() {
+            let payers: Vec<&str> = ended_order
+                .debts()
+                .into_iter()
+                .filter(|(debtor, _)| ended_order.paid.contains(&debtor.id))
+                .map(|(debtor, _)| debtor.first_name.as_str())
+                .collect();
+            return Err(format!(
+                "{} already paid for {}, so it can't be reopened. Use /start again {} to start a new order with the same items.",
+                payers.join(", "),
+                order_name,
+                order_name
+            ));
+        }
+        if self.orders.contains_key(&order_name) {
+            return Err(format!(
+                "There is already an order for {} in progress, so the ended one can't be reopened.",
+                order_name
+            ));
+        }
+        let ended_order = match self
+            .awaiting_payment
+            .iter()
+            .position(|ended_order| ended_order.id == id)
+        {
+            Some(index) => self.awaiting_payment.remove(index),
+            None => {
+                let index = self
+                    .history
+                    .iter()
+                    .position(|ended_order| ended_order.id == id)
+                    .expect("the order was just found");
+                self.history.remove(index)
+            }
+        };
+        // paying back each debt cancels it, as nobody has paid yet
+        for (debtor, amount) in ended_order.debts() {
+            self.ledger
+                .record_payment(debtor, &ended_order.order.owner, amount);
+        }
+        let mut order = ended_order.order;
+        order.state = OrderState::Open;
+        // the order would otherwise be ended again straight away
+        if order.deadline.is_some_and(|deadline| deadline <= now) {
+            order.set_deadline(None);
+        }
+        self.orders.insert(order_name, order.clone());
+        Ok(order)
+    }
+
+    /// Records that the user has paid for an order awaiting payment, archiving it once everyone has paid
+    /// Returns the updated order
+    pub fn mark_paid(&mut self, id: u64, user: &User) -> Result<ArchivedOrder, String> {
+        let index = match self
+            .awaiting_payment
+            .iter()
+            .position(|ended_order| ended_order.id == id)
+        {
+            Some(index) => index,
+            None => return Err("Everyone has already paid for this order.".into()),
+        };
+        let ended_order = &mut self.awaiting_payment[index];
+        let amount = match ended_order
+            .unpaid()
+            .iter()
+            .find(|(debtor, _)| debtor.id == user.id)
+        {
+            Some((_, amount)) => *amount,
+            None => {
+                return Err(format!(
+                    "You don't owe {} anything for {}.",
+                    ended_order.order.owner.first_name, ended_order.order.name
+                ))
+            }
+        };
+        self.ledger
+            .record_payment(user, &ended_order.order.owner, amount);
+        ended_order.paid.push(user.id);
+        self.archive_paid_orders();
+        Ok(self
+            .find_ended_order(id)
+            .cloned()
+            .expect("orders are archived rather than removed once paid for"))
+    }
+
+    /// Records a payment suggested by the ledger, returning what was paid
+    pub fn record_transfer(
+        &mut self,
+        from: UserId,
+        to: UserId,
+        amount: Money,
+    ) -> Result<Transfer, String> {
+        let transfer = self
+            .ledger
+            .record_transfer(from, to, amount)
+            .ok_or_else(|| "This payment has already been settled.".to_string())?;
+        if self.ledger.outstanding_balances().is_empty() {
+            // everyone has been paid what they are owed overall, however the payments were made
+            for ended_order in self.awaiting_payment.iter_mut() {
+                let debtors: Vec<UserId> = ended_order
+                    .unpaid()
+                    .iter()
+                    .map(|(debtor, _)| debtor.id)
+                    .collect();
+                ended_order.paid.extend(debtors);
+            }
+            self.credits.clear();
+        } else {
+            self.settle_orders_between(transfer.from.id, transfer.to.id, transfer.amount);
+        }
+        self.archive_paid_orders();
+        Ok(transfer)
+    }
+
+    /// Marks the orders `from` owes `to` for as paid, from oldest to newest, as far as a payment of `amount` covers them
+    /// Earlier payments which didn't cover a whole order count towards the payment, and whatever isn't used is kept for later
+    /// What `to` owes `from` for other orders counts towards the payment, and is marked as paid as far as it was used
+    fn settle_orders_between(&mut self, from: UserId, to: UserId, amount: Money) {
+        let paid = amount + self.take_credit(from, to);
+        let paid_back = self.take_credit(to, from);
+        if paid_back >= paid {
+            self.add_credit(to, from, paid_back - paid);
+            return;
+        }
+        let paid = paid - paid_back;
+        let debt = |ended_order: &ArchivedOrder, debtor: UserId, owner: UserId| {
+            if ended_order.order.owner.id != owner {
+                return None;
+            }
+            ended_order
+                .unpaid()
+                .iter()
+                .find(|(user, _)| user.id == debtor)
+                .map(|(_, amount)| *amount)
+        };
+        let owed_back: Money = self
+            .awaiting_payment
+            .iter()
+            .filter_map(|ended_order| debt(ended_order, to, from))
+            .sum();
+        let mut available = paid + owed_back;
+        let mut covered = Money::default();
+        for ended_order in self.awaiting_payment.iter_mut() {
+            match debt(ended_order, from, to) {
+                Some(owed) if owed <= available => {
+                    available = available - owed;
+                    covered += owed;
+                    ended_order.paid.push(from);
+                }
+                _ => (),
+            }
+        }
+        if covered <= paid {
+            self.add_credit(from, to, paid - covered);
+            return;
+        }
+        // debts beyond the payment were covered by what `to` owed back, which is settled in turn
+        let mut offset = covered - paid;
+        for ended_order in self.awaiting_payment.iter_mut() {
+            match debt(ended_order, to, from) {
+                Some(owed) if owed <= offset => {
+                    offset = offset - owed;
+                    ended_order.paid.push(to);
+                }
+                _ => (),
+            }
+        }
+        // what was used but didn't cover a whole order counts as `to` having paid `from`
+        self.add_credit(to, from, offset);
+    }
+
+    /// Removes and returns what `from` paid `to` without covering a whole order
+    fn take_credit(&mut self, from: UserId, to: UserId) -> Money {
+        match self
+            .credits
+            .iter()
+            .position(|&(payer, payee, _)| payer == from && payee == to)
+        {
+            Some(index) => self.credits.remove(index).2,
+            None => Money::default(),
+        }
+    }
+
+    /// Records that `from` paid `to` an amount which didn't cover a whole order
+    fn add_credit(&mut self, from: UserId, to: UserId, amount: Money) {
+        if amount.is_zero() {
+            return;
+        }
+        match self
+            .credits
+            .iter_mut()
+            .find(|(payer, payee, _)| *payer == from && *payee == to)
+        {
+            Some(credit) => credit.2 += amount,
+            None => self.credits.push((from, to, amount)),
+        }
+    }
+
+    /// Archives orders which everyone has paid for
+    fn archive_paid_orders(&mut self) {
+        let (settled_orders, awaiting_payment) = self
+            .awaiting_payment
+            .drain(..)
+            .partition(|ended_order| ended_order.unpaid().is_empty());
+        self.awaiting_payment = awaiting_payment;
+        for settled_order in settled_orders {
+            self.archive_order(settled_order);
+        }
+    }
+
+    /// Returns the ended order with the given id, whether or not everyone has paid for it
+    fn find_ended_order(&self, id: u64) -> Option<&ArchivedOrder> {
+        self.awaiting_payment
+            .iter()
+            .chain(self.history.iter().rev())
+            .find(|ended_order| ended_order.id == id)
+    }
+
+    /// Returns the orders awaiting payment which the user has yet to pay for, from oldest to newest
+    pub fn find_unpaid_orders(&self, user: &User) -> Vec<&ArchivedOrder> {
+        self.awaiting_payment
+            .iter()
+            .filter(|ended_order| {
+                ended_order
+                    .unpaid()
+                    .iter()
+                    .any(|(debtor, _)| debtor.id == user.id)
+            })
+            .collect()
+    }
+
+    /// Keeps an ended order in this conversation's history, discarding the oldest ones if there are too many
+    fn archive_order(&mut self, archived_order: ArchivedOrder) {
+        self.history.push(archived_order);
+        if self.history.len() > MAX_ARCHIVED_ORDERS {
+            let excess = self.history.len() - MAX_ARCHIVED_ORDERS;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Returns up to `count` archived orders, from newest to oldest
+    pub fn recent_history(&self, count: usize) -> Vec<&ArchivedOrder> {
+        self.history.iter().rev().take(count).collect()
+    }
+
+    /// Returns the most recently ended order with the given name, if any
+    pub fn find_last_archived_order(&self, order_name: &str) -> Option<&ArchivedOrder> {
+        self.history
+            .iter()
+            .rev()
+            .find(|archived| archived.order.name == order_name)
+    }
+
+    /// Returns the archived order with the given id, if it is still kept
+    pub fn find_archived_order(&self, id: u64) -> Option<&ArchivedOrder> {
+        self.history.iter().find(|archived| archived.id == id)
+    }
+
     /// Adds an item to the specified order, returning the Order that was just updated
-    pub fn add_item(&mut self, order_name: &str, user: User, item: String) -> Option<Order> {
+    /// The item's price is updated if the request includes one
+    /// `added_by` is whoever ordered the item for the user, if someone else did
+    pub fn add_item(
+        &mut self,
+        order_name: &str,
+        user: User,
+        request: ItemRequest,
+        added_by: Option<User>,
+    ) -> Option<Order> {
         match self.orders.get_mut(order_name) {
             Some(order) => {
-                let _overrode_previous_order = order.add_item(user, item.clone());
+                if let Some(price) = request.price {
+                    order.set_price(&request.item, price);
+                }
+                // ordering an item again without choosing options, such as to change its quantity, keeps those chosen
+                let options = if request.options.is_empty() {
+                    order
+                        .items
+                        .get(&request.item)
+                        .and_then(|entries| entries.iter().find(|entry| entry.user.id == user.id))
+                        .map_or(vec![], |entry| entry.options.clone())
+                } else {
+                    request.options
+                };
+                let _overrode_previous_order = order.add_item_for(
+                    user.clone(),
+                    request.item.clone(),
+                    request.quantity,
+                    added_by,
+                );
+                order.set_note(&user, &request.item, request.note);
+                order.set_options(&user, &request.item, options);
                 Some(order.clone())
             }
             None => None, // the order we're trying to add an item to does not exist
         }
     }
 
-    /// Removes a user's item from the order, returning the item that was just removed
-    pub fn remove_item(&mut self, order_name: &str, user: &User) -> Option<Order> {
+    /// Changes the quantity of an item the user ordered, returning the updated Order and the new quantity
+    /// Returns None if the order doesn't exist, or the user tried reducing the quantity of an item they didn't order
+    pub fn adjust_quantity(
+        &mut self,
+        order_name: &str,
+        user: User,
+        item: &str,
+        delta: i32,
+    ) -> Option<(Order, u32)> {
+        let order = self.orders.get_mut(order_name)?;
+        let quantity = order.adjust_quantity(user, item, delta)?;
+        Some((order.clone(), quantity))
+    }
+
+    /// Removes a user's item from the order, returning the Order that was just updated
+    /// If no item is specified, the item the user ordered is removed
+    pub fn remove_item(
+        &mut self,
+        order_name: &str,
+        user: &User,
+        item: Option<&str>,
+    ) -> Option<Order> {
         match self.orders.get_mut(order_name) {
             Some(order) => {
-                if let Some(_item_removed) = order.remove_item(user) {
-                    Some(self.orders[order_name].clone())
+                let removed = match item {
+                    Some(item) => order.remove_user_item(user, item),
+                    None => order.remove_item(user).is_some(),
+                };
+                if removed {
+                    Some(order.clone())
                 } else {
                     None // the user did not order this
                 }
@@ -64,17 +629,195 @@ impl ConversationOrders {
         }
     }
 
-    /// Returns inline keyboard buttons which users can click to order an existing item
-    pub fn generate_reply_markup(&self) -> InlineKeyboardMarkup {
-        let buttons: Vec<InlineKeyboardButton> = self
+    /// Sets the price of an item in the specified order, returning the Order that was just updated
+    pub fn set_price(&mut self, order_name: &str, item: &str, price: Money) -> Option<Order> {
+        let order = self.orders.get_mut(order_name)?;
+        order.set_price(item, price);
+        Some(order.clone())
+    }
+
+    /// Changes one of the charges of the specified order, returning the Order that was just updated
+    pub fn set_charge(&mut self, order_name: &str, charge: Charge) -> Option<Order> {
+        let order = self.orders.get_mut(order_name)?;
+        order.charges.apply(charge);
+        Some(order.clone())
+    }
+
+    /// Sets or removes the time at which an order is automatically ended, returning the Order that was just updated
+    pub fn set_deadline(
+        &mut self,
+        order_name: &str,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Option<Order> {
+        let order = self.orders.get_mut(order_name)?;
+        order.set_deadline(deadline);
+        Some(order.clone())
+    }
+
+    /// Sets how many minutes before an order's deadline reminders are posted, returning the Order that was just updated
+    pub fn set_reminders(&mut self, order_name: &str, reminders: Vec<u32>) -> Option<Order> {
+        let order = self.orders.get_mut(order_name)?;
+        order.reminders = reminders;
+        order.reminders_sent.clear();
+        Some(order.clone())
+    }
+
+    /// Returns the orders for which a reminder is due at `now` and the minutes left until their deadlines, sorted by name
+    /// The reminders are marked as posted
+    pub fn take_due_reminders(&mut self, now: DateTime<Utc>) -> Vec<(Order, i64)> {
+        let mut due: Vec<(Order, i64)> = self
+            .orders
+            .values_mut()
+            .filter_map(|order| {
+                let minutes_left = order.take_due_reminder(now)?;
+                Some((order.clone(), minutes_left))
+            })
+            .collect();
+        due.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        due
+    }
+
+    /// Starts choosing the options of an item, returning the wizard which was started
+    /// The oldest wizards are forgotten once there are more than MAX_OPTION_WIZARDS
+    pub fn start_option_wizard(
+        &mut self,
+        user: User,
+        order_name: &str,
+        item: &str,
+        groups: Vec<OptionGroup>,
+    ) -> &OptionWizard {
+        self.last_option_wizard_id += 1;
+        self.option_wizards.push(OptionWizard::new(
+            self.last_option_wizard_id,
+            user,
+            order_name.to_string(),
+            item.to_string(),
+            groups,
+        ));
+        if self.option_wizards.len() > MAX_OPTION_WIZARDS {
+            self.option_wizards.remove(0);
+        }
+        self.option_wizards.last().unwrap()
+    }
+
+    /// Finds a user who has taken part in this conversation's orders by their username or first name, ignoring case
+    /// Telegram doesn't let bots look up users, so only users seen in orders can be found
+    pub fn find_user(&self, name: &str) -> Option<&User> {
+        let name = name.trim_start_matches('@').to_lowercase();
+        let ended_orders = self.history.iter().chain(&self.awaiting_payment);
+        let orders = self
             .orders
             .values()
-            .map(|order| order.generate_inline_buttons())
-            .flatten()
+            .chain(ended_orders.map(|ended_order| &ended_order.order));
+        let mut users = orders.flat_map(|order| {
+            std::iter::once(&order.owner)
+                .chain(&order.co_owners)
+                .chain(order.participants())
+        });
+        users.find(|user| {
+            user.username
+                .as_ref()
+                .is_some_and(|username| username.to_lowercase() == name)
+                || user.first_name.to_lowercase() == name
+        })
+    }
+
+    /// Returns the users who took part in recently ended orders, sorted by name
+    pub fn recent_participants(&self) -> Vec<&User> {
+        let mut ended_orders: Vec<&ArchivedOrder> =
+            self.history.iter().chain(&self.awaiting_payment).collect();
+        ended_orders.sort_by_key(|ended_order| ended_order.id);
+        let mut participants: Vec<&User> = ended_orders
+            .iter()
+            .rev()
+            .take(RECENT_ORDERS_FOR_REMINDERS)
+            .flat_map(|ended_order| ended_order.order.participants())
             .collect();
+        participants.sort_by(|a, b| (&a.first_name, a.id).cmp(&(&b.first_name, b.id)));
+        participants.dedup_by_key(|user| user.id);
+        participants
+    }
+
+    /// Returns the names of orders whose deadline is at or before `now`, sorted by name
+    pub fn overdue_orders(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .orders
+            .values()
+            .filter(|order| order.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|order| order.name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Allows or disallows ordering several items in an order, returning whether multiple items are now allowed
+    pub fn toggle_multiple_items(&mut self, order_name: &str) -> Option<bool> {
+        let order = self.orders.get_mut(order_name)?;
+        order.allow_multiple_items = !order.allow_multiple_items;
+        Some(order.allow_multiple_items)
+    }
+
+    /// Limits ordering to the menu of an order or allows any item again, returning whether ordering is now limited
+    /// Returns None if the order doesn't exist
+    pub fn toggle_menu_only(&mut self, order_name: &str) -> Option<bool> {
+        let order = self.orders.get_mut(order_name)?;
+        order.menu_only = !order.menu_only;
+        Some(order.menu_only)
+    }
+
+    /// Schedules an order to be started whenever it is due after `now`, returning the new schedule
+    pub fn add_schedule(
+        &mut self,
+        owner: User,
+        order_name: String,
+        recurrence: Recurrence,
+        options: StartOptions,
+        now: DateTime<Utc>,
+    ) -> &ScheduledOrder {
+        // schedules saved before the counter was kept have ids up to the largest one still kept
+        self.last_schedule_id = self
+            .schedules
+            .iter()
+            .map(|s| s.id)
+            .fold(self.last_schedule_id, u64::max)
+            + 1;
+        self.schedules.push(ScheduledOrder {
+            id: self.last_schedule_id,
+            order_name,
+            next_start: recurrence.next_after(now),
+            recurrence,
+            options,
+            owner,
+        });
+        self.schedules.last().unwrap()
+    }
+
+    /// Removes a scheduled order, returning it if it exists
+    pub fn remove_schedule(&mut self, id: u64) -> Option<ScheduledOrder> {
+        let index = self.schedules.iter().position(|s| s.id == id)?;
+        Some(self.schedules.remove(index))
+    }
+
+    /// Returns the scheduled orders which should be started at `now`, moving their next starts past `now`
+    pub fn take_due_schedules(&mut self, now: DateTime<Utc>) -> Vec<ScheduledOrder> {
+        let mut due = vec![];
+        for schedule in self.schedules.iter_mut() {
+            if schedule.take_due_start(now) {
+                due.push(schedule.clone());
+            }
+        }
+        due
+    }
+
+    /// Returns inline keyboard buttons which users can click to order an existing item
+    pub fn generate_reply_markup(&self) -> InlineKeyboardMarkup {
         let mut keyboard_markup = InlineKeyboardMarkup::new();
-        for row in buttons.chunks(2) {
-            keyboard_markup.add_row(row.to_vec());
+        for row in self
+            .orders
+            .values()
+            .flat_map(|order| order.generate_inline_buttons())
+        {
+            keyboard_markup.add_row(row);
         }
         keyboard_markup
     }
@@ -88,7 +831,10 @@ impl fmt::Display for ConversationOrders {
             let orders_to_display: Vec<String> = self
                 .orders
                 .values()
-                .map(|order| format!("{}", order))
+                .map(|order| match order.deadline {
+                    Some(deadline) => format!("{}\nEnds at {}.", order, format_time(&deadline)),
+                    None => format!("{}", order),
+                })
                 .collect();
             let header = if orders_to_display.len() > 1 {
                 format!("There are {} orders.\n", orders_to_display.len())
@@ -99,3 +845,217 @@ impl fmt::Display for ConversationOrders {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::user;
+
+    /// Starts and ends an order owned by `owner`, in which each debtor ordered an item with the given price in cents
+    fn end_order(
+        conversation_orders: &mut ConversationOrders,
+        name: &str,
+        owner: &User,
+        debts: &[(&User, i64)],
+    ) -> u64 {
+        conversation_orders.insert_order(Order::new(name.into(), owner.clone()));
+        for &(debtor, cents) in debts {
+            let request = ItemRequest {
+                price: Some(Money::from_cents(cents)),
+                ..ItemRequest::new(&format!("{} item", debtor.first_name))
+            };
+            conversation_orders.add_item(name, debtor.clone(), request, None);
+        }
+        let order = conversation_orders.orders.remove(name).unwrap();
+        conversation_orders.end_order(order, owner).id
+    }
+
+    /// Returns the names of those who have yet to pay for an order, or None once it has been archived
+    fn unpaid(conversation_orders: &ConversationOrders, id: u64) -> Option<Vec<String>> {
+        conversation_orders
+            .awaiting_payment
+            .iter()
+            .find(|ended_order| ended_order.id == id)
+            .map(|ended_order| {
+                ended_order
+                    .unpaid()
+                    .iter()
+                    .map(|(debtor, _)| debtor.first_name.clone())
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn transfers_settle_the_orders_they_cover() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        let mut conversation_orders = ConversationOrders::default();
+        let breakfast = end_order(
+            &mut conversation_orders,
+            "breakfast",
+            &alice,
+            &[(&bob, 500), (&carol, 400)],
+        );
+        let lunch = end_order(&mut conversation_orders, "lunch", &alice, &[(&bob, 300)]);
+        let dinner = end_order(&mut conversation_orders, "dinner", &bob, &[(&alice, 200)]);
+        let snacks = end_order(&mut conversation_orders, "snacks", &alice, &[(&bob, 100)]);
+
+        conversation_orders
+            .record_transfer(bob.id, alice.id, Money::from_cents(400))
+            .unwrap();
+        assert_eq!(
+            unpaid(&conversation_orders, breakfast),
+            Some(vec!["Carol".to_string()]),
+            "what is owed back counts towards the payment"
+        );
+        assert_eq!(
+            unpaid(&conversation_orders, lunch),
+            Some(vec!["Bob".to_string()]),
+            "orders which the rest of the payment doesn't cover are still unpaid"
+        );
+        assert_eq!(
+            unpaid(&conversation_orders, snacks),
+            None,
+            "newer orders are settled if older ones aren't covered"
+        );
+        assert_eq!(
+            unpaid(&conversation_orders, dinner),
+            None,
+            "what is owed back is settled as far as it was used"
+        );
+
+        conversation_orders
+            .record_transfer(bob.id, alice.id, Money::from_cents(200))
+            .unwrap();
+        assert_eq!(
+            unpaid(&conversation_orders, lunch),
+            Some(vec!["Bob".to_string()]),
+            "partial payments don't settle an order"
+        );
+        conversation_orders
+            .record_transfer(bob.id, alice.id, Money::from_cents(100))
+            .unwrap();
+        assert_eq!(
+            unpaid(&conversation_orders, lunch),
+            None,
+            "partial payments add up"
+        );
+        assert_eq!(
+            unpaid(&conversation_orders, breakfast),
+            Some(vec!["Carol".to_string()])
+        );
+
+        conversation_orders
+            .record_transfer(carol.id, alice.id, Money::from_cents(400))
+            .unwrap();
+        assert!(conversation_orders.awaiting_payment.is_empty());
+        assert!(conversation_orders.ledger.outstanding_balances().is_empty());
+    }
+
+    #[test]
+    fn changes_to_ended_orders_are_not_undone() {
+        let alice = user(1, "Alice");
+        let mut conversation_orders = ConversationOrders::default();
+        conversation_orders.insert_order(Order::new("waffles".into(), alice.clone()));
+        conversation_orders.add_item("waffles", alice.clone(), ItemRequest::new("plain"), None);
+        let order = &conversation_orders.orders["waffles"];
+        let (inverse, started_at) = (
+            Inverse::restore_items(order, &alice, None),
+            order.started_at,
+        );
+        conversation_orders.add_item(
+            "waffles",
+            alice.clone(),
+            ItemRequest::new("chocolate"),
+            None,
+        );
+        conversation_orders.push_undo(UndoEntry {
+            user: alice.id,
+            description: "ordering chocolate for waffles".into(),
+            inverse,
+            order_started_at: Some(started_at),
+        });
+
+        let order = conversation_orders
+            .remove_order(&alice, false, "waffles")
+            .unwrap();
+        conversation_orders.end_order(order, &alice);
+        let mut restarted = Order::new("waffles".into(), alice.clone());
+        restarted.started_at = started_at + Duration::minutes(30);
+        conversation_orders.insert_order(restarted);
+        assert_eq!(
+            conversation_orders.undo(&alice, Utc::now()).err(),
+            Some("waffles has ended, so ordering chocolate for waffles can't be undone.".into())
+        );
+        assert!(
+            conversation_orders.orders["waffles"].items.is_empty(),
+            "items of the ended order aren't restored into the new one"
+        );
+    }
+
+    #[test]
+    fn orders_are_only_reopened_before_anyone_pays() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        let mut conversation_orders = ConversationOrders::default();
+        conversation_orders.insert_order(Order::new("waffles".into(), alice.clone()));
+        for debtor in [&bob, &carol] {
+            let request = ItemRequest {
+                price: Some(Money::from_cents(500)),
+                ..ItemRequest::new("chocolate")
+            };
+            conversation_orders.add_item("waffles", debtor.clone(), request, None);
+        }
+        let order = conversation_orders.orders.remove("waffles").unwrap();
+        let id = conversation_orders.end_order(order, &alice).id;
+        conversation_orders.mark_paid(id, &bob).unwrap();
+        assert_eq!(
+            conversation_orders.reopen_order(&alice, id, Utc::now()).err(),
+            Some("Bob already paid for waffles, so it can't be reopened. Use /start again waffles to start a new order with the same items.".into())
+        );
+        let unpaid: Vec<&str> = conversation_orders.awaiting_payment[0]
+            .unpaid()
+            .iter()
+            .map(|(debtor, _)| debtor.first_name.as_str())
+            .collect();
+        assert_eq!(unpaid, vec!["Carol"]);
+        let balances: Vec<(&str, Money)> = conversation_orders
+            .ledger
+            .outstanding_balances()
+            .iter()
+            .map(|balance| (balance.user.first_name.as_str(), balance.amount))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![
+                ("Alice", Money::from_cents(500)),
+                ("Carol", Money::from_cents(-500))
+            ],
+            "balances agree with who has yet to pay"
+        );
+
+        let mut conversation_orders = ConversationOrders::default();
+        conversation_orders.insert_order(Order::new("waffles".into(), alice.clone()));
+        let request = ItemRequest {
+            price: Some(Money::from_cents(500)),
+            ..ItemRequest::new("chocolate")
+        };
+        conversation_orders.add_item("waffles", bob.clone(), request, None);
+        let order = conversation_orders.orders.remove("waffles").unwrap();
+        let id = conversation_orders.end_order(order, &alice).id;
+        conversation_orders
+            .reopen_order(&alice, id, Utc::now())
+            .unwrap();
+        assert!(conversation_orders.ledger.outstanding_balances().is_empty());
+        let order = conversation_orders.orders.remove("waffles").unwrap();
+        let id = conversation_orders.end_order(order, &alice).id;
+        conversation_orders.mark_paid(id, &bob).unwrap();
+        assert!(
+            conversation_orders.ledger.outstanding_balances().is_empty(),
+            "debts cancelled by reopening aren't counted again"
+        );
+        assert!(conversation_orders.awaiting_payment.is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use chrono::{DateTime, Datelike, Duration, Local, LocalResult, NaiveTime, TimeZone, Utc, Weekday};
+
+/// Parses a time of day such as 11:30, 9am or 2:30pm
+pub fn parse_time(s: &str) -> Result<NaiveTime, String> {
+    let error = || format!("{} is not a valid time. For example, use 11:30", s);
+    let (time, hour_offset) = if let Some(time) = s.strip_suffix("am") {
+        (time, Some(0))
+    } else if let Some(time) = s.strip_suffix("pm") {
+        (time, Some(12))
+    } else {
+        (s, None)
+    };
+    let (hours, minutes) = match time.find(':') {
+        Some(sep) => (&time[..sep], &time[sep + 1..]),
+        // a time without minutes is only accepted with am or pm, so that numbers aren't mistaken for times
+        None if hour_offset.is_some() => (time, "0"),
+        None => return Err(error()),
+    };
+    if minutes.len() > 2 {
+        return Err(error());
+    }
+    let mut hours: u32 = hours.parse().map_err(|_| error())?;
+    let minutes: u32 = minutes.parse().map_err(|_| error())?;
+    if let Some(offset) = hour_offset {
+        if !(1..=12).contains(&hours) {
+            return Err(error());
+        }
+        // 12am is midnight and 12pm is noon
+        hours = hours % 12 + offset;
+    }
+    NaiveTime::from_hms_opt(hours, minutes, 0).ok_or_else(error)
+}
+
+/// Returns the next time it is `time` in the bot's local timezone, after `now`
+pub fn next_occurrence(time: NaiveTime, now: DateTime<Utc>) -> DateTime<Utc> {
+    next_occurrence_on(time, &[], now)
+}
+
+/// Returns the next time it is `time` on one of `days` in the bot's local timezone, after `now`
+/// Every day is allowed if `days` is empty
+pub fn next_occurrence_on(time: NaiveTime, days: &[Weekday], now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.with_timezone(&Local).date_naive();
+    let mut date = today;
+    loop {
+        if !days.is_empty() && !days.contains(&date.weekday()) {
+            date += Duration::days(1);
+            continue;
+        }
+        let candidate = match Local.from_local_datetime(&date.and_time(time)) {
+            LocalResult::Single(candidate) | LocalResult::Ambiguous(candidate, _) => {
+                Some(candidate.with_timezone(&Utc))
+            }
+            // the time is skipped on this day, as clocks go forward
+            LocalResult::None => None,
+        };
+        match candidate {
+            Some(candidate) if candidate > now => return candidate,
+            _ => date += Duration::days(1),
+        }
+    }
+}
+
+/// Returns a fixed time in the bot's local timezone, so that tests don't depend on when they are run
+#[cfg(test)]
+pub fn local_time(hour: u32, minute: u32) -> DateTime<Utc> {
+    // a Friday, far from daylight saving changes
+    Local
+        .with_ymd_and_hms(2024, 1, 12, hour, minute, 0)
+        .single()
+        .expect("the time exists in every timezone")
+        .with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let time = |h, m| Ok(NaiveTime::from_hms_opt(h, m, 0).unwrap());
+        assert_eq!(parse_time("11:30"), time(11, 30));
+        assert_eq!(parse_time("9:05"), time(9, 5));
+        assert_eq!(parse_time("2:30pm"), time(14, 30));
+        assert_eq!(parse_time("9am"), time(9, 0));
+        assert_eq!(parse_time("12am"), time(0, 0));
+        assert_eq!(parse_time("12pm"), time(12, 0));
+        assert_eq!(
+            parse_time("11"),
+            Err("11 is not a valid time. For example, use 11:30".into())
+        );
+        assert!(parse_time("25:00").is_err());
+        assert!(parse_time("13pm").is_err());
+        assert!(parse_time("11:300").is_err());
+    }
+
+    #[test]
+    fn next_occurrence_is_in_the_future() {
+        let now = local_time(12, 0);
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(next_occurrence(time(12, 1), now), local_time(12, 1));
+        assert_eq!(
+            next_occurrence(time(11, 59), now),
+            local_time(11, 59) + Duration::days(1)
+        );
+        assert_eq!(
+            next_occurrence(time(12, 0), now),
+            now + Duration::days(1),
+            "the current time is already past"
+        );
+    }
+
+    #[test]
+    fn next_occurrence_on_is_on_one_of_the_days() {
+        let now = local_time(12, 0);
+        let earlier = NaiveTime::from_hms_opt(11, 59, 0).unwrap();
+        assert_eq!(
+            next_occurrence_on(earlier, &[Weekday::Fri], now),
+            local_time(11, 59) + Duration::days(7)
+        );
+        assert_eq!(
+            next_occurrence_on(earlier, &[Weekday::Sat], now),
+            local_time(11, 59) + Duration::days(1)
+        );
+        assert_eq!(
+            next_occurrence_on(earlier, &[Weekday::Mon, Weekday::Thu], now),
+            local_time(11, 59) + Duration::days(3)
+        );
+    }
+}
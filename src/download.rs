@@ -0,0 +1,62 @@
+use futures::{future, Future, Stream};
+use hyper::{Body, Client, Uri};
+use hyper_tls::HttpsConnector;
+use std::fmt;
+
+/// Why a file couldn't be downloaded
+#[derive(Debug)]
+pub enum DownloadError {
+    /// the file is larger than the most which may be downloaded
+    TooLarge,
+    Failed(String),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::TooLarge => write!(f, "The file is too large to download."),
+            DownloadError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Downloads the contents of a file, such as a document sent to the bot, failing once it exceeds `max_size` bytes
+/// Errors are described without the URL, since URLs of Telegram files contain the bot's token
+pub fn download(
+    url: &str,
+    max_size: usize,
+) -> Box<dyn Future<Item = Vec<u8>, Error = DownloadError>> {
+    let uri: Uri = match url.parse() {
+        Ok(uri) => uri,
+        Err(_) => {
+            return Box::new(future::err(DownloadError::Failed(
+                "The file's address is not valid.".to_string(),
+            )))
+        }
+    };
+    let connector = match HttpsConnector::new(1) {
+        Ok(connector) => connector,
+        Err(e) => {
+            return Box::new(future::err(DownloadError::Failed(format!(
+                "TLS error: {}",
+                e
+            ))))
+        }
+    };
+    let client = Client::builder().build::<_, Body>(connector);
+    let failed =
+        |e: hyper::Error| DownloadError::Failed(format!("Failed to download the file: {}", e));
+    Box::new(client.get(uri).map_err(failed).and_then(move |response| {
+        // the size reported by Telegram may be missing, so the body is measured as it arrives
+        response
+            .into_body()
+            .map_err(failed)
+            .fold(vec![], move |mut body, chunk| {
+                if body.len() + chunk.len() > max_size {
+                    return Err(DownloadError::TooLarge);
+                }
+                body.extend_from_slice(&chunk);
+                Ok(body)
+            })
+    }))
+}
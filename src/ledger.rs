@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use telegram_bot::types::{chat::User, UserId};
+
+use crate::{money::Money, storage::UserDef};
+
+/// A user's net balance in a conversation
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Balance {
+    #[serde(serialize_with = "UserDef::serialize")]
+    pub user: User,
+    /// positive if the user is owed money, negative if they owe money
+    pub amount: Money,
+}
+
+/// A payment which settles some of what one user owes another
+#[derive(Clone)]
+pub struct Transfer {
+    pub from: User,
+    pub to: User,
+    pub amount: Money,
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pays {} {}",
+            self.from.first_name, self.to.first_name, self.amount
+        )
+    }
+}
+
+/// Who owes whom across a conversation's ended orders, kept as each user's net balance
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    balances: Vec<Balance>,
+}
+
+impl Ledger {
+    /// Records that `debtor` owes `creditor` an amount
+    pub fn record_debt(&mut self, debtor: &User, creditor: &User, amount: Money) {
+        self.adjust(debtor, -amount);
+        self.adjust(creditor, amount);
+    }
+
+    /// Records that `payer` paid `payee` an amount
+    pub fn record_payment(&mut self, payer: &User, payee: &User, amount: Money) {
+        self.record_debt(payee, payer, amount);
+    }
+
+    fn find(&self, user: UserId) -> Option<&Balance> {
+        self.balances.iter().find(|balance| balance.user.id == user)
+    }
+
+    /// Returns the balances of users who owe or are owed money, sorted by name
+    pub fn outstanding_balances(&self) -> Vec<&Balance> {
+        let mut balances: Vec<&Balance> = self
+            .balances
+            .iter()
+            .filter(|balance| !balance.amount.is_zero())
+            .collect();
+        balances
+            .sort_by(|a, b| (&a.user.first_name, a.user.id).cmp(&(&b.user.first_name, b.user.id)));
+        balances
+    }
+
+    /// Records a transfer suggested by settle_up, returning it if the users still owe and are owed money
+    /// The amount is reduced if the balances changed after the transfer was suggested
+    pub fn record_transfer(&mut self, from: UserId, to: UserId, amount: Money) -> Option<Transfer> {
+        let from = self.find(from)?;
+        let to = self.find(to)?;
+        let amount = amount.min(-from.amount).min(to.amount);
+        if amount <= Money::default() {
+            return None;
+        }
+        let transfer = Transfer {
+            from: from.user.clone(),
+            to: to.user.clone(),
+            amount,
+        };
+        self.record_payment(&transfer.from, &transfer.to, amount);
+        Some(transfer)
+    }
+
+    /// Returns transfers which settle all balances
+    /// The user owing the most repeatedly pays the user owed the most, so there is at most one transfer fewer than users with a balance
+    pub fn settle_up(&self) -> Vec<Transfer> {
+        let mut debtors: Vec<(User, Money)> = vec![];
+        let mut creditors: Vec<(User, Money)> = vec![];
+        for balance in self.outstanding_balances() {
+            if balance.amount < Money::default() {
+                debtors.push((balance.user.clone(), -balance.amount));
+            } else {
+                creditors.push((balance.user.clone(), balance.amount));
+            }
+        }
+        let mut transfers = vec![];
+        loop {
+            // sorting is stable, so users owing or owed the same amount keep their order by name
+            debtors.sort_by_key(|(_, owed)| std::cmp::Reverse(*owed));
+            creditors.sort_by_key(|(_, due)| std::cmp::Reverse(*due));
+            let (debtor, owed) = match debtors.first_mut() {
+                Some(debtor) if !debtor.1.is_zero() => (&mut debtor.0, &mut debtor.1),
+                _ => break,
+            };
+            let (creditor, due) = match creditors.first_mut() {
+                Some(creditor) if !creditor.1.is_zero() => (&mut creditor.0, &mut creditor.1),
+                _ => break,
+            };
+            let amount = (*owed).min(*due);
+            *owed = *owed - amount;
+            *due = *due - amount;
+            transfers.push(Transfer {
+                from: debtor.clone(),
+                to: creditor.clone(),
+                amount,
+            });
+        }
+        transfers
+    }
+
+    /// Adds an amount to the user's balance, updating their details in case they changed
+    fn adjust(&mut self, user: &User, amount: Money) {
+        match self
+            .balances
+            .iter_mut()
+            .find(|balance| balance.user.id == user.id)
+        {
+            Some(balance) => {
+                balance.user = user.clone();
+                balance.amount += amount;
+            }
+            None => self.balances.push(Balance {
+                user: user.clone(),
+                amount,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::user;
+
+    #[test]
+    fn settle_up() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        let mut ledger = Ledger::default();
+        ledger.record_debt(&bob, &alice, Money::from_cents(500));
+        ledger.record_debt(&alice, &carol, Money::from_cents(300));
+        ledger.record_debt(&bob, &carol, Money::from_cents(200));
+        let balances: Vec<(&str, Money)> = ledger
+            .outstanding_balances()
+            .iter()
+            .map(|balance| (balance.user.first_name.as_str(), balance.amount))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![
+                ("Alice", Money::from_cents(200)),
+                ("Bob", Money::from_cents(-700)),
+                ("Carol", Money::from_cents(500)),
+            ]
+        );
+
+        let transfers: Vec<String> = ledger
+            .settle_up()
+            .iter()
+            .map(|transfer| transfer.to_string())
+            .collect();
+        assert_eq!(
+            transfers,
+            vec!["Bob pays Carol 5.00", "Bob pays Alice 2.00"]
+        );
+
+        assert!(ledger
+            .record_transfer(bob.id, carol.id, Money::from_cents(500))
+            .is_some());
+        let transfer = ledger
+            .record_transfer(bob.id, alice.id, Money::from_cents(300))
+            .unwrap();
+        assert_eq!(
+            transfer.amount,
+            Money::from_cents(200),
+            "transfers never exceed what is owed"
+        );
+        assert!(ledger
+            .record_transfer(bob.id, alice.id, Money::from_cents(200))
+            .is_none());
+        assert!(ledger.outstanding_balances().is_empty());
+        assert!(ledger.settle_up().is_empty());
+    }
+}
@@ -1,29 +1,199 @@
 #![warn(clippy::all)]
 extern crate futures;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+extern crate serde;
+extern crate serde_json;
 extern crate telegram_bot;
 extern crate tokio_core;
 
+mod archive;
 mod bot;
+mod callback;
+mod charges;
 mod command;
 mod conversation_orders;
+mod deadline;
+mod download;
+mod ledger;
+mod menu;
+mod money;
 mod order;
+mod schedule;
+#[cfg(feature = "sqlite")]
+mod sqlite_storage;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod undo;
+mod wizard;
 
 use bot::CommandResult;
 use command::Command::*;
+use download::DownloadError;
+use storage::{JsonFileStorage, Storage};
 
-use std::{env, time::Duration};
+use std::{cell::RefCell, env, path::Path, rc::Rc, time::Duration};
 
-use futures::Stream;
+use chrono::Utc;
+use futures::{future, Future, Stream};
 use telegram_bot::*;
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Handle, Interval};
+
+/// The bot is shared by the handlers of updates and of downloads which complete later
+type SharedBot = Rc<RefCell<bot::Bot<Box<dyn Storage>>>>;
+
+/// How often the bot checks for reminders to post and deadlines which have passed
+const TIMER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Opens the storage active orders are saved to, so that they survive restarts
+#[cfg(feature = "sqlite")]
+fn open_storage() -> Box<dyn Storage> {
+    if let Ok(database_path) = env::var("ORDERS_DATABASE") {
+        let storage = sqlite_storage::SqliteStorage::open(Path::new(&database_path))
+            .unwrap_or_else(|e| panic!("Failed to open {}: {}", database_path, e));
+        return Box::new(storage);
+    }
+    open_file_storage()
+}
+
+/// Opens the storage active orders are saved to, so that they survive restarts
+#[cfg(not(feature = "sqlite"))]
+fn open_storage() -> Box<dyn Storage> {
+    open_file_storage()
+}
+
+fn open_file_storage() -> Box<dyn Storage> {
+    let path = env::var("ORDERS_FILE").unwrap_or_else(|_| "orders.json".to_string());
+    let storage = JsonFileStorage::open(Path::new(&path))
+        .unwrap_or_else(|e| panic!("Failed to load orders from {}: {}", path, e));
+    Box::new(storage)
+}
+
+/// Downloads a menu file sent with the caption /menu import <name> and saves it, replying with the result
+fn import_menu(
+    api: &Api,
+    handle: &Handle,
+    token: &str,
+    bot: &SharedBot,
+    message: &Message,
+    document: &Document,
+    name: String,
+) {
+    if document
+        .file_size
+        .is_some_and(|size| size > menu::MAX_IMPORT_SIZE)
+    {
+        api.spawn(message.text_reply(format!(
+            "Menu files must not exceed {} KB.",
+            menu::MAX_IMPORT_SIZE / 1000
+        )));
+        return;
+    }
+    let file_name = document.file_name.clone().unwrap_or_default();
+    let token = token.to_string();
+    let (api, bot, message) = (api.clone(), bot.clone(), message.clone());
+    let future = api
+        .send(document.get_file())
+        .map_err(|e| DownloadError::Failed(e.to_string()))
+        .and_then(move |file| match file.get_url(&token) {
+            Some(url) => download::download(&url, menu::MAX_IMPORT_SIZE as usize),
+            None => Box::new(future::err(DownloadError::Failed(
+                "Telegram did not provide the file.".to_string(),
+            ))),
+        })
+        .then(move |result| {
+            let res = match result {
+                Ok(contents) => {
+                    bot.borrow_mut()
+                        .import_menu(message.chat.id(), name, &file_name, &contents)
+                }
+                Err(DownloadError::TooLarge) => CommandResult::failure(format!(
+                    "Menu files must not exceed {} KB.",
+                    menu::MAX_IMPORT_SIZE / 1000
+                )),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    CommandResult::failure(
+                        "The menu file could not be downloaded. Try sending it again.".into(),
+                    )
+                }
+            };
+            api.spawn(message.text_reply(res.response));
+            Ok(())
+        });
+    handle.spawn(future);
+}
+
+/// Ends an order which the user didn't start if they are an administrator of the chat, as looked up with Telegram
+fn end_order_if_admin(
+    api: &Api,
+    handle: &Handle,
+    bot: &SharedBot,
+    message: &Message,
+    order_name: String,
+) {
+    let (api, bot, message) = (api.clone(), bot.clone(), message.clone());
+    let future = api
+        .send(message.chat.get_administrators())
+        .then(move |result| {
+            let is_admin = match result {
+                Ok(administrators) => administrators
+                    .iter()
+                    .any(|member| member.user.id == message.from.id),
+                // private chats have no administrators
+                Err(e) => {
+                    eprintln!("{}", e);
+                    false
+                }
+            };
+            let mut bot = bot.borrow_mut();
+            let res = if is_admin {
+                bot.end_order_as_admin(message.chat.id(), &message.from, &order_name)
+            } else {
+                bot.end_order(message.chat.id(), &message.from, &order_name)
+            };
+            match res.reply_markup {
+                Some(markup) => api.spawn(message.text_reply(res.response).reply_markup(markup)),
+                None => api.spawn(message.text_reply(res.response)),
+            }
+            Ok(())
+        });
+    handle.spawn(future);
+}
+
+/// Sends the result of something the bot did without being asked to a conversation
+fn send_result(api: &Api, chat: ChatId, res: CommandResult) {
+    match res.reply_markup {
+        Some(markup) => api.spawn(chat.text(res.response).reply_markup(markup)),
+        None => api.spawn(chat.text(res.response)),
+    }
+}
+
+/// Periodically does whatever is due, such as posting reminders before deadlines and ending orders whose deadline has passed
+fn spawn_timers(api: &Api, handle: &Handle, bot: &SharedBot) {
+    let (api, bot) = (api.clone(), bot.clone());
+    let timers = Interval::new(TIMER_INTERVAL, handle)
+        .expect("failed to create the timer")
+        .map_err(|e| eprintln!("{}", e))
+        .for_each(move |_| {
+            for (chat, res) in bot.borrow_mut().run_timers(Utc::now()) {
+                send_result(&api, chat, res);
+            }
+            Ok(())
+        });
+    handle.spawn(timers);
+}
 
 fn main() {
     let mut core = Core::new().unwrap();
+    let handle = core.handle();
 
     let token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not set");
-    let api = Api::configure(token).build(core.handle()).unwrap();
+    let api = Api::configure(&token).build(core.handle()).unwrap();
 
-    let mut bot = bot::Bot::new();
+    let bot: SharedBot = Rc::new(RefCell::new(bot::Bot::new(open_storage())));
+    spawn_timers(&api, &handle, &bot);
     // Fetch new updates via long poll method
     let mut stream = api.stream();
     let future = stream
@@ -46,39 +216,131 @@ fn main() {
         match update.kind {
             UpdateKind::Message(message) => {
                 if let MessageKind::Text { ref data, .. } = message.kind {
+                    let shared_bot = &bot;
+                    let mut bot = bot.borrow_mut();
                     let had_active_orders_before = bot.has_active_orders();
                     let res = match command::parse_command(
                         data,
-                        &bot.get_active_order_names(&message.chat),
+                        &bot.get_active_order_names(message.chat.id()),
                     ) {
-                        Ok(Help) => CommandResult::success("/start <order name> - starts an order. For example, /start waffles.
+                        Ok(Help) => CommandResult::success("/start <order name> [--multi] - starts an order. For example, /start waffles. Use --multi to let everyone order several different items.
+    To offer a menu, list its items on the following lines, each optionally followed by its price. For example, a second line of chocolate 4.50, plain 3
+    /start <order name> --menu <menu name> - starts an order offering a saved menu. Saved menus with the same name as the order are offered automatically. Add --menu-only to only allow items on the menu.
+    /start <order name> --until <time> - starts an order which ends automatically at the given time, such as 11:30 or 2pm.
+    /start again <order name> - starts an order with the same items as the last order with that name.
+    /schedule <order name> <days> <time> [options] - starts an order automatically, with the same options as /start. For example, /schedule waffles fri 10:00 --until 11:30. Days may be fri, mon,wed,fri, mon-fri, weekdays or daily.
+    /schedules - lists scheduled orders, and lets you delete those you scheduled.
     /view - shows active orders.
+    /history [number] - lists recently ended orders, 5 by default and at most 20, and lets you show their summaries.
+    /menu save <menu name> - saves a menu listed on the following lines, in the same format as /start.
+    /menu import <menu name> - saves a menu from a CSV or JSON file sent with this caption. CSV files need a header row with name, and optionally price, category and options columns. Options are listed like size: s/m/l; toppings (any): pearls/jelly, and are chosen when tapping on the item.
+    /menu list, /menu show <menu name>, /menu delete <menu name> - lists, shows or deletes saved menus.
 
     The following commands will ask for the order name, if there are multiple active orders.
 
-    /order [order name] <item> - adds an item to an order, or replaces the previously chosen one.
-    /cancel [order-name] - removes your previously selected item from an order.
-    /end [order-name] - stops an order.
+    /order [order name] [quantity] <item> [@price] - adds an item to an order, or replaces the previously chosen one. Tap + or - to change the quantity. For example, /order 2 chocolate @4.50
+    /order [order name] for <@user|\"guest name\"> <item> - orders an item for someone else, such as a user of the chat or a guest who isn't on Telegram. For example, /order for \"Bob (guest)\" waffle
+    /order [order name] <item> -- <note> - adds a note to an item, such as how you would like it. For example, /order chocolate -- no cream, extra hot
+    /price [order name] <item> <price> - sets the price of an item, so that everyone can see how much they owe.
+    /cancel [order-name] [item] - removes your previously selected item, or the specified one, from an order.
+    /multi [order-name] - lets everyone order several different items, or turns this off again.
+    /menuonly [order-name] - only allows items on the order's menu to be ordered, or turns this off again.
+    /delivery [order-name] <fee> - sets the delivery fee, which everyone shares.
+    /tax [order-name] <percentage> - sets a service charge or tax, such as /tax 10%.
+    /tip [order-name] <amount> - sets the tip, which everyone shares.
+    /deadline [order-name] <time|none> - ends the order automatically at the given time, or stops doing so. Times are in the bot's local timezone.
+    /remind [order-name] <minutes...|none> - posts reminders this many minutes before the deadline, 15 and 5 by default. For example, /remind 30 10
+    /split [order-name] <evenly|proportionally> - shares charges evenly, or in proportion to the price of what everyone ordered (the default).
+    /lock [order-name] - stops everyone from changing their orders, such as while calling the vendor. Use /unlock [order-name] to allow changes again.
+    /placed [order-name], /arrived [order-name] - records that the order was placed, or that it has arrived.
+    /owners [order-name] [add|remove <name>] - shows who manages the order, or lets someone else manage it too. Use their username or first name, such as /owners add @alice.
+    /handover [order-name] <name> - hands over your management of the order to someone else. If you own it, they will be paid for it instead.
+    /end [order-name] - stops an order. Only those who manage it or a chat administrator may end it. If anyone owes its creator money, the order is kept until everyone has paid.
+    /reopen [order-name] - reopens the last order to end, or the last one with that name, within 10 minutes of it ending and before anyone has paid for it. You can also tap Undo below its summary.
+    /undo - reverts the last change you made, such as ordering, cancelling, or starting or ending an order.
+
+    /paid [order-name] - records that you have paid for an ended order.
+    /unpaid - shows who has yet to pay for ended orders.
+    /balances - shows how much everyone owes or is owed across ended orders.
+    /settle - suggests the fewest payments which settle everyone's balances.
 
     For feature requests, bug reports and source: https://github.com/Neurrone/food-ordering-bot".to_string()),
-                        Ok(StartOrder(order_name)) => {
-                            bot.start_order(message.chat.clone(), message.from.clone(), order_name)
+                        Ok(StartOrder(order_name, options)) => {
+                            bot.start_order(message.chat.id(), message.from.clone(), order_name, options)
+                        }
+                        Ok(StartOrderAgain(order_name, options)) => {
+                            bot.start_order_again(message.chat.id(), message.from.clone(), order_name, options)
                         }
                         Ok(EndOrder(order_name)) => {
-                            bot.end_order(&message.chat, &message.from, &order_name)
+                            if bot.needs_admin_to_end(message.chat.id(), &message.from, &order_name) {
+                                // the reply is sent once Telegram says whether the user is an administrator
+                                end_order_if_admin(&api, &handle, shared_bot, &message, order_name);
+                                return Ok(());
+                            }
+                            bot.end_order(message.chat.id(), &message.from, &order_name)
+                        }
+                        Ok(Undo) => bot.undo(message.chat.id(), &message.from),
+                        Ok(Reopen(order_name)) => {
+                            bot.reopen_order(message.chat.id(), &message.from, order_name.as_deref())
+                        }
+                        Ok(AddItem(order_name, request)) => {
+                            bot.add_item(message.chat.id(), message.from.clone(), &order_name, request)
+                        }
+                        Ok(RemoveItem(order_name, item)) => {
+                            bot.remove_item(message.chat.id(), &message.from, &order_name, item.as_deref())
+                        }
+                        Ok(ToggleMultipleItems(order_name)) => {
+                            bot.toggle_multiple_items(message.chat.id(), &message.from, &order_name)
+                        }
+                        Ok(ToggleMenuOnly(order_name)) => {
+                            bot.toggle_menu_only(message.chat.id(), &message.from, &order_name)
+                        }
+                        Ok(ChangeState(order_name, state)) => {
+                            bot.change_state(message.chat.id(), &message.from, &order_name, state)
                         }
-                        Ok(AddItem(order_name, item_name)) => {
-                            bot.add_item(
-                                &message.chat,
-                                message.from.clone(),
-                                &order_name,
-                                item_name,
-                            )
+                        Ok(ListOwners(order_name)) => bot.list_owners(message.chat.id(), &order_name),
+                        Ok(AddOwner(order_name, name)) => {
+                            bot.add_owner(message.chat.id(), &message.from, &order_name, &name)
                         }
-                        Ok(RemoveItem(order_name)) => {
-                            bot.remove_item(&message.chat, &message.from, &order_name)
+                        Ok(RemoveOwner(order_name, name)) => {
+                            bot.remove_owner(message.chat.id(), &message.from, &order_name, &name)
                         }
-                        Ok(ViewOrders) => bot.view_orders(&message.chat),
+                        Ok(HandOver(order_name, name)) => {
+                            bot.hand_over(message.chat.id(), &message.from, &order_name, &name)
+                        }
+                        Ok(SetPrice(order_name, item, price)) => {
+                            bot.set_price(message.chat.id(), &message.from, &order_name, &item, price)
+                        }
+                        Ok(SetCharge(order_name, charge)) => {
+                            bot.set_charge(message.chat.id(), &message.from, &order_name, charge)
+                        }
+                        Ok(SetDeadline(order_name, time)) => {
+                            bot.set_deadline(message.chat.id(), &message.from, &order_name, time)
+                        }
+                        Ok(SetReminders(order_name, reminders)) => {
+                            bot.set_reminders(message.chat.id(), &message.from, &order_name, reminders)
+                        }
+                        Ok(Paid(order_name)) => {
+                            bot.mark_paid(message.chat.id(), &message.from, order_name.as_deref())
+                        }
+                        Ok(ViewUnpaid) => bot.view_unpaid(message.chat.id()),
+                        Ok(ViewBalances) => bot.view_balances(message.chat.id()),
+                        Ok(SettleUp) => bot.settle_up(message.chat.id()),
+                        Ok(SaveMenu(name, items)) => bot.save_menu(message.chat.id(), name, items),
+                        Ok(ImportMenu(name)) => CommandResult::failure(format!(
+                            "Send the menu as a CSV or JSON file, with the caption /menu import {}",
+                            name
+                        )),
+                        Ok(ListMenus) => bot.list_menus(message.chat.id()),
+                        Ok(ShowMenu(name)) => bot.show_menu(message.chat.id(), &name),
+                        Ok(DeleteMenu(name)) => bot.delete_menu(message.chat.id(), &name),
+                        Ok(ScheduleOrder(order_name, recurrence, options)) => {
+                            bot.schedule_order(message.chat.id(), message.from.clone(), order_name, recurrence, options)
+                        }
+                        Ok(ListSchedules) => bot.list_schedules(message.chat.id()),
+                        Ok(DeleteSchedule(id)) => bot.delete_schedule(message.chat.id(), &message.from, id),
+                        Ok(ViewOrders) => bot.view_orders(message.chat.id()),
+                        Ok(History(count)) => bot.view_history(message.chat.id(), count),
                         Err(error_message) => CommandResult::failure(error_message),
                     };
                     match res.reply_markup {
@@ -95,6 +357,10 @@ fn main() {
                         };
                         println!("{}", status);
                     }
+                } else if let MessageKind::Document { ref data, caption: Some(ref caption) } = message.kind {
+                    if let Ok(ImportMenu(name)) = command::parse_command(caption, &[]) {
+                        import_menu(&api, &handle, &token, &bot, &message, data, name);
+                    }
                 }
             },
             UpdateKind::CallbackQuery(query) => {
@@ -110,7 +376,7 @@ fn main() {
                     }
                     None => false
                 };
-                let (res, answer) = bot.handle_callback_query(&query.message.chat, query.from.clone(), &query.data, is_original_command_output_of_view);
+                let (res, answer) = bot.borrow_mut().handle_callback_query(query.message.chat.id(), query.from.clone(), &query.data, is_original_command_output_of_view);
                 api.spawn(query.answer(answer));
                 match res.reply_markup {
                     Some(ref markup) if res.success => api.spawn(
@@ -0,0 +1,532 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::money::Money;
+
+/// The largest menu file which may be imported, in bytes
+pub const MAX_IMPORT_SIZE: i64 = 100_000;
+
+/// The most validation errors reported for a menu file, so that the reply stays readable
+const MAX_IMPORT_ERRORS: usize = 10;
+
+/// An item offered by an order from the start, so that it can be ordered with a single tap
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MenuItem {
+    pub name: String,
+    /// the price of one of this item, if known
+    pub price: Option<Money>,
+    /// the section of the menu this item is listed under, such as drinks
+    #[serde(default)]
+    pub category: Option<String>,
+    /// choices to make when ordering this item, such as its size
+    #[serde(default)]
+    pub options: Vec<OptionGroup>,
+}
+
+/// A choice to make when ordering an item, such as its size
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OptionGroup {
+    /// what is being chosen, e.g "size"
+    pub name: String,
+    /// the possible choices, e.g "s", "m" and "l"
+    pub choices: Vec<String>,
+    /// whether any number of choices may be made, such as toppings, rather than exactly one
+    #[serde(default)]
+    pub multiple: bool,
+}
+
+/// The choices made for one of an item's option groups when ordering it, such as size m
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Selection {
+    /// the name of the option group, e.g "size"
+    pub group: String,
+    /// the choices made, in the order the group lists them, which may be none for groups allowing multiple choices
+    pub choices: Vec<String>,
+}
+
+/// Describes the choices made for an item, such as "size m, toppings pearls and jelly"
+/// Groups where nothing was chosen are left out
+pub fn describe_selections(selections: &[Selection]) -> String {
+    let described: Vec<String> = selections
+        .iter()
+        .filter(|selection| !selection.choices.is_empty())
+        .map(|selection| format!("{} {}", selection.group, selection.choices.join(" and ")))
+        .collect();
+    described.join(", ")
+}
+
+impl MenuItem {
+    /// Creates an item without a category or options
+    pub fn new(name: String, price: Option<Money>) -> Self {
+        Self {
+            name,
+            price,
+            category: None,
+            options: vec![],
+        }
+    }
+}
+
+/// Parses menu items, one per line or separated by commas, each optionally followed by its price
+/// For example, "chocolate 4.50, plain @3"
+pub fn parse_menu<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> Result<Vec<MenuItem>, String> {
+    let mut menu: Vec<MenuItem> = vec![];
+    for entry in lines.into_iter().flat_map(|line| line.split(',')) {
+        let mut words: Vec<&str> = entry.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let mut price = None;
+        if words.len() > 1 {
+            let last_word = words[words.len() - 1];
+            let amount = last_word.strip_prefix('@').unwrap_or(last_word);
+            if let Ok(amount) = amount.parse() {
+                price = Some(amount);
+                words.pop();
+            } else if last_word.starts_with('@') {
+                // only prices may start with @, so report why it isn't valid
+                return Err(amount.parse::<Money>().unwrap_err());
+            }
+        }
+        add_menu_item(&mut menu, MenuItem::new(words.join(" "), price));
+    }
+    Ok(menu)
+}
+
+/// Adds an item to a menu, replacing any item with the same name
+fn add_menu_item(menu: &mut Vec<MenuItem>, item: MenuItem) {
+    // an item listed twice keeps its last price
+    menu.retain(|existing| existing.name != item.name);
+    menu.push(item);
+}
+
+/// Returns the menu item whose name is closest to `name`, if any is close enough to be a likely typo
+pub fn suggest<'a>(name: &str, menu: &'a [MenuItem]) -> Option<&'a MenuItem> {
+    menu.iter()
+        .map(|item| (edit_distance(name, &item.name), item))
+        .filter(|(distance, item)| {
+            // allow roughly one mistake for every 3 characters, or a partial name such as "choc"
+            *distance <= 2.max(name.len() / 3) || item.name.contains(name)
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, item)| item)
+}
+
+/// Returns the number of characters which must be inserted, removed or replaced to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let replace_cost = previous_row[j] + usize::from(a_char != *b_char);
+            row.push(replace_cost.min(previous_row[j + 1] + 1).min(row[j] + 1));
+        }
+        previous_row = row;
+    }
+    previous_row[b.len()]
+}
+
+/// Parses option groups such as "size: s/m/l; sugar: 0%/50%/100%"
+/// Groups named with (any), such as "toppings (any): pearls/jelly", allow any number of choices
+fn parse_options(text: &str) -> Result<Vec<OptionGroup>, String> {
+    let mut groups = vec![];
+    for group in text
+        .split(';')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+    {
+        let (name, choices) = match group.find(':') {
+            Some(sep) => (&group[..sep], &group[sep + 1..]),
+            None => {
+                return Err(format!(
+                    "{} is not a valid option. For example, use size: s/m/l",
+                    group
+                ))
+            }
+        };
+        let name = normalize(name);
+        let (name, multiple) = match name.strip_suffix("(any)") {
+            Some(name) => (name, true),
+            None => (name.as_str(), false),
+        };
+        groups.push(option_group(name, choices.split('/'), multiple)?);
+    }
+    Ok(groups)
+}
+
+/// Validates an option group, whether written as text or listed in a JSON file
+/// Its name and choices are normalized, and empty choices are left out
+fn option_group<'a>(
+    name: &str,
+    choices: impl Iterator<Item = &'a str>,
+    multiple: bool,
+) -> Result<OptionGroup, String> {
+    let name = normalize(name);
+    if name.is_empty() {
+        return Err("An option has no name. For example, use size: s/m/l".into());
+    }
+    let choices: Vec<String> = choices
+        .map(normalize)
+        .filter(|choice| !choice.is_empty())
+        .collect();
+    if choices.is_empty() {
+        return Err(format!("Option {} has no choices.", name));
+    }
+    Ok(OptionGroup {
+        name,
+        choices,
+        multiple,
+    })
+}
+
+/// Lowercases and trims text from a menu file, as commands and inline keyboard buttons are case insensitive
+fn normalize(text: &str) -> String {
+    text.trim().to_lowercase()
+}
+
+/// Parses a menu from an uploaded CSV or JSON file, depending on its extension or contents
+/// Every invalid row is reported, so that they can all be fixed at once
+pub fn import_menu(file_name: &str, contents: &[u8]) -> Result<Vec<MenuItem>, Vec<String>> {
+    let contents = String::from_utf8_lossy(contents);
+    let file_name = file_name.to_lowercase();
+    let is_json = if file_name.ends_with(".json") {
+        true
+    } else if file_name.ends_with(".csv") {
+        false
+    } else {
+        contents.trim_start().starts_with('[')
+    };
+    let (menu, mut errors) = if is_json {
+        import_json(&contents)
+    } else {
+        import_csv(&contents)
+    };
+    if errors.is_empty() && menu.is_empty() {
+        errors.push("The menu has no items.".to_string());
+    }
+    if errors.is_empty() {
+        return Ok(menu);
+    }
+    if errors.len() > MAX_IMPORT_ERRORS {
+        let remaining = errors.len() - MAX_IMPORT_ERRORS;
+        errors.truncate(MAX_IMPORT_ERRORS);
+        errors.push(format!("...and {} more.", remaining));
+    }
+    Err(errors)
+}
+
+/// Reads a CSV file with a header row naming its columns, which are name, price, category and options
+/// Only the name column is required
+fn import_csv(contents: &str) -> (Vec<MenuItem>, Vec<String>) {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    let headers: Vec<String> = match reader.headers() {
+        Ok(headers) => headers.iter().map(normalize).collect(),
+        Err(e) => return (vec![], vec![format!("Row 1: {}", e)]),
+    };
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let name_column = match column("name").or_else(|| column("item")) {
+        Some(index) => index,
+        None => {
+            return (
+                vec![],
+                vec!["The first row must name the columns, including a name column. For example, name,price,category,options".into()],
+            )
+        }
+    };
+    let (price_column, category_column, options_column) =
+        (column("price"), column("category"), column("options"));
+
+    let mut menu = vec![];
+    let mut errors = vec![];
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let row = e.position().map_or(0, |position| position.line());
+                errors.push(format!("Row {}: {}", row, e));
+                continue;
+            }
+        };
+        let row = record.position().map_or(0, |position| position.line());
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let field = |index: Option<usize>| {
+            index
+                .and_then(|index| record.get(index))
+                .filter(|value| !value.is_empty())
+        };
+        let item = parse_options(field(options_column).unwrap_or("")).and_then(|options| {
+            parse_row(
+                field(Some(name_column)),
+                field(price_column),
+                field(category_column),
+                options,
+            )
+        });
+        match item {
+            Ok(item) => add_menu_item(&mut menu, item),
+            Err(e) => errors.push(format!("Row {}: {}", row, e)),
+        }
+    }
+    (menu, errors)
+}
+
+/// Reads a JSON list of items such as {"name": "thai tea", "price": 3.5, "category": "drinks", "options": "size: s/m/l"}
+/// Options may also be listed as objects such as {"name": "size", "choices": ["s", "m", "l"]}
+fn import_json(contents: &str) -> (Vec<MenuItem>, Vec<String>) {
+    let entries: Vec<Value> = match serde_json::from_str(contents) {
+        Ok(Value::Array(entries)) => entries,
+        Ok(_) => {
+            return (
+                vec![],
+                vec!["The file must contain a list of items.".into()],
+            )
+        }
+        Err(e) => return (vec![], vec![format!("The file is not valid JSON: {}", e)]),
+    };
+    let mut menu = vec![];
+    let mut errors = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        match import_json_item(entry) {
+            Ok(item) => add_menu_item(&mut menu, item),
+            Err(e) => errors.push(format!("Item {}: {}", i + 1, e)),
+        }
+    }
+    (menu, errors)
+}
+
+fn import_json_item(entry: &Value) -> Result<MenuItem, String> {
+    // prices may be given as numbers or strings
+    let price = match entry.get("price") {
+        Some(Value::Number(number)) => Some(number.to_string()),
+        Some(Value::String(price)) => Some(price.clone()),
+        Some(Value::Null) | None => None,
+        Some(_) => return Err("The price must be a number, such as 4.50".into()),
+    };
+    let text = |key: &str| match entry.get(key) {
+        Some(Value::String(text)) => Ok(Some(text.as_str())),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(format!("The {} must be text.", key)),
+    };
+    let options = match entry.get("options") {
+        Some(Value::String(options)) => parse_options(options)?,
+        Some(Value::Array(groups)) => {
+            let groups: Vec<OptionGroup> = serde_json::from_value(Value::Array(groups.clone()))
+                .map_err(|_| "Options must be listed like {\"name\": \"size\", \"choices\": [\"s\", \"m\", \"l\"]}")?;
+            groups
+                .iter()
+                .map(|group| {
+                    option_group(
+                        &group.name,
+                        group.choices.iter().map(String::as_str),
+                        group.multiple,
+                    )
+                })
+                .collect::<Result<_, _>>()?
+        }
+        Some(Value::Null) | None => vec![],
+        Some(_) => return Err("Options must be text or a list.".into()),
+    };
+    parse_row(text("name")?, price.as_deref(), text("category")?, options)
+}
+
+/// Validates the fields of an imported item
+fn parse_row(
+    name: Option<&str>,
+    price: Option<&str>,
+    category: Option<&str>,
+    options: Vec<OptionGroup>,
+) -> Result<MenuItem, String> {
+    let name = normalize(name.unwrap_or(""));
+    if name.is_empty() {
+        return Err("The item has no name.".into());
+    }
+    let price = match price {
+        Some(price) => Some(price.trim().parse()?),
+        None => None,
+    };
+    Ok(MenuItem {
+        name,
+        price,
+        category: category
+            .map(normalize)
+            .filter(|category| !category.is_empty()),
+        options,
+    })
+}
+
+/// Lists menu items one per line, grouped by category and followed by their options
+/// Menus without categories or options are listed in the format parse_menu accepts
+pub fn format_menu(menu: &[MenuItem]) -> String {
+    let mut categories: Vec<Option<&str>> = vec![];
+    for item in menu {
+        if !categories.contains(&item.category.as_deref()) {
+            categories.push(item.category.as_deref());
+        }
+    }
+    // uncategorized items are listed first, without a heading
+    categories.sort_by_key(|category| category.is_some());
+    let sections: Vec<String> = categories
+        .iter()
+        .map(|&category| {
+            let lines: Vec<String> = menu
+                .iter()
+                .filter(|item| item.category.as_deref() == category)
+                .map(format_menu_item)
+                .collect();
+            match category {
+                Some(category) => format!("{}:\n{}", category, lines.join("\n")),
+                None => lines.join("\n"),
+            }
+        })
+        .collect();
+    sections.join("\n\n")
+}
+
+fn format_menu_item(item: &MenuItem) -> String {
+    let mut line = match item.price {
+        Some(price) => format!("{} {}", item.name, price),
+        None => item.name.clone(),
+    };
+    if !item.options.is_empty() {
+        let options: Vec<String> = item
+            .options
+            .iter()
+            .map(|group| {
+                let name = if group.multiple {
+                    format!("{} (any)", group.name)
+                } else {
+                    group.name.clone()
+                };
+                format!("{}: {}", name, group.choices.join("/"))
+            })
+            .collect();
+        line.push_str(&format!(" ({})", options.join("; ")));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let menu = parse_menu("large chocolate 4.50\nplain @3, coffee\n\nplain $3.20".lines());
+        assert_eq!(
+            menu,
+            Ok(vec![
+                MenuItem::new("large chocolate".into(), Some(Money::from_cents(450))),
+                MenuItem::new("coffee".into(), None),
+                MenuItem::new("plain".into(), Some(Money::from_cents(320))),
+            ])
+        );
+        assert_eq!(
+            parse_menu(vec!["chocolate @four"]),
+            Err("four is not a valid amount. For example, use 4.50".into())
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        let menu = vec![
+            MenuItem::new("chocolate".into(), None),
+            MenuItem::new("plain".into(), None),
+            MenuItem::new("thai tea".into(), None),
+        ];
+        let suggestion = |name| suggest(name, &menu).map(|item| item.name.as_str());
+        assert_eq!(suggestion("chocolat"), Some("chocolate"));
+        assert_eq!(suggestion("plian"), Some("plain"));
+        assert_eq!(suggestion("thai"), Some("thai tea"));
+        assert_eq!(suggestion("pizza"), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn import_csv_menu() {
+        let csv = "Name,Price,Category,Options\nPad Thai,8.50,Mains,\n\"Thai Tea\",3,Drinks,\"Size: S/M/L; Sugar: 0%/50%/100%; Toppings (any): Pearls/Jelly\"\n";
+        let menu = import_menu("thai-place.csv", csv.as_bytes()).unwrap();
+        assert_eq!(menu.len(), 2);
+        assert_eq!(menu[1].name, "thai tea");
+        assert_eq!(menu[1].category.as_deref(), Some("drinks"));
+        assert_eq!(
+            menu[1].options,
+            vec![
+                OptionGroup {
+                    name: "size".into(),
+                    choices: vec!["s".into(), "m".into(), "l".into()],
+                    multiple: false,
+                },
+                OptionGroup {
+                    name: "sugar".into(),
+                    choices: vec!["0%".into(), "50%".into(), "100%".into()],
+                    multiple: false,
+                },
+                OptionGroup {
+                    name: "toppings".into(),
+                    choices: vec!["pearls".into(), "jelly".into()],
+                    multiple: true,
+                },
+            ]
+        );
+        assert_eq!(
+            format_menu(&menu),
+            "mains:\npad thai 8.50\n\ndrinks:\nthai tea 3.00 (size: s/m/l; sugar: 0%/50%/100%; toppings (any): pearls/jelly)"
+        );
+
+        assert_eq!(
+            import_menu("menu.csv", b"name,price\nchocolate,four\n,3\nplain,3"),
+            Err(vec![
+                "Row 2: four is not a valid amount. For example, use 4.50".to_string(),
+                "Row 3: The item has no name.".to_string(),
+            ])
+        );
+        assert!(import_menu("menu.csv", b"item price\nchocolate 4").is_err());
+    }
+
+    #[test]
+    fn import_json_menu() {
+        let json = r#"[
+            {"name": "Pad Thai", "price": 8.5, "category": "mains"},
+            {"name": "thai tea", "price": "3", "options": [{"name": "size", "choices": ["s", "l"]}, {"name": "toppings", "choices": ["pearls"], "multiple": true}]}
+        ]"#;
+        let menu = import_menu("menu.json", json.as_bytes()).unwrap();
+        assert_eq!(menu[0].price, Some(Money::from_cents(850)));
+        assert_eq!(menu[1].options[0].choices, vec!["s", "l"]);
+        assert!(!menu[1].options[0].multiple);
+        assert!(menu[1].options[1].multiple);
+
+        assert_eq!(
+            import_menu("menu", br#"[{"price": 3}, {"name": "tea", "price": true}]"#),
+            Err(vec![
+                "Item 1: The item has no name.".to_string(),
+                "Item 2: The price must be a number, such as 4.50".to_string(),
+            ])
+        );
+        assert_eq!(
+            import_menu(
+                "menu.json",
+                br#"[
+                    {"name": "tea", "options": [{"name": " ", "choices": ["s"]}]},
+                    {"name": "coffee", "options": [{"name": "size", "choices": ["", " "]}]},
+                    {"name": "juice", "options": ": s/m"}
+                ]"#
+            ),
+            Err(vec![
+                "Item 1: An option has no name. For example, use size: s/m/l".to_string(),
+                "Item 2: Option size has no choices.".to_string(),
+                "Item 3: An option has no name. For example, use size: s/m/l".to_string(),
+            ]),
+            "options listed as objects are validated like those written as text"
+        );
+        assert_eq!(
+            import_menu("menu.json", b"[]"),
+            Err(vec!["The menu has no items.".to_string()])
+        );
+    }
+}
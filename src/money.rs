@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+/// The largest price accepted, to avoid overflows when amounts are added up
+const MAX_PRICE_IN_CENTS: i64 = 100_000_000;
+
+/// An amount of money, kept in cents to avoid rounding errors
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Money(i64);
+
+impl Money {
+    pub fn from_cents(cents: i64) -> Self {
+        Money(cents)
+    }
+
+    pub fn cents(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Divides this amount in proportion to the given weights, or evenly if they are all 0
+    /// Leftover cents go to the first shares with a weight, so the shares always add up to this amount
+    pub fn split(self, weights: &[i64]) -> Vec<Money> {
+        let weights: Vec<i64> = if weights.iter().all(|&weight| weight == 0) {
+            vec![1; weights.len()]
+        } else {
+            weights.to_vec()
+        };
+        let total_weight: i64 = weights.iter().sum();
+        let mut shares: Vec<Money> = weights
+            .iter()
+            .map(|&weight| {
+                // widened since amounts multiplied by subtotals may overflow
+                Money((i128::from(self.0) * i128::from(weight) / i128::from(total_weight)) as i64)
+            })
+            .collect();
+        let mut leftover = self.0 - shares.iter().map(|share| share.0).sum::<i64>();
+        for (share, &weight) in shares.iter_mut().zip(&weights) {
+            if leftover == 0 {
+                break;
+            }
+            if weight > 0 {
+                share.0 += 1;
+                leftover -= 1;
+            }
+        }
+        shares
+    }
+}
+
+/// A percentage such as a service charge or tax, kept in hundredths of a percent
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Percentage(u32);
+
+impl Percentage {
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns this percentage of an amount, rounded to the nearest cent
+    pub fn of(self, amount: Money) -> Money {
+        Money((amount.0 * i64::from(self.0) + 5_000) / 10_000)
+    }
+}
+
+impl FromStr for Percentage {
+    type Err = String;
+
+    /// Parses percentages such as 10, 10% or 7.5%
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("{} is not a valid percentage. For example, use 10%", s);
+        // percentages have the same format as amounts, with 2 decimal places at most
+        let hundredths: Money = s.trim_end_matches('%').parse().map_err(|_| error())?;
+        if s.starts_with('$') || hundredths.0 > 10_000 {
+            return Err(error());
+        }
+        Ok(Percentage(hundredths.0 as u32))
+    }
+}
+
+impl fmt::Display for Percentage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percentage = format!("{}.{:02}", self.0 / 100, self.0 % 100);
+        // 7.50 is shown as 7.5, and 10.00 as 10
+        let percentage = percentage.trim_end_matches('0').trim_end_matches('.');
+        write!(f, "{}%", percentage)
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    /// Parses amounts such as 4, 4.5, 4.50 or $4.50
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("{} is not a valid amount. For example, use 4.50", s);
+        let amount = s.trim_start_matches('$');
+        let (dollars, cents) = match amount.find('.') {
+            Some(sep) => (&amount[..sep], &amount[sep + 1..]),
+            None => (amount, ""),
+        };
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if dollars.is_empty() || !all_digits(dollars) || !all_digits(cents) || cents.len() > 2 {
+            return Err(error());
+        }
+        let dollars: i64 = dollars.parse().map_err(|_| error())?;
+        // a single decimal digit is in tenths, e.g 4.5 is 4.50
+        let cents: i64 = format!("{:0<2}", cents).parse().map_err(|_| error())?;
+        match dollars.checked_mul(100).map(|c| c + cents) {
+            Some(total) if total <= MAX_PRICE_IN_CENTS => Ok(Money(total)),
+            _ => Err(format!("{} is too large.", s)),
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl Mul<u32> for Money {
+    type Output = Money;
+    fn mul(self, quantity: u32) -> Money {
+        Money(self.0 * i64::from(quantity))
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::default(), Add::add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("4".parse(), Ok(Money(400)));
+        assert_eq!("4.5".parse(), Ok(Money(450)));
+        assert_eq!("4.50".parse(), Ok(Money(450)));
+        assert_eq!("$0.05".parse(), Ok(Money(5)));
+        assert!("4.505".parse::<Money>().is_err());
+        assert!(".50".parse::<Money>().is_err());
+        assert!("-4".parse::<Money>().is_err());
+        assert!("four".parse::<Money>().is_err());
+        assert!("99999999999999999999".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            Money(1000).split(&[1, 1, 1]),
+            vec![Money(334), Money(333), Money(333)]
+        );
+        assert_eq!(
+            Money(1000).split(&[0, 300, 100]),
+            vec![Money(0), Money(750), Money(250)]
+        );
+        assert_eq!(Money(5).split(&[0, 0]), vec![Money(3), Money(2)]);
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!("10".parse(), Ok(Percentage(1000)));
+        assert_eq!("7.5%".parse(), Ok(Percentage(750)));
+        assert!("101%".parse::<Percentage>().is_err());
+        assert!("$10".parse::<Percentage>().is_err());
+        assert_eq!(Percentage(750).to_string(), "7.5%");
+        assert_eq!(Percentage(1000).to_string(), "10%");
+        assert_eq!(Percentage(1000).of(Money(1355)), Money(136));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Money(450).to_string(), "4.50");
+        assert_eq!(Money(5).to_string(), "0.05");
+        assert_eq!(Money(-1205).to_string(), "-12.05");
+    }
+}
@@ -1,84 +1,726 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     fmt,
     string::String,
 };
 use telegram_bot::{
-    types::{chat::User, InlineKeyboardMarkup},
+    types::{chat::User, InlineKeyboardMarkup, UserId},
     InlineKeyboardButton,
 };
 
+use crate::{
+    callback::CallbackAction,
+    charges::Charges,
+    menu::{self, MenuItem, OptionGroup, Selection},
+    money::Money,
+    storage::{serialize_optional_user, serialize_users, UserDef},
+};
+
+/// The largest quantity of an item a user may order
+pub const MAX_QUANTITY: u32 = 99;
+
+/// How many minutes before an order's deadline reminders are posted, unless changed with /remind
+const DEFAULT_REMINDERS: [u32; 2] = [15, 5];
+
+/// Where an order is in its lifecycle, from accepting items to having ended
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderState {
+    /// accepting items
+    #[default]
+    Open,
+    /// items can no longer be changed, such as while the vendor is being called
+    Locked,
+    /// the order has been placed with the vendor
+    Placed,
+    /// the order has arrived
+    Delivered,
+    /// the order has ended
+    Closed,
+}
+
+impl OrderState {
+    /// Describes moving an order to this state, such as "locked"
+    fn action(self) -> &'static str {
+        match self {
+            OrderState::Open => "unlocked",
+            OrderState::Locked => "locked",
+            OrderState::Placed => "marked as placed",
+            OrderState::Delivered => "marked as arrived",
+            OrderState::Closed => "ended",
+        }
+    }
+}
+
+impl fmt::Display for OrderState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            OrderState::Open => "open",
+            OrderState::Locked => "locked",
+            OrderState::Placed => "placed",
+            OrderState::Delivered => "delivered",
+            OrderState::Closed => "closed",
+        };
+        write!(f, "{}", state)
+    }
+}
+
 /// Represents an active order
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Order {
     /// The name of the order, e.g "waffles"
     pub name: String,
     /// map of the item name to the users who ordered them
-    pub items: HashMap<String, HashSet<User>>,
-    /// the creater of the order
+    #[serde(deserialize_with = "deserialize_items")]
+    pub items: HashMap<String, Vec<OrderEntry>>,
+    /// the creater of the order, or whoever it was handed over to, who is paid for it
+    #[serde(serialize_with = "UserDef::serialize")]
     pub owner: User,
+    /// users who may manage the order as well as its owner
+    #[serde(default, serialize_with = "serialize_users")]
+    pub co_owners: Vec<User>,
+    /// whether items may still be changed, and whether the order has been placed or has arrived
+    #[serde(default)]
+    pub state: OrderState,
+    /// when the order was started
+    #[serde(default = "Utc::now")]
+    pub started_at: DateTime<Utc>,
+    /// whether users may order several different items, rather than each item replacing the previous one
+    #[serde(default)]
+    pub allow_multiple_items: bool,
+    /// map of the item name to the price of one of that item, for items whose price is known
+    #[serde(default)]
+    pub prices: HashMap<String, Money>,
+    /// delivery fee, service charge or tax and tip, shared by participants
+    #[serde(default)]
+    pub charges: Charges,
+    /// the items of menus offered when the order was started
+    #[serde(default)]
+    pub attached_menu: Vec<MenuItem>,
+    /// whether only items on the attached menu may be ordered
+    #[serde(default)]
+    pub menu_only: bool,
+    /// when the order is automatically ended, if ever
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+    /// how many minutes before the deadline reminders are posted, from earliest to latest
+    #[serde(default = "default_reminders")]
+    pub reminders: Vec<u32>,
+    /// the reminders which have been posted for the current deadline, so that each is only posted once
+    #[serde(default)]
+    pub reminders_sent: Vec<u32>,
+}
+
+fn default_reminders() -> Vec<u32> {
+    DEFAULT_REMINDERS.to_vec()
+}
+
+/// A user's order of an item
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OrderEntry {
+    #[serde(serialize_with = "UserDef::serialize")]
+    pub user: User,
+    /// how many of the item the user ordered, at least 1
+    pub quantity: u32,
+    /// who ordered the item for the user, if someone else did
+    #[serde(default, serialize_with = "serialize_optional_user")]
+    pub added_by: Option<User>,
+    /// how the user would like the item customised, such as no cream
+    #[serde(default)]
+    pub note: Option<String>,
+    /// the choices made for the item's option groups, such as its size, if it has any
+    #[serde(default)]
+    pub options: Vec<Selection>,
+}
+
+/// Returns whether a user stands in for a guest, rather than being on Telegram
+pub fn is_guest(user: &User) -> bool {
+    i64::from(user.id) < 0
+}
+
+/// Returns the user standing in for a guest who isn't on Telegram, such as "Bob (guest)"
+/// Guests are told apart by name, and have negative ids, which Telegram never gives users
+pub fn guest(name: &str) -> User {
+    // FNV-1a, as a guest's id must stay the same whenever the bot is restarted
+    let hash = name
+        .to_lowercase()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    User {
+        id: UserId::new(-((hash >> 1) as i64) - 1),
+        first_name: name.to_string(),
+        last_name: None,
+        username: None,
+        is_bot: false,
+        language_code: None,
+    }
+}
+
+/// Orders saved before quantities were supported only stored the users who ordered each item
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Entry(OrderEntry),
+    User(User),
+}
+
+fn deserialize_items<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, Vec<OrderEntry>>, D::Error> {
+    let items: HashMap<String, Vec<StoredEntry>> = HashMap::deserialize(deserializer)?;
+    Ok(items
+        .into_iter()
+        .map(|(item, entries)| {
+            let entries = entries
+                .into_iter()
+                .map(|entry| match entry {
+                    StoredEntry::Entry(entry) => entry,
+                    StoredEntry::User(user) => OrderEntry {
+                        user,
+                        quantity: 1,
+                        added_by: None,
+                        note: None,
+                        options: vec![],
+                    },
+                })
+                .collect();
+            (item, entries)
+        })
+        .collect())
 }
 
 impl Order {
-    /// Adds an item to the current order
+    /// Creates an order with no items
+    pub fn new(name: String, owner: User) -> Self {
+        Self {
+            name,
+            items: HashMap::new(),
+            owner,
+            co_owners: vec![],
+            state: OrderState::Open,
+            started_at: Utc::now(),
+            allow_multiple_items: false,
+            prices: HashMap::new(),
+            charges: Charges::default(),
+            attached_menu: vec![],
+            menu_only: false,
+            deadline: None,
+            reminders: default_reminders(),
+            reminders_sent: vec![],
+        }
+    }
+
+    /// Returns an error explaining why items can't be ordered or cancelled, unless the order is open
+    pub fn check_open(&self) -> Result<(), String> {
+        match self.state {
+            OrderState::Open => Ok(()),
+            OrderState::Locked => Err(format!(
+                "{} is locked, so orders can't be changed. Ask {} to /unlock it.",
+                self.name,
+                self.managers()
+            )),
+            state => Err(format!(
+                "{} is {}, so orders can't be changed.",
+                self.name, state
+            )),
+        }
+    }
+
+    /// Moves the order to another state, if it can move there from its current state
+    /// Locked orders may be unlocked, but orders which were placed or have arrived can't go back
+    pub fn set_state(&mut self, state: OrderState) -> Result<(), String> {
+        use OrderState::*;
+        if self.state == state {
+            return Err(format!("{} is already {}.", self.name, state));
+        }
+        match (self.state, state) {
+            (Open, Locked)
+            | (Locked, Open)
+            | (Open | Locked, Placed)
+            | (Open | Locked | Placed, Delivered) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(format!(
+                "{} is {}, so it can't be {}.",
+                self.name,
+                self.state,
+                state.action()
+            )),
+        }
+    }
+
+    /// Returns whether `user` may manage the order, such as changing its charges or ending it
+    pub fn is_managed_by(&self, user: &User) -> bool {
+        self.owner.id == user.id || self.co_owners.iter().any(|co_owner| co_owner.id == user.id)
+    }
+
+    /// Describes who manages the order, such as "Alice or Bob"
+    pub fn managers(&self) -> String {
+        let names: Vec<&str> = std::iter::once(&self.owner)
+            .chain(&self.co_owners)
+            .map(|user| user.first_name.as_str())
+            .collect();
+        match names.split_last() {
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => unreachable!("orders always have an owner"),
+        }
+    }
+
+    /// Lets another user manage the order, returning false if they already do
+    pub fn add_co_owner(&mut self, user: User) -> bool {
+        if self.is_managed_by(&user) {
+            return false;
+        }
+        self.co_owners.push(user);
+        true
+    }
+
+    /// Stops a co-owner from managing the order, returning false if they weren't a co-owner
+    pub fn remove_co_owner(&mut self, user: &User) -> bool {
+        let co_owners_before = self.co_owners.len();
+        self.co_owners.retain(|co_owner| co_owner.id != user.id);
+        self.co_owners.len() != co_owners_before
+    }
+
+    /// Hands over `from`'s management of the order to `to`, making `to` the owner if `from` was the owner
+    pub fn hand_over(&mut self, from: &User, to: User) {
+        self.remove_co_owner(&to);
+        if self.owner.id == from.id {
+            self.owner = to;
+        } else if let Some(co_owner) = self
+            .co_owners
+            .iter_mut()
+            .find(|co_owner| co_owner.id == from.id)
+        {
+            *co_owner = to;
+        }
+    }
+
+    /// Sets or removes the time at which the order is automatically ended, so that reminders are posted again
+    pub fn set_deadline(&mut self, deadline: Option<DateTime<Utc>>) {
+        self.deadline = deadline;
+        self.reminders_sent.clear();
+    }
+
+    /// Returns the number of minutes until the deadline if a reminder is due at `now`, marking it as posted
+    /// When several reminders are due at once, only the latest is posted
+    pub fn take_due_reminder(&mut self, now: DateTime<Utc>) -> Option<i64> {
+        // there is no point in reminding anyone to order once items can't be changed
+        if self.state != OrderState::Open {
+            return None;
+        }
+        let deadline = self.deadline.filter(|&deadline| deadline > now)?;
+        let due: Vec<u32> = self
+            .reminders
+            .iter()
+            .cloned()
+            .filter(|&minutes| {
+                !self.reminders_sent.contains(&minutes)
+                    && now >= deadline - Duration::minutes(i64::from(minutes))
+            })
+            .collect();
+        if due.is_empty() {
+            return None;
+        }
+        self.reminders_sent.extend(due);
+        // rounded up, so that a reminder 5 minutes before doesn't say 4 minutes because the check ran late
+        let seconds_left = (deadline - now).num_seconds();
+        Some((seconds_left + 59) / 60)
+    }
+
+    /// Adds a quantity of an item to the current order
+    /// Unless multiple items are allowed, this replaces the user's previous item
     /// Returns whether the addition overrides the user's previous order
-    pub fn add_item(&mut self, user: User, item: String) -> bool {
-        // Remove any existing items this user has ordered
-        let overrides_existing_order = self.remove_item(&user).is_some();
-        match self.items.get_mut(&item) {
-            Some(users) => {
-                users.insert(user);
-                overrides_existing_order
+    pub fn add_item(&mut self, user: User, item: String, quantity: u32) -> bool {
+        self.add_item_for(user, item, quantity, None)
+    }
+
+    /// Adds a quantity of an item for a user, which may have been ordered for them by someone else
+    /// Returns whether the addition overrides the user's previous order, as with add_item
+    pub fn add_item_for(
+        &mut self,
+        user: User,
+        item: String,
+        quantity: u32,
+        added_by: Option<User>,
+    ) -> bool {
+        let overrides_existing_order = if self.allow_multiple_items {
+            self.remove_user_item(&user, &item)
+        } else {
+            // Remove any existing items this user has ordered
+            self.remove_item(&user).is_some()
+        };
+        self.items.entry(item).or_default().push(OrderEntry {
+            user,
+            quantity,
+            added_by,
+            note: None,
+            options: vec![],
+        });
+        overrides_existing_order
+    }
+
+    /// Changes the quantity of an item by `delta`, ordering it if the user hasn't already done so
+    /// The item is cancelled if its quantity drops to 0
+    /// Returns the new quantity, or None if the user tried to reduce the quantity of an item they didn't order
+    pub fn adjust_quantity(&mut self, user: User, item: &str, delta: i32) -> Option<u32> {
+        let current_quantity = match self.items.get(item) {
+            Some(entries) => entries
+                .iter()
+                .find(|entry| entry.user.id == user.id)
+                .map(|entry| entry.quantity),
+            None => None,
+        };
+        match current_quantity {
+            Some(quantity) => {
+                let new_quantity = (quantity as i32 + delta).min(MAX_QUANTITY as i32);
+                if new_quantity <= 0 {
+                    self.remove_user_item(&user, item);
+                    return Some(0);
+                }
+                for entry in self.items.get_mut(item).into_iter().flatten() {
+                    if entry.user.id == user.id {
+                        entry.quantity = new_quantity as u32;
+                    }
+                }
+                Some(new_quantity as u32)
             }
-            None => {
-                let mut users = HashSet::new();
-                users.insert(user);
-                self.items.insert(item, users);
-                overrides_existing_order
+            None if delta > 0 => {
+                let quantity = (delta as u32).min(MAX_QUANTITY);
+                self.add_item(user, item.to_string(), quantity);
+                Some(quantity)
             }
+            None => None,
         }
     }
 
-    /// Returns the item a user has ordered, if any
-    pub fn find_user_item(&self, user: &User) -> Option<String> {
-        for (item, users) in self.items.iter() {
-            if users.contains(user) {
-                return Some(item.to_string());
+    /// Makes menu items available in the inline keyboard, without anyone having ordered them yet
+    /// These items are kept as the order's menu, which ordering may be limited to
+    pub fn offer_menu(&mut self, menu: &[MenuItem]) {
+        for item in menu {
+            self.items.entry(item.name.clone()).or_default();
+            if let Some(price) = item.price {
+                self.prices.insert(item.name.clone(), price);
             }
+            self.attached_menu
+                .retain(|existing| existing.name != item.name);
+            self.attached_menu.push(item.clone());
+        }
+    }
+
+    /// Ensures that an item may be ordered, which is always the case unless ordering is limited to the menu
+    /// Otherwise, the error suggests the closest item on the menu
+    pub fn check_on_menu(&self, item: &str) -> Result<(), String> {
+        if !self.menu_only
+            || self
+                .attached_menu
+                .iter()
+                .any(|menu_item| menu_item.name == item)
+        {
+            return Ok(());
+        }
+        match menu::suggest(item, &self.attached_menu) {
+            Some(suggestion) => Err(format!(
+                "{} is not on the menu for {}. Did you mean {}?",
+                item, self.name, suggestion.name
+            )),
+            None => Err(format!(
+                "{} is not on the menu for {}. Tap on an item to order it, or use /view to see the menu.",
+                item, self.name
+            )),
         }
-        None
+    }
+
+    /// Returns the items offered by this order and their prices, sorted by name
+    pub fn menu(&self) -> Vec<MenuItem> {
+        let mut menu: Vec<MenuItem> = self
+            .items
+            .keys()
+            .map(|item| MenuItem::new(item.clone(), self.prices.get(item).cloned()))
+            .collect();
+        menu.sort_by(|a, b| a.name.cmp(&b.name));
+        menu
+    }
+
+    /// Sets the price of one of an item, offering it in the inline keyboard if nobody has ordered it yet
+    pub fn set_price(&mut self, item: &str, price: Money) {
+        self.items.entry(item.to_string()).or_default();
+        self.prices.insert(item.to_string(), price);
+    }
+
+    /// Removes the price of an item, no longer offering it in the inline keyboard unless someone ordered it or it is on the menu
+    pub fn remove_price(&mut self, item: &str) {
+        self.prices.remove(item);
+        let is_on_menu = self
+            .attached_menu
+            .iter()
+            .any(|menu_item| menu_item.name == item);
+        if !is_on_menu && self.items.get(item).is_some_and(Vec::is_empty) {
+            self.items.remove(item);
+        }
+    }
+
+    /// Returns the price of the items each participant ordered, sorted by name
+    /// Items without a price are not included
+    pub fn subtotals(&self) -> Vec<(&User, Money)> {
+        self.participants()
+            .into_iter()
+            .map(|user| {
+                let amount = self
+                    .items
+                    .iter()
+                    .filter_map(|(item, entries)| {
+                        let price = self.prices.get(item)?;
+                        let entry = entries.iter().find(|entry| entry.user.id == user.id)?;
+                        Some(*price * entry.quantity)
+                    })
+                    .sum();
+                (user, amount)
+            })
+            .collect()
+    }
+
+    /// Returns how much each participant owes, including their share of the charges, sorted by name
+    pub fn amounts_owed(&self) -> Vec<(&User, Money)> {
+        let subtotals = self.subtotals();
+        let amounts: Vec<Money> = subtotals.iter().map(|(_, amount)| *amount).collect();
+        let shares = self.charges.shares(&amounts);
+        subtotals
+            .into_iter()
+            .zip(shares)
+            .map(|((user, subtotal), share)| (user, subtotal + share))
+            .collect()
+    }
+
+    /// Returns whether the price of something that was ordered, or a charge, is known
+    /// Until then, nobody owes anything
+    pub fn has_amounts(&self) -> bool {
+        !self.charges.is_empty()
+            || self
+                .items
+                .iter()
+                .any(|(item, entries)| !entries.is_empty() && self.prices.contains_key(item))
+    }
+
+    /// Returns the items a user has ordered, sorted by name
+    pub fn find_user_items(&self, user: &User) -> Vec<String> {
+        let mut items: Vec<String> = self
+            .items
+            .iter()
+            .filter(|(_, entries)| entries.iter().any(|entry| entry.user.id == user.id))
+            .map(|(item, _)| item.to_string())
+            .collect();
+        items.sort();
+        items
+    }
+
+    /// Returns the users who have ordered something, sorted by name
+    pub fn participants(&self) -> Vec<&User> {
+        let mut participants: Vec<&User> = self
+            .items
+            .values()
+            .flatten()
+            .map(|entry| &entry.user)
+            .collect();
+        participants.sort_by(|a, b| (&a.first_name, a.id).cmp(&(&b.first_name, b.id)));
+        // users who ordered several items would otherwise be listed once per item
+        participants.dedup_by_key(|user| user.id);
+        participants
     }
 
     /// Removes a user's order, returning the item that was removed, if any
     pub fn remove_item(&mut self, user: &User) -> Option<String> {
-        for (item, users) in self.items.iter_mut() {
-            if users.remove(user) {
+        for (item, entries) in self.items.iter_mut() {
+            if let Some(index) = entries.iter().position(|entry| entry.user.id == user.id) {
                 // some items may not have any users / orders attached to them after removal
                 // for example, if one person ordered chocolate and then cancelled his order,
                 // we want chocolate to persist in the inline keyboard
                 // hence, we don't remove items with no users associated with them
+                entries.remove(index);
                 return Some(item.to_string());
             }
         }
         None
     }
 
-    /// Returns inline keyboard buttons which users can click to order an existing item
-    pub fn generate_inline_buttons(&self) -> Vec<InlineKeyboardButton> {
+    /// Returns the items a user has ordered and how many of each, sorted by name
+    pub fn user_quantities(&self, user: &User) -> Vec<(String, u32)> {
+        let mut quantities: Vec<(String, u32)> = self
+            .items
+            .iter()
+            .filter_map(|(item, entries)| {
+                let entry = entries.iter().find(|entry| entry.user.id == user.id)?;
+                Some((item.clone(), entry.quantity))
+            })
+            .collect();
+        quantities.sort();
+        quantities
+    }
+
+    /// Returns who ordered items for a user, if someone else did
+    pub fn added_by(&self, user: &User) -> Option<&User> {
+        self.items
+            .values()
+            .flatten()
+            .find(|entry| entry.user.id == user.id)?
+            .added_by
+            .as_ref()
+    }
+
+    /// Returns the notes a user added to the items they ordered, sorted by item
+    pub fn user_notes(&self, user: &User) -> Vec<(String, String)> {
+        let mut notes: Vec<(String, String)> = self
+            .items
+            .iter()
+            .filter_map(|(item, entries)| {
+                let entry = entries.iter().find(|entry| entry.user.id == user.id)?;
+                Some((item.clone(), entry.note.clone()?))
+            })
+            .collect();
+        notes.sort();
+        notes
+    }
+
+    /// Sets or clears the note a user added to an item they ordered
+    pub fn set_note(&mut self, user: &User, item: &str, note: Option<String>) {
+        for entry in self.items.get_mut(item).into_iter().flatten() {
+            if entry.user.id == user.id {
+                entry.note = note.clone();
+            }
+        }
+    }
+
+    /// Returns the options a user chose for the items they ordered, for items with options, sorted by item
+    pub fn user_options(&self, user: &User) -> Vec<(String, Vec<Selection>)> {
+        let mut options: Vec<(String, Vec<Selection>)> = self
+            .items
+            .iter()
+            .filter_map(|(item, entries)| {
+                let entry = entries.iter().find(|entry| entry.user.id == user.id)?;
+                Some((item.clone(), entry.options.clone()))
+                    .filter(|(_, options)| !options.is_empty())
+            })
+            .collect();
+        options.sort();
+        options
+    }
+
+    /// Sets the options a user chose for an item they ordered
+    pub fn set_options(&mut self, user: &User, item: &str, options: Vec<Selection>) {
+        for entry in self.items.get_mut(item).into_iter().flatten() {
+            if entry.user.id == user.id {
+                entry.options = options.clone();
+            }
+        }
+    }
+
+    /// Ensures that ordering an item with the given options doesn't replace other options the user chose for it
+    /// Each user may only order one choice of options for an item, and ordering it without options keeps those chosen
+    pub fn check_options(
+        &self,
+        user: &User,
+        item: &str,
+        options: &[Selection],
+    ) -> Result<(), String> {
+        let chosen = self
+            .items
+            .get(item)
+            .and_then(|entries| entries.iter().find(|entry| entry.user.id == user.id))
+            .map_or(&[][..], |entry| &entry.options);
+        if options.is_empty() || chosen.is_empty() || chosen == options {
+            return Ok(());
+        }
+        Err(format!(
+            "{} already ordered {} with {}, and only one choice of options may be ordered for each item. Cancel it first to order it with {} instead.",
+            user.first_name,
+            item,
+            menu::describe_selections(chosen),
+            menu::describe_selections(options)
+        ))
+    }
+
+    /// Returns the option groups of an item on the attached menu, which are chosen when it is ordered
+    pub fn option_groups(&self, item: &str) -> &[OptionGroup] {
+        self.attached_menu
+            .iter()
+            .find(|menu_item| menu_item.name == item)
+            .map_or(&[], |menu_item| &menu_item.options)
+    }
+
+    /// Replaces everything a user has ordered with the given items and quantities
+    /// `added_by` is whoever ordered them for the user, if someone else did
+    pub fn restore_user_quantities(
+        &mut self,
+        user: &User,
+        quantities: &[(String, u32)],
+        added_by: Option<&User>,
+    ) {
+        for entries in self.items.values_mut() {
+            entries.retain(|entry| entry.user.id != user.id);
+        }
+        for (item, quantity) in quantities {
+            self.items
+                .entry(item.clone())
+                .or_default()
+                .push(OrderEntry {
+                    user: user.clone(),
+                    quantity: *quantity,
+                    added_by: added_by.cloned(),
+                    note: None,
+                    options: vec![],
+                });
+        }
+    }
+
+    /// Removes a specific item from a user's order, returning whether the user had ordered it
+    pub fn remove_user_item(&mut self, user: &User, item: &str) -> bool {
+        match self.items.get_mut(item) {
+            Some(entries) => {
+                let previous_len = entries.len();
+                entries.retain(|entry| entry.user.id != user.id);
+                entries.len() != previous_len
+            }
+            None => false,
+        }
+    }
+
+    /// Returns rows of inline keyboard buttons which users can click to order an existing item or change its quantity
+    pub fn generate_inline_buttons(&self) -> Vec<Vec<InlineKeyboardButton>> {
         let mut items: Vec<&String> = self.items.keys().collect();
         items.sort();
         items
             .iter()
             .cloned()
-            .map(|item| InlineKeyboardButton::callback(item, format!("{} {}", self.name, item)))
+            .map(|item| {
+                vec![
+                    InlineKeyboardButton::callback(
+                        item,
+                        CallbackAction::ToggleItem(self.name.clone(), item.clone()).to_data(),
+                    ),
+                    InlineKeyboardButton::callback(
+                        "-",
+                        CallbackAction::DecrementItem(self.name.clone(), item.clone()).to_data(),
+                    ),
+                    InlineKeyboardButton::callback(
+                        "+",
+                        CallbackAction::IncrementItem(self.name.clone(), item.clone()).to_data(),
+                    ),
+                ]
+            })
             .collect()
     }
 
     /// Returns inline keyboard buttons which users can click to order an existing item
     pub fn generate_reply_markup(&self) -> InlineKeyboardMarkup {
         let mut keyboard_markup = InlineKeyboardMarkup::new();
-        for row in self.generate_inline_buttons().chunks(2) {
-            keyboard_markup.add_row(row.to_vec());
+        for row in self.generate_inline_buttons() {
+            keyboard_markup.add_row(row);
         }
         keyboard_markup
     }
@@ -87,34 +729,406 @@ impl Order {
 impl fmt::Display for Order {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // filter out items which have no users ordering them
-        let items_with_orders: HashMap<&String, &HashSet<User>> = self
+        let items_with_orders: HashMap<&String, &Vec<OrderEntry>> = self
             .items
             .iter()
-            .filter(|&(_, users)| !users.is_empty())
+            .filter(|&(_, entries)| !entries.is_empty())
             .collect();
 
+        // open and closed orders are told apart by whether they are active, so only other states are shown
+        let title = match self.state {
+            OrderState::Open | OrderState::Closed => self.name.clone(),
+            state => format!("{} ({})", self.name, state),
+        };
         if items_with_orders.is_empty() {
-            return write!(f, "Orders for {}:\n\nNone", self.name);
+            return write!(f, "Orders for {}:\n\nNone", title);
         }
 
         let mut sorted_orders: Vec<String> = items_with_orders
             .iter()
-            .map(|(item, users)| {
-                let mut sorted_users: Vec<String> =
-                    users.iter().map(|user| user.first_name.clone()).collect();
+            .map(|(item, entries)| {
+                let mut sorted_users: Vec<String> = entries
+                    .iter()
+                    .map(|entry| {
+                        let name = if entry.quantity > 1 {
+                            format!("{} x{}", entry.user.first_name, entry.quantity)
+                        } else {
+                            entry.user.first_name.clone()
+                        };
+                        match &entry.added_by {
+                            Some(added_by) => {
+                                format!("{} (added by {})", name, added_by.first_name)
+                            }
+                            None => name,
+                        }
+                    })
+                    .collect();
                 sorted_users.sort();
-                format!("{} {}: {}", users.len(), item, sorted_users.join(", "))
+                // notes are listed under the item, so that it keeps a single button however it is customised
+                let mut notes: Vec<String> = entries
+                    .iter()
+                    .filter_map(|entry| {
+                        let note = entry.note.as_ref()?;
+                        Some(format!("\n  {}: {}", entry.user.first_name, note))
+                    })
+                    .collect();
+                notes.sort();
+                // identical choices of options are counted together, as they are ordered together
+                let mut configurations: BTreeMap<String, (u32, Vec<&str>)> = BTreeMap::new();
+                for entry in entries.iter() {
+                    let description = menu::describe_selections(&entry.options);
+                    if !description.is_empty() {
+                        let (quantity, names) = configurations.entry(description).or_default();
+                        *quantity += entry.quantity;
+                        names.push(&entry.user.first_name);
+                    }
+                }
+                let configurations: Vec<String> = configurations
+                    .into_iter()
+                    .map(|(description, (quantity, mut names))| {
+                        names.sort();
+                        format!(
+                            "\n  {} with {}: {}",
+                            quantity,
+                            description,
+                            names.join(", ")
+                        )
+                    })
+                    .collect();
+                let quantity: u32 = entries.iter().map(|entry| entry.quantity).sum();
+                let line = match self.prices.get(*item) {
+                    Some(price) => format!(
+                        "{} {} @ {} = {}: {}",
+                        quantity,
+                        item,
+                        price,
+                        *price * quantity,
+                        sorted_users.join(", ")
+                    ),
+                    None => format!("{} {}: {}", quantity, item, sorted_users.join(", ")),
+                };
+                line + &configurations.concat() + &notes.concat()
             })
             .collect();
         sorted_orders.sort();
-        let total_orders: usize = items_with_orders.iter().map(|(_, users)| users.len()).sum();
+        let total_orders: u32 = items_with_orders
+            .values()
+            .flat_map(|entries| entries.iter())
+            .map(|entry| entry.quantity)
+            .sum();
 
         write!(
             f,
             "{} orders for {}:\n\n{}",
             total_orders,
-            self.name,
+            title,
             sorted_orders.join("\n")
-        )
+        )?;
+
+        if !self.has_amounts() {
+            return Ok(());
+        }
+        if !self.charges.is_empty() {
+            let subtotal: Money = self.subtotals().iter().map(|(_, amount)| *amount).sum();
+            write!(
+                f,
+                "\n\nSubtotal: {}\n{}\nCharges are split {}.",
+                subtotal,
+                self.charges.describe(subtotal).join("\n"),
+                self.charges.split
+            )?;
+        }
+        let amounts_owed = self.amounts_owed();
+        let lines: Vec<String> = amounts_owed
+            .iter()
+            .map(|(user, amount)| format!("{}: {}", user.first_name, amount))
+            .collect();
+        let total: Money = amounts_owed.iter().map(|(_, amount)| *amount).sum();
+        write!(
+            f,
+            "\n\nAmounts owed:\n{}\n\nTotal: {}",
+            lines.join("\n"),
+            total
+        )?;
+        if items_with_orders
+            .keys()
+            .any(|item| !self.prices.contains_key(*item))
+        {
+            write!(f, "\nItems without a price are not included.")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        charges::{Charge, SplitMethod},
+        test_utils::user,
+    };
+
+    #[test]
+    fn adjust_quantity() {
+        let alice = user(1, "Alice");
+        let mut order = Order::new("waffles".into(), alice.clone());
+        assert_eq!(order.adjust_quantity(alice.clone(), "chocolate", -1), None);
+        assert_eq!(
+            order.adjust_quantity(alice.clone(), "chocolate", 1),
+            Some(1)
+        );
+        assert_eq!(
+            order.adjust_quantity(alice.clone(), "chocolate", 1),
+            Some(2)
+        );
+        assert_eq!(
+            format!("{}", order),
+            "2 orders for waffles:\n\n2 chocolate: Alice x2"
+        );
+
+        assert_eq!(
+            order.adjust_quantity(alice.clone(), "chocolate", -2),
+            Some(0)
+        );
+        assert!(order.find_user_items(&alice).is_empty());
+        assert!(
+            order.items.contains_key("chocolate"),
+            "cancelled items remain in the inline keyboard"
+        );
+    }
+
+    #[test]
+    fn multiple_items() {
+        let alice = user(1, "Alice");
+        let mut order = Order::new("waffles".into(), alice.clone());
+        order.add_item(alice.clone(), "chocolate".into(), 1);
+        order.add_item(alice.clone(), "plain".into(), 1);
+        assert_eq!(
+            order.find_user_items(&alice),
+            vec!["plain".to_string()],
+            "only one item may be ordered by default"
+        );
+
+        order.allow_multiple_items = true;
+        order.add_item(alice.clone(), "chocolate".into(), 1);
+        order.add_item(alice.clone(), "chocolate".into(), 2);
+        assert_eq!(order.find_user_items(&alice), vec!["chocolate", "plain"]);
+        assert_eq!(order.items["chocolate"][0].quantity, 2);
+        assert_eq!(order.participants().len(), 1);
+
+        assert!(order.remove_user_item(&alice, "plain"));
+        assert!(!order.remove_user_item(&alice, "plain"));
+        assert_eq!(order.find_user_items(&alice), vec!["chocolate"]);
+    }
+
+    #[test]
+    fn amounts_owed() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let mut order = Order::new("waffles".into(), alice.clone());
+        order.allow_multiple_items = true;
+        order.add_item(alice.clone(), "chocolate".into(), 2);
+        order.add_item(bob.clone(), "chocolate".into(), 1);
+        order.add_item(bob.clone(), "coffee".into(), 1);
+        order.set_price("chocolate", Money::from_cents(450));
+        assert_eq!(
+            format!("{}", order),
+            "4 orders for waffles:\n\n1 coffee: Bob\n3 chocolate @ 4.50 = 13.50: Alice x2, Bob\n\nAmounts owed:\nAlice: 9.00\nBob: 4.50\n\nTotal: 13.50\nItems without a price are not included."
+        );
+
+        order.set_price("coffee", Money::from_cents(300));
+        let amounts: Vec<(&str, Money)> = order
+            .amounts_owed()
+            .into_iter()
+            .map(|(user, amount)| (user.first_name.as_str(), amount))
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                ("Alice", Money::from_cents(900)),
+                ("Bob", Money::from_cents(750))
+            ]
+        );
+    }
+
+    #[test]
+    fn charges_are_shared() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let mut order = Order::new("pizza".into(), alice.clone());
+        order.add_item(alice.clone(), "hawaiian".into(), 1);
+        order.add_item(bob.clone(), "margherita".into(), 1);
+        order.set_price("hawaiian", Money::from_cents(1500));
+        order.set_price("margherita", Money::from_cents(500));
+        order
+            .charges
+            .apply(Charge::DeliveryFee(Money::from_cents(400)));
+        order.charges.apply(Charge::Tax("10%".parse().unwrap()));
+        assert_eq!(
+            format!("{}", order),
+            "2 orders for pizza:\n\n1 hawaiian @ 15.00 = 15.00: Alice\n1 margherita @ 5.00 = 5.00: Bob\n\nSubtotal: 20.00\nDelivery fee: 4.00\nService charge/tax (10%): 2.00\nCharges are split proportionally.\n\nAmounts owed:\nAlice: 19.50\nBob: 6.50\n\nTotal: 26.00"
+        );
+
+        order.charges.apply(Charge::Split(SplitMethod::Evenly));
+        let amounts: Vec<Money> = order
+            .amounts_owed()
+            .into_iter()
+            .map(|(_, amount)| amount)
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![Money::from_cents(1800), Money::from_cents(800)]
+        );
+    }
+
+    #[test]
+    fn menu_only() {
+        let alice = user(1, "Alice");
+        let mut order = Order::new("waffles".into(), alice);
+        order.offer_menu(&[MenuItem::new("chocolate".into(), None)]);
+        assert!(order.check_on_menu("strawberry").is_ok());
+
+        order.menu_only = true;
+        assert!(order.check_on_menu("chocolate").is_ok());
+        assert_eq!(
+            order.check_on_menu("chocolat"),
+            Err("chocolat is not on the menu for waffles. Did you mean chocolate?".into())
+        );
+        assert_eq!(
+            order.check_on_menu("pizza"),
+            Err("pizza is not on the menu for waffles. Tap on an item to order it, or use /view to see the menu.".into())
+        );
+    }
+
+    #[test]
+    fn co_owners() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let carol = user(3, "Carol");
+        let mut order = Order::new("waffles".into(), alice.clone());
+        assert!(order.is_managed_by(&alice));
+        assert!(!order.is_managed_by(&bob));
+        assert!(order.add_co_owner(bob.clone()));
+        assert!(!order.add_co_owner(bob.clone()));
+        assert!(!order.add_co_owner(alice.clone()));
+        assert!(order.is_managed_by(&bob));
+        assert_eq!(order.managers(), "Alice or Bob");
+
+        order.hand_over(&alice, carol.clone());
+        assert_eq!(order.owner, carol);
+        assert!(!order.is_managed_by(&alice));
+        assert_eq!(order.managers(), "Carol or Bob");
+        order.hand_over(&bob, alice.clone());
+        assert_eq!(order.managers(), "Carol or Alice");
+        assert!(order.remove_co_owner(&alice));
+        assert!(!order.remove_co_owner(&carol), "owners aren't co-owners");
+        assert_eq!(order.managers(), "Carol");
+    }
+
+    #[test]
+    fn notes() {
+        let alice = user(1, "Alice");
+        let bob = user(2, "Bob");
+        let mut order = Order::new("waffles".into(), alice.clone());
+        order.add_item(bob.clone(), "chocolate".into(), 1);
+        order.set_note(&bob, "chocolate", Some("no cream".into()));
+        order.add_item(alice.clone(), "chocolate".into(), 2);
+        order.set_note(&alice, "chocolate", Some("extra hot".into()));
+        order.add_item(alice.clone(), "plain".into(), 1);
+        order.set_note(&alice, "plain", Some("crispy".into()));
+        assert_eq!(
+            order.to_string(),
+            "2 orders for waffles:\n\n1 chocolate: Bob\n  Bob: no cream\n1 plain: Alice\n  Alice: crispy"
+        );
+
+        order.allow_multiple_items = true;
+        order.add_item(alice.clone(), "chocolate".into(), 2);
+        order.set_note(&alice, "chocolate", Some("extra hot".into()));
+        assert_eq!(
+            order.to_string(),
+            "4 orders for waffles:\n\n1 plain: Alice\n  Alice: crispy\n3 chocolate: Alice x2, Bob\n  Alice: extra hot\n  Bob: no cream"
+        );
+        assert_eq!(
+            order.user_notes(&alice),
+            vec![
+                ("chocolate".to_string(), "extra hot".to_string()),
+                ("plain".to_string(), "crispy".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn states() {
+        let alice = user(1, "Alice");
+        let mut order = Order::new("waffles".into(), alice.clone());
+        order.add_item(alice.clone(), "chocolate".into(), 1);
+        assert_eq!(order.check_open(), Ok(()));
+        assert_eq!(order.set_state(OrderState::Locked), Ok(()));
+        assert_eq!(
+            order.check_open(),
+            Err("waffles is locked, so orders can't be changed. Ask Alice to /unlock it.".into())
+        );
+        assert_eq!(
+            order.to_string(),
+            "1 orders for waffles (locked):\n\n1 chocolate: Alice"
+        );
+        assert_eq!(
+            order.set_state(OrderState::Locked),
+            Err("waffles is already locked.".into())
+        );
+        assert_eq!(order.set_state(OrderState::Open), Ok(()));
+        assert_eq!(
+            order.to_string(),
+            "1 orders for waffles:\n\n1 chocolate: Alice"
+        );
+        assert_eq!(order.set_state(OrderState::Placed), Ok(()));
+        assert_eq!(
+            order.set_state(OrderState::Open),
+            Err("waffles is placed, so it can't be unlocked.".into())
+        );
+        assert_eq!(order.set_state(OrderState::Delivered), Ok(()));
+        assert_eq!(
+            order.check_open(),
+            Err("waffles is delivered, so orders can't be changed.".into())
+        );
+        assert_eq!(
+            order.set_state(OrderState::Placed),
+            Err("waffles is delivered, so it can't be marked as placed.".into())
+        );
+    }
+
+    #[test]
+    fn reminders() {
+        let alice = user(1, "Alice");
+        let mut order = Order::new("waffles".into(), alice);
+        let now = Utc::now();
+        assert_eq!(order.take_due_reminder(now), None);
+
+        order.set_deadline(Some(now + Duration::minutes(20)));
+        assert_eq!(order.take_due_reminder(now), None);
+        let fifteen_minutes_before = now + Duration::minutes(5);
+        assert_eq!(order.take_due_reminder(fifteen_minutes_before), Some(15));
+        assert_eq!(order.take_due_reminder(fifteen_minutes_before), None);
+
+        order.set_deadline(Some(now + Duration::minutes(3)));
+        assert_eq!(
+            order.take_due_reminder(now),
+            Some(3),
+            "only one reminder is posted when several are due"
+        );
+        assert_eq!(order.take_due_reminder(now + Duration::minutes(1)), None);
+        assert_eq!(order.take_due_reminder(now + Duration::minutes(3)), None);
+    }
+
+    #[test]
+    fn orders_without_quantities_can_be_loaded() {
+        let json = r#"{
+            "name": "waffles",
+            "items": {"chocolate": [{"id": 1, "first_name": "Alice", "is_bot": false}]},
+            "owner": {"id": 1, "first_name": "Alice", "is_bot": false}
+        }"#;
+        let order: Order = serde_json::from_str(json).unwrap();
+        assert_eq!(order.items["chocolate"][0].user.first_name, "Alice");
+        assert_eq!(order.items["chocolate"][0].quantity, 1);
     }
 }
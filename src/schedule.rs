@@ -0,0 +1,225 @@
+use chrono::{DateTime, Duration, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use telegram_bot::types::chat::User;
+
+use crate::{archive::format_time, command::StartOptions, deadline, storage::UserDef};
+
+/// How late a scheduled order may be started, such as after the bot was offline
+/// Orders missed by more than this are skipped, rather than started long after they were due
+const MAX_START_DELAY_MINUTES: i64 = 60;
+
+static WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+
+/// When a scheduled order is started, such as every Friday at 10:00
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Recurrence {
+    /// the days of the week on which the order is started, from Monday to Sunday, with every day allowed if empty
+    pub days: Vec<Weekday>,
+    /// the time of day at which the order is started, in the bot's local timezone
+    pub time: NaiveTime,
+}
+
+impl Recurrence {
+    /// Parses days of the week, such as fri, mon,wed,fri, mon-fri, weekdays or daily, followed by a time such as 10:00
+    pub fn parse(days: &str, time: &str) -> Result<Self, String> {
+        Ok(Self {
+            days: parse_days(days)?,
+            time: deadline::parse_time(time)?,
+        })
+    }
+
+    /// Returns the next time the order is due after `now`
+    pub fn next_after(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        deadline::next_occurrence_on(self.time, &self.days, now)
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.time.format("%H:%M");
+        if self.days.is_empty() {
+            return write!(f, "every day at {}", time);
+        }
+        if self.days == WEEKDAYS {
+            return write!(f, "every weekday at {}", time);
+        }
+        let days: Vec<String> = self.days.iter().map(|day| day.to_string()).collect();
+        match days.split_last() {
+            Some((last, [])) => write!(f, "every {} at {}", last, time),
+            Some((last, rest)) => write!(f, "every {} and {} at {}", rest.join(", "), last, time),
+            None => unreachable!("days is not empty"),
+        }
+    }
+}
+
+/// Parses days of the week such as fri, fridays, mon,wed,fri, mon-fri, weekdays or daily
+/// Returns the days from Monday to Sunday, or no days if the order is started every day
+fn parse_days(s: &str) -> Result<Vec<Weekday>, String> {
+    let error = |day: &str| {
+        format!(
+            "{} is not a day of the week. For example, use fri, mon,wed,fri, mon-fri, weekdays or daily",
+            day
+        )
+    };
+    let parse_day = |day: &str| -> Result<Weekday, String> {
+        day.parse()
+            .or_else(|_| day.trim_end_matches('s').parse())
+            .map_err(|_| error(day))
+    };
+    match s {
+        "daily" | "everyday" => return Ok(vec![]),
+        "weekdays" => return Ok(WEEKDAYS.to_vec()),
+        _ => (),
+    }
+    let mut days = vec![];
+    for part in s.split(',').filter(|part| !part.is_empty()) {
+        match part.find('-') {
+            Some(sep) => {
+                let first = parse_day(&part[..sep])?;
+                let last = parse_day(&part[sep + 1..])?;
+                let mut day = first;
+                days.push(day);
+                while day != last {
+                    day = day.succ();
+                    days.push(day);
+                }
+            }
+            None => days.push(parse_day(part)?),
+        }
+    }
+    if days.is_empty() {
+        return Err(error(s));
+    }
+    days.sort_by_key(|day| day.num_days_from_monday());
+    days.dedup();
+    if days.len() == 7 {
+        days.clear();
+    }
+    Ok(days)
+}
+
+/// An order which is started automatically, such as every Friday at 10:00
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScheduledOrder {
+    /// Identifies this schedule among the conversation's schedules, increasing with each order scheduled
+    pub id: u64,
+    pub order_name: String,
+    pub recurrence: Recurrence,
+    /// options the order is started with, such as its saved menu and deadline
+    pub options: StartOptions,
+    /// who scheduled the order, who becomes the creater of each order started
+    #[serde(serialize_with = "UserDef::serialize")]
+    pub owner: User,
+    /// when the order is next started
+    pub next_start: DateTime<Utc>,
+}
+
+impl ScheduledOrder {
+    /// Returns whether the order should be started at `now`, moving its next start past `now` if it is due
+    /// Starts missed by more than MAX_START_DELAY_MINUTES are skipped
+    pub fn take_due_start(&mut self, now: DateTime<Utc>) -> bool {
+        if self.next_start > now {
+            return false;
+        }
+        let is_on_time = now - self.next_start <= Duration::minutes(MAX_START_DELAY_MINUTES);
+        self.next_start = self.recurrence.next_after(now);
+        is_on_time
+    }
+}
+
+impl fmt::Display for ScheduledOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.order_name, self.recurrence)?;
+        if let Some(menu_name) = &self.options.saved_menu {
+            write!(f, " with menu {}", menu_name)?;
+        }
+        if let Some(deadline) = self.options.deadline {
+            write!(f, ", ending at {}", deadline.format("%H:%M"))?;
+        }
+        write!(f, ". Next on {}", format_time(&self.next_start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::user;
+
+    #[test]
+    fn parse() {
+        let recurrence = |days: &[Weekday], h| {
+            Ok(Recurrence {
+                days: days.to_vec(),
+                time: NaiveTime::from_hms_opt(h, 0, 0).unwrap(),
+            })
+        };
+        assert_eq!(
+            Recurrence::parse("fri", "10:00"),
+            recurrence(&[Weekday::Fri], 10)
+        );
+        assert_eq!(
+            Recurrence::parse("fridays", "10am"),
+            recurrence(&[Weekday::Fri], 10)
+        );
+        assert_eq!(
+            Recurrence::parse("fri,mon,wed", "12:00"),
+            recurrence(&[Weekday::Mon, Weekday::Wed, Weekday::Fri], 12)
+        );
+        assert_eq!(
+            Recurrence::parse("mon-fri", "9am"),
+            recurrence(&WEEKDAYS, 9)
+        );
+        assert_eq!(
+            Recurrence::parse("sat-mon", "9am"),
+            recurrence(&[Weekday::Mon, Weekday::Sat, Weekday::Sun], 9)
+        );
+        assert_eq!(Recurrence::parse("daily", "9am"), recurrence(&[], 9));
+        assert_eq!(
+            Recurrence::parse("someday", "9am"),
+            Err("someday is not a day of the week. For example, use fri, mon,wed,fri, mon-fri, weekdays or daily".into())
+        );
+        assert!(Recurrence::parse("fri", "10").is_err());
+    }
+
+    #[test]
+    fn display() {
+        let recurrence = |days: &str| Recurrence::parse(days, "10:00").unwrap().to_string();
+        assert_eq!(recurrence("fri"), "every Fri at 10:00");
+        assert_eq!(recurrence("mon,wed,fri"), "every Mon, Wed and Fri at 10:00");
+        assert_eq!(recurrence("weekdays"), "every weekday at 10:00");
+        assert_eq!(recurrence("mon-sun"), "every day at 10:00");
+    }
+
+    #[test]
+    fn due_starts() {
+        let now = deadline::local_time(9, 0);
+        let recurrence = Recurrence::parse("daily", "10:00").unwrap();
+        let mut scheduled = ScheduledOrder {
+            id: 1,
+            order_name: "waffles".into(),
+            next_start: recurrence.next_after(now),
+            recurrence,
+            options: StartOptions::default(),
+            owner: user(1, "Alice"),
+        };
+        let start = scheduled.next_start;
+        assert!(!scheduled.take_due_start(start - Duration::seconds(1)));
+        assert!(scheduled.take_due_start(start));
+        assert!(scheduled.next_start > start);
+        assert!(!scheduled.take_due_start(start));
+
+        let next_start = scheduled.next_start;
+        assert!(
+            !scheduled.take_due_start(next_start + Duration::hours(2)),
+            "starts missed long ago are skipped"
+        );
+        assert!(scheduled.next_start > next_start + Duration::hours(2));
+    }
+}
@@ -0,0 +1,107 @@
+use rusqlite::{params, Connection, NO_PARAMS};
+use std::path::Path;
+use telegram_bot::types::ChatId;
+
+use crate::{
+    conversation_orders::ConversationOrders,
+    storage::{MemoryStorage, Storage},
+};
+
+/// Keeps orders in memory, writing a conversation's orders to an SQLite database whenever they change
+/// Each conversation is stored as a row containing its orders as JSON
+pub struct SqliteStorage {
+    connection: Connection,
+    memory: MemoryStorage,
+}
+
+impl SqliteStorage {
+    /// Opens or creates the given database, loading any orders saved there
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection = Connection::open(path).map_err(|e| e.to_string())?;
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS conversation_orders (
+                    chat_id INTEGER PRIMARY KEY,
+                    orders TEXT NOT NULL
+                )",
+                NO_PARAMS,
+            )
+            .map_err(|e| e.to_string())?;
+
+        let mut memory = MemoryStorage::default();
+        {
+            let mut statement = connection
+                .prepare("SELECT chat_id, orders FROM conversation_orders")
+                .map_err(|e| e.to_string())?;
+            let rows = statement
+                .query_map(NO_PARAMS, |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })
+                .map_err(|e| e.to_string())?;
+            for row in rows {
+                let (chat_id, orders) = row.map_err(|e| e.to_string())?;
+                let conversation_orders: ConversationOrders =
+                    serde_json::from_str(&orders).map_err(|e| e.to_string())?;
+                memory.insert(ChatId::new(chat_id), conversation_orders);
+            }
+        }
+        Ok(Self { connection, memory })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, chat: ChatId) -> Option<&ConversationOrders> {
+        self.memory.get(chat)
+    }
+    fn get_mut(&mut self, chat: ChatId) -> Option<&mut ConversationOrders> {
+        self.memory.get_mut(chat)
+    }
+    fn insert(&mut self, chat: ChatId, conversation_orders: ConversationOrders) {
+        self.memory.insert(chat, conversation_orders)
+    }
+    fn chats(&self) -> Vec<ChatId> {
+        self.memory.chats()
+    }
+    fn save(&mut self, chat: ChatId) -> Result<(), String> {
+        let chat_id: i64 = chat.into();
+        let result = match self.memory.get(chat) {
+            Some(conversation_orders) => {
+                let orders =
+                    serde_json::to_string(conversation_orders).map_err(|e| e.to_string())?;
+                self.connection.execute(
+                    "INSERT OR REPLACE INTO conversation_orders (chat_id, orders) VALUES (?1, ?2)",
+                    params![chat_id, orders],
+                )
+            }
+            None => self.connection.execute(
+                "DELETE FROM conversation_orders WHERE chat_id = ?1",
+                params![chat_id],
+            ),
+        };
+        result
+            .map(|_| ())
+            .map_err(|e| format!("Failed to save orders for chat {}: {}", chat, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{order::Order, test_utils::user};
+
+    #[test]
+    fn saved_orders_can_be_loaded() {
+        let path = std::env::temp_dir().join("food-ordering-bot-sqlite-test.db");
+        let chat = ChatId::new(-42);
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let mut conversation_orders = ConversationOrders::default();
+        conversation_orders.insert_order(Order::new("waffles".into(), user(1, "Alice")));
+        storage.insert(chat, conversation_orders);
+        storage.save(chat).unwrap();
+
+        let loaded = SqliteStorage::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.chats(), vec![chat]);
+        assert!(loaded.get(chat).unwrap().orders.contains_key("waffles"));
+    }
+}
@@ -0,0 +1,198 @@
+use serde::{Serialize, Serializer};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use telegram_bot::types::{chat::User, ChatId, UserId};
+
+use crate::conversation_orders::ConversationOrders;
+
+/// Mirror of telegram_bot's `User`, which only implements `Deserialize`
+/// The field names match those expected by `User`'s `Deserialize` implementation, so no mirror is needed when loading
+#[derive(Serialize)]
+#[serde(remote = "User")]
+pub struct UserDef {
+    pub id: UserId,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub is_bot: bool,
+    pub language_code: Option<String>,
+}
+
+/// A user which is serialized with `UserDef`, for serializing users held in other types
+struct UserRef<'a>(&'a User);
+
+impl Serialize for UserRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UserDef::serialize(self.0, serializer)
+    }
+}
+
+/// Serializes several users, such as the co-owners of an order, with `UserDef`
+pub fn serialize_users<S: Serializer>(users: &[User], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(users.iter().map(UserRef))
+}
+
+/// Serializes a user who may be absent, such as whoever ordered an item for someone else, with `UserDef`
+pub fn serialize_optional_user<S: Serializer>(
+    user: &Option<User>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match user {
+        Some(user) => serializer.serialize_some(&UserRef(user)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Where each conversation's orders are kept
+/// Changes made through `get_mut` and `insert` are only guaranteed to be durable once `save` is called
+pub trait Storage {
+    /// Returns the orders of a conversation, if any
+    fn get(&self, chat: ChatId) -> Option<&ConversationOrders>;
+    /// Returns the orders of a conversation for modification, if any
+    fn get_mut(&mut self, chat: ChatId) -> Option<&mut ConversationOrders>;
+    /// Adds or replaces the orders of a conversation
+    fn insert(&mut self, chat: ChatId, conversation_orders: ConversationOrders);
+    /// Returns the conversations which have orders
+    fn chats(&self) -> Vec<ChatId>;
+    /// Persists the current state of a conversation's orders
+    fn save(&mut self, chat: ChatId) -> Result<(), String>;
+}
+
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn get(&self, chat: ChatId) -> Option<&ConversationOrders> {
+        (**self).get(chat)
+    }
+    fn get_mut(&mut self, chat: ChatId) -> Option<&mut ConversationOrders> {
+        (**self).get_mut(chat)
+    }
+    fn insert(&mut self, chat: ChatId, conversation_orders: ConversationOrders) {
+        (**self).insert(chat, conversation_orders)
+    }
+    fn chats(&self) -> Vec<ChatId> {
+        (**self).chats()
+    }
+    fn save(&mut self, chat: ChatId) -> Result<(), String> {
+        (**self).save(chat)
+    }
+}
+
+/// Keeps orders in memory only, so they are lost when the bot exits
+#[derive(Default)]
+pub struct MemoryStorage {
+    conversations: HashMap<ChatId, ConversationOrders>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, chat: ChatId) -> Option<&ConversationOrders> {
+        self.conversations.get(&chat)
+    }
+    fn get_mut(&mut self, chat: ChatId) -> Option<&mut ConversationOrders> {
+        self.conversations.get_mut(&chat)
+    }
+    fn insert(&mut self, chat: ChatId, conversation_orders: ConversationOrders) {
+        self.conversations.insert(chat, conversation_orders);
+    }
+    fn chats(&self) -> Vec<ChatId> {
+        self.conversations.keys().cloned().collect()
+    }
+    fn save(&mut self, _chat: ChatId) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Keeps orders in memory, writing all of them to a JSON file whenever they change
+pub struct JsonFileStorage {
+    path: PathBuf,
+    memory: MemoryStorage,
+}
+
+impl JsonFileStorage {
+    /// Opens the given file, loading any orders saved there
+    /// A missing file is treated as there being no orders
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let conversations = match fs::read_to_string(path) {
+            Ok(contents) => {
+                // JSON object keys are strings, so orders are stored as a list of pairs instead
+                let chats: Vec<(ChatId, ConversationOrders)> = serde_json::from_str(&contents)?;
+                chats.into_iter().collect()
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            memory: MemoryStorage { conversations },
+        })
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let chats: Vec<(&ChatId, &ConversationOrders)> = self.memory.conversations.iter().collect();
+        let contents = serde_json::to_string(&chats)?;
+        // write to a temporary file first, so that a crash while writing doesn't leave a truncated file behind
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+impl Storage for JsonFileStorage {
+    fn get(&self, chat: ChatId) -> Option<&ConversationOrders> {
+        self.memory.get(chat)
+    }
+    fn get_mut(&mut self, chat: ChatId) -> Option<&mut ConversationOrders> {
+        self.memory.get_mut(chat)
+    }
+    fn insert(&mut self, chat: ChatId, conversation_orders: ConversationOrders) {
+        self.memory.insert(chat, conversation_orders)
+    }
+    fn chats(&self) -> Vec<ChatId> {
+        self.memory.chats()
+    }
+    fn save(&mut self, _chat: ChatId) -> Result<(), String> {
+        // the whole file is rewritten, so it doesn't matter which conversation changed
+        self.write()
+            .map_err(|e| format!("Failed to save orders to {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::ItemRequest, money::Money, order::Order, test_utils::user};
+
+    #[test]
+    fn saved_orders_can_be_loaded() {
+        let path = std::env::temp_dir().join("food-ordering-bot-storage-test.json");
+        let alice = user(1, "Alice");
+        let mut conversation_orders = ConversationOrders::default();
+        conversation_orders.insert_order(Order::new("waffles".into(), alice.clone()));
+        let request = ItemRequest {
+            quantity: 2,
+            price: Some(Money::from_cents(450)),
+            ..ItemRequest::new("chocolate")
+        };
+        conversation_orders.add_item("waffles", alice.clone(), request, None);
+        let chat = ChatId::new(-42);
+        let mut storage = JsonFileStorage::open(&path).unwrap();
+        storage.insert(chat, conversation_orders);
+        storage.save(chat).unwrap();
+
+        let loaded = JsonFileStorage::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let order = &loaded.get(chat).unwrap().orders["waffles"];
+        assert_eq!(order.owner, alice);
+        assert_eq!(order.items["chocolate"][0].user, alice);
+        assert_eq!(order.items["chocolate"][0].quantity, 2);
+        assert_eq!(order.prices["chocolate"], Money::from_cents(450));
+    }
+
+    #[test]
+    fn missing_file_has_no_orders() {
+        let path = std::env::temp_dir().join("food-ordering-bot-missing.json");
+        assert!(JsonFileStorage::open(&path).unwrap().chats().is_empty());
+    }
+}
@@ -0,0 +1,13 @@
+use telegram_bot::types::{chat::User, UserId};
+
+/// Creates a user with the given id and first name
+pub fn user(id: i64, first_name: &str) -> User {
+    User {
+        id: UserId::new(id),
+        first_name: first_name.to_string(),
+        last_name: None,
+        username: None,
+        is_bot: false,
+        language_code: None,
+    }
+}
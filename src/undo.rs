@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use telegram_bot::types::{chat::User, UserId};
+
+use crate::{
+    charges::Charges, menu::Selection, money::Money, order::Order, storage::serialize_optional_user,
+};
+
+/// How to revert a change a user made, recorded when the change is made
+// at most MAX_UNDO_ENTRIES are kept per chat, so their size doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Inverse {
+    /// restores what the user, or whoever they ordered for, had ordered, as items and their quantities
+    RestoreItems {
+        order_name: String,
+        quantities: Vec<(String, u32)>,
+        /// who the user ordered for, if it wasn't themselves
+        #[serde(default, serialize_with = "serialize_optional_user")]
+        recipient: Option<User>,
+        /// who had ordered the items for their owner, if someone else had
+        #[serde(default, serialize_with = "serialize_optional_user")]
+        added_by: Option<User>,
+        /// the notes added to the items, as items and their notes
+        #[serde(default)]
+        notes: Vec<(String, String)>,
+        /// the options chosen for the items, as items and their options
+        #[serde(default)]
+        options: Vec<(String, Vec<Selection>)>,
+    },
+    /// restores the price of an item, removing it if the item had no price
+    RestorePrice {
+        order_name: String,
+        item: String,
+        price: Option<Money>,
+    },
+    /// restores the delivery fee, service charge or tax, tip and how they were split
+    RestoreCharges {
+        order_name: String,
+        charges: Charges,
+    },
+    /// discards an order which was just started
+    RemoveOrder { order_name: String },
+    /// reopens an order which was just ended
+    ReopenOrder { id: u64 },
+}
+
+impl Inverse {
+    /// Returns the name of the active order the change is reverted in, unless the change ended an order
+    pub fn order_name(&self) -> Option<&str> {
+        match self {
+            Inverse::RestoreItems { order_name, .. }
+            | Inverse::RestorePrice { order_name, .. }
+            | Inverse::RestoreCharges { order_name, .. }
+            | Inverse::RemoveOrder { order_name } => Some(order_name),
+            Inverse::ReopenOrder { .. } => None,
+        }
+    }
+
+    /// Records everything `owner` has ordered from an order, so that it can be restored after a change
+    /// `recipient` is who the change is made for, if it isn't whoever is making it
+    pub fn restore_items(order: &Order, owner: &User, recipient: Option<User>) -> Self {
+        Inverse::RestoreItems {
+            order_name: order.name.clone(),
+            quantities: order.user_quantities(owner),
+            recipient,
+            added_by: order.added_by(owner).cloned(),
+            notes: order.user_notes(owner),
+            options: order.user_options(owner),
+        }
+    }
+}
+
+/// A change a user made which can be undone
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UndoEntry {
+    /// who made the change, who is the only one who may undo it
+    pub user: UserId,
+    /// describes the change, such as "ordering chocolate for waffles"
+    pub description: String,
+    pub inverse: Inverse,
+    /// when the changed order was started, which tells it apart from later orders with the same name
+    #[serde(default)]
+    pub order_started_at: Option<DateTime<Utc>>,
+}
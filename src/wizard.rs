@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+use telegram_bot::{
+    types::{chat::User, InlineKeyboardMarkup},
+    InlineKeyboardButton,
+};
+
+use crate::{
+    callback::CallbackAction,
+    menu::{self, OptionGroup, Selection},
+    storage::UserDef,
+};
+
+/// How many choices are shown on each row of buttons
+const CHOICES_PER_ROW: usize = 3;
+
+/// Walks a user through choosing the options of an item they tapped on, one option group at a time
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OptionWizard {
+    /// Identifies this wizard among the conversation's wizards, increasing with each wizard started
+    pub id: u64,
+    /// who is choosing, who is the only one who may tap on the wizard's buttons
+    #[serde(serialize_with = "UserDef::serialize")]
+    pub user: User,
+    pub order_name: String,
+    pub item: String,
+    /// the item's option groups, as they were when the wizard was started
+    pub groups: Vec<OptionGroup>,
+    /// the choices made for each group so far, in the order the groups are listed
+    pub selections: Vec<Selection>,
+    /// the indices of the choices made so far for the current group, if it allows multiple choices
+    pub pending: Vec<usize>,
+}
+
+impl OptionWizard {
+    /// Starts choosing the options of an item, which must have at least one option group
+    pub fn new(
+        id: u64,
+        user: User,
+        order_name: String,
+        item: String,
+        groups: Vec<OptionGroup>,
+    ) -> Self {
+        Self {
+            id,
+            user,
+            order_name,
+            item,
+            groups,
+            selections: vec![],
+            pending: vec![],
+        }
+    }
+
+    /// Returns the group whose choices are being made, or None once every group has been chosen
+    pub fn current_group(&self) -> Option<&OptionGroup> {
+        self.groups.get(self.selections.len())
+    }
+
+    /// Returns whether a choice has been made for every group
+    pub fn is_complete(&self) -> bool {
+        self.current_group().is_none()
+    }
+
+    /// Makes a choice for the current group, moving on to the next group unless it allows multiple choices
+    /// For groups allowing multiple choices, tapping a choice again undoes it
+    /// Returns a short description of what was done, to answer the tap with
+    pub fn choose(&mut self, index: usize) -> Result<String, String> {
+        let group = self
+            .current_group()
+            .ok_or_else(|| "These options have already been chosen.".to_string())?;
+        let choice = group
+            .choices
+            .get(index)
+            .cloned()
+            .ok_or_else(|| "This choice is no longer available.".to_string())?;
+        if !group.multiple {
+            self.selections.push(Selection {
+                group: group.name.clone(),
+                choices: vec![choice.clone()],
+            });
+            return Ok(format!("Chose {}.", choice));
+        }
+        match self.pending.iter().position(|&pending| pending == index) {
+            Some(position) => {
+                self.pending.remove(position);
+                Ok(format!("Removed {}.", choice))
+            }
+            None => {
+                self.pending.push(index);
+                Ok(format!("Added {}.", choice))
+            }
+        }
+    }
+
+    /// Finishes choosing for the current group, which allows multiple choices, and moves on to the next group
+    pub fn finish_group(&mut self) -> Result<(), String> {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.sort();
+        let group = self
+            .current_group()
+            .ok_or_else(|| "These options have already been chosen.".to_string())?;
+        let selection = Selection {
+            group: group.name.clone(),
+            choices: pending
+                .iter()
+                .filter_map(|&index| group.choices.get(index).cloned())
+                .collect(),
+        };
+        self.selections.push(selection);
+        Ok(())
+    }
+
+    /// Asks the user to choose for the current group, listing what they have chosen so far
+    pub fn prompt(&self) -> String {
+        let mut prompt = match self.current_group() {
+            Some(group) if group.multiple => format!(
+                "{}, choose any {} for your {} from {}, then tap Done.",
+                self.user.first_name, group.name, self.item, self.order_name
+            ),
+            Some(group) => format!(
+                "{}, choose the {} of your {} from {}.",
+                self.user.first_name, group.name, self.item, self.order_name
+            ),
+            None => format!(
+                "{} has chosen the options of their {}.",
+                self.user.first_name, self.item
+            ),
+        };
+        let chosen = menu::describe_selections(&self.selections);
+        if !chosen.is_empty() {
+            prompt.push_str(&format!("\nSo far: {}", chosen));
+        }
+        prompt
+    }
+
+    /// Returns the buttons for the choices of the current group, followed by buttons to finish or cancel
+    pub fn generate_reply_markup(&self) -> InlineKeyboardMarkup {
+        let mut markup = InlineKeyboardMarkup::new();
+        let group = match self.current_group() {
+            Some(group) => group,
+            None => return markup,
+        };
+        let buttons: Vec<InlineKeyboardButton> = group
+            .choices
+            .iter()
+            .enumerate()
+            .map(|(index, choice)| {
+                let label = if self.pending.contains(&index) {
+                    format!("\u{2713} {}", choice)
+                } else {
+                    choice.clone()
+                };
+                InlineKeyboardButton::callback(
+                    label,
+                    CallbackAction::ChooseOption(self.id, index).to_data(),
+                )
+            })
+            .collect();
+        for row in buttons.chunks(CHOICES_PER_ROW) {
+            markup.add_row(row.to_vec());
+        }
+        let mut last_row = vec![];
+        if group.multiple {
+            last_row.push(InlineKeyboardButton::callback(
+                "Done",
+                CallbackAction::FinishOptionGroup(self.id).to_data(),
+            ));
+        }
+        last_row.push(InlineKeyboardButton::callback(
+            "Cancel",
+            CallbackAction::CancelOptions(self.id).to_data(),
+        ));
+        markup.add_row(last_row);
+        markup
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::user;
+
+    fn group(name: &str, choices: &[&str], multiple: bool) -> OptionGroup {
+        OptionGroup {
+            name: name.into(),
+            choices: choices.iter().map(|choice| choice.to_string()).collect(),
+            multiple,
+        }
+    }
+
+    #[test]
+    fn choices() {
+        let mut wizard = OptionWizard::new(
+            1,
+            user(1, "Alice"),
+            "bubble-tea".into(),
+            "thai tea".into(),
+            vec![
+                group("size", &["s", "m", "l"], false),
+                group("toppings", &["pearls", "jelly", "pudding"], true),
+            ],
+        );
+        assert_eq!(
+            wizard.prompt(),
+            "Alice, choose the size of your thai tea from bubble-tea."
+        );
+        assert_eq!(
+            wizard.choose(3),
+            Err("This choice is no longer available.".into())
+        );
+        assert_eq!(wizard.choose(1), Ok("Chose m.".into()));
+
+        assert_eq!(
+            wizard.prompt(),
+            "Alice, choose any toppings for your thai tea from bubble-tea, then tap Done.\nSo far: size m"
+        );
+        assert_eq!(wizard.choose(2), Ok("Added pudding.".into()));
+        assert_eq!(wizard.choose(0), Ok("Added pearls.".into()));
+        assert_eq!(wizard.choose(1), Ok("Added jelly.".into()));
+        assert_eq!(wizard.choose(1), Ok("Removed jelly.".into()));
+        assert!(!wizard.is_complete());
+        assert_eq!(wizard.finish_group(), Ok(()));
+        assert!(wizard.is_complete());
+        assert_eq!(
+            wizard.selections,
+            vec![
+                Selection {
+                    group: "size".into(),
+                    choices: vec!["m".into()]
+                },
+                Selection {
+                    group: "toppings".into(),
+                    choices: vec!["pearls".into(), "pudding".into()]
+                },
+            ],
+            "choices are kept in the order the group lists them"
+        );
+        assert_eq!(
+            wizard.choose(0),
+            Err("These options have already been chosen.".into())
+        );
+    }
+}